@@ -38,6 +38,8 @@ async function fetchApi<T>(endpoint: string, options: RequestInit = {}): Promise
     ...options,
     credentials: "include", // Include cookies for CORS
     headers: {
+      // Responses use camelCase keys, matching the shared types, and bodies may too
+      Accept: "application/json; profile=camelCase",
       "Content-Type": "application/json",
       ...authHeaders,
       ...options.headers,
@@ -53,10 +55,10 @@ async function fetchApi<T>(endpoint: string, options: RequestInit = {}): Promise
 
 // User API
 export const userApi = {
-  getCurrentUser: () => fetchApi<any>("/api/users/me"),
+  getCurrentUser: () => fetchApi<any>("/api/v1/users/me"),
   list: (params: { query?: string; limit?: number; offset?: number }) =>
     fetchApi<{ items: any[]; total: number }>(
-      `/api/users?${new URLSearchParams({
+      `/api/v1/users?${new URLSearchParams({
         ...(params.query && { query: params.query }),
         limit: params.limit?.toString() || "20",
         offset: params.offset?.toString() || "0",
@@ -67,12 +69,12 @@ export const userApi = {
 // Book API
 export const bookApi = {
   getTotalCount: async () => {
-    const result = await fetchApi<{ total: number }>("/api/books?limit=1");
+    const result = await fetchApi<{ total: number }>("/api/v1/books?limit=1");
     return result.total;
   },
   list: (params: { query?: string; limit?: number; offset?: number }) =>
     fetchApi<{ items: any[]; total: number }>(
-      `/api/books?${new URLSearchParams({
+      `/api/v1/books?${new URLSearchParams({
         ...(params.query && { query: params.query }),
         limit: params.limit?.toString() || "20",
         offset: params.offset?.toString() || "0",
@@ -82,33 +84,33 @@ export const bookApi = {
 
 // Checkout API
 export const checkoutApi = {
-  getOverdueCheckouts: () => fetchApi<any[]>("/api/checkouts/overdue"),
+  getOverdueCheckouts: () => fetchApi<any[]>("/api/v1/checkouts/overdue"),
   getActiveCount: async () => {
-    const result = await fetchApi<{ total: number }>("/api/checkouts?status=ACTIVE&limit=1");
+    const result = await fetchApi<{ total: number }>("/api/v1/checkouts?status=ACTIVE&limit=1");
     return result.total;
   },
   list: (params: { status?: string; limit?: number; offset?: number }) =>
     fetchApi<{ items: any[]; total: number }>(
-      `/api/checkouts?${new URLSearchParams({
+      `/api/v1/checkouts?${new URLSearchParams({
         ...(params.status && { status: params.status }),
         limit: params.limit?.toString() || "20",
         offset: params.offset?.toString() || "0",
       }).toString()}`
     ),
   checkoutBook: (data: { isbn: string; user_id: string }) =>
-    fetchApi<any>("/api/checkouts/checkout", {
+    fetchApi<any>("/api/v1/checkouts/checkout", {
       method: "POST",
       body: JSON.stringify(data),
     }),
   returnBook: (data: { checkoutId: string }) =>
-    fetchApi<any>("/api/checkouts/return", {
+    fetchApi<any>("/api/v1/checkouts/return", {
       method: "POST",
-      body: JSON.stringify({ checkout_id: data.checkoutId }),
+      body: JSON.stringify(data),
     }),
   renewCheckout: (data: { checkoutId: string }) =>
-    fetchApi<any>("/api/checkouts/renew", {
+    fetchApi<any>("/api/v1/checkouts/renew", {
       method: "POST",
-      body: JSON.stringify({ checkout_id: data.checkoutId }),
+      body: JSON.stringify(data),
     }),
 };
 
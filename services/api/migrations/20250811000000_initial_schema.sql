@@ -0,0 +1,71 @@
+-- Core library schema. Statements are idempotent so the migration can be
+-- applied on top of a database that was already provisioned through Prisma.
+
+DO $$ BEGIN
+    CREATE TYPE user_role AS ENUM ('USER', 'ADMIN');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE checkout_status AS ENUM ('ACTIVE', 'RETURNED', 'OVERDUE');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+CREATE TABLE IF NOT EXISTS users (
+    id UUID PRIMARY KEY,
+    email TEXT NOT NULL,
+    name TEXT NOT NULL,
+    email_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    image TEXT,
+    role user_role NOT NULL DEFAULT 'USER',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    max_checkouts INTEGER NOT NULL DEFAULT 5,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT users_email_key UNIQUE (email)
+);
+
+CREATE TABLE IF NOT EXISTS books (
+    id UUID PRIMARY KEY,
+    isbn TEXT NOT NULL,
+    title TEXT NOT NULL,
+    author TEXT NOT NULL,
+    publisher TEXT,
+    published_year INTEGER,
+    genre TEXT,
+    description TEXT,
+    cover_url TEXT,
+    total_copies INTEGER NOT NULL DEFAULT 1,
+    available_copies INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT books_isbn_key UNIQUE (isbn)
+);
+
+CREATE TABLE IF NOT EXISTS checkouts (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    book_id UUID NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+    status checkout_status NOT NULL DEFAULT 'ACTIVE',
+    checked_out_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    due_date TIMESTAMPTZ NOT NULL,
+    returned_at TIMESTAMPTZ,
+    renewal_count INTEGER NOT NULL DEFAULT 0,
+    max_renewals INTEGER NOT NULL DEFAULT 2,
+    overdue_email_sent BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS checkouts_user_id_status_idx ON checkouts (user_id, status);
+CREATE INDEX IF NOT EXISTS checkouts_book_id_status_idx ON checkouts (book_id, status);
+CREATE INDEX IF NOT EXISTS checkouts_due_date_idx ON checkouts (due_date) WHERE status = 'ACTIVE';
+
+CREATE TABLE IF NOT EXISTS overdue_email_failures (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    checkout_id UUID NOT NULL,
+    error_message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,70 @@
+-- Physical copies of a book. Availability counters on books are derived from
+-- copy status and refreshed whenever a copy changes.
+
+CREATE TYPE copy_status AS ENUM ('AVAILABLE', 'ON_LOAN', 'LOST', 'IN_REPAIR', 'WITHDRAWN');
+CREATE TYPE copy_condition AS ENUM ('NEW', 'GOOD', 'FAIR', 'POOR', 'DAMAGED');
+
+CREATE TABLE book_copies (
+    id UUID PRIMARY KEY,
+    book_id UUID NOT NULL REFERENCES books (id) ON DELETE CASCADE,
+    barcode TEXT NOT NULL,
+    shelf_location TEXT,
+    condition copy_condition NOT NULL DEFAULT 'GOOD',
+    status copy_status NOT NULL DEFAULT 'AVAILABLE',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT book_copies_barcode_key UNIQUE (barcode)
+);
+
+CREATE INDEX book_copies_book_id_status_idx ON book_copies (book_id, status);
+
+ALTER TABLE checkouts
+    ADD COLUMN copy_id UUID REFERENCES book_copies (id) ON DELETE SET NULL;
+
+-- A copy can only be on one active loan at a time.
+CREATE UNIQUE INDEX checkouts_active_copy_key ON checkouts (copy_id) WHERE status = 'ACTIVE';
+
+-- Backfill one copy per counted total_copies.
+INSERT INTO book_copies (id, book_id, barcode)
+SELECT
+    copy_id,
+    book_id,
+    'LIB-' || upper(substr(replace(copy_id::text, '-', ''), 1, 12))
+FROM (
+    SELECT gen_random_uuid() AS copy_id, b.id AS book_id
+    FROM books b, generate_series(1, GREATEST(b.total_copies, 0))
+) AS generated;
+
+-- Attach existing active loans to copies, oldest loan first.
+WITH ranked_loans AS (
+    SELECT
+        c.id AS checkout_id,
+        c.book_id,
+        ROW_NUMBER() OVER (PARTITION BY c.book_id ORDER BY c.checked_out_at) AS n
+    FROM checkouts c
+    WHERE c.status = 'ACTIVE'
+), ranked_copies AS (
+    SELECT
+        bc.id AS copy_id,
+        bc.book_id,
+        ROW_NUMBER() OVER (PARTITION BY bc.book_id ORDER BY bc.barcode) AS n
+    FROM book_copies bc
+)
+UPDATE checkouts c
+SET copy_id = rc.copy_id
+FROM ranked_loans rl
+JOIN ranked_copies rc ON rc.book_id = rl.book_id AND rc.n = rl.n
+WHERE c.id = rl.checkout_id;
+
+UPDATE book_copies
+SET status = 'ON_LOAN'
+WHERE id IN (SELECT copy_id FROM checkouts WHERE status = 'ACTIVE' AND copy_id IS NOT NULL);
+
+UPDATE books b
+SET
+    total_copies = (
+        SELECT COUNT(*) FROM book_copies bc WHERE bc.book_id = b.id AND bc.status <> 'WITHDRAWN'
+    ),
+    available_copies = (
+        SELECT COUNT(*) FROM book_copies bc WHERE bc.book_id = b.id AND bc.status = 'AVAILABLE'
+    );
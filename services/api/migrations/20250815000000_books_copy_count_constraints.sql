@@ -0,0 +1,6 @@
+-- Counters are derived from book_copies, but guard them at the database level too.
+
+ALTER TABLE books
+    ADD CONSTRAINT books_total_copies_non_negative CHECK (total_copies >= 0),
+    ADD CONSTRAINT books_available_copies_non_negative CHECK (available_copies >= 0),
+    ADD CONSTRAINT books_available_copies_within_total CHECK (available_copies <= total_copies);
@@ -0,0 +1,19 @@
+-- One row per occurrence of a scheduled job. Inserting the row is the claim: the instance
+-- whose insert succeeds runs the job, and replicas firing for the same occurrence skip it.
+
+CREATE TYPE job_run_status AS ENUM ('RUNNING', 'SUCCEEDED', 'FAILED');
+
+CREATE TABLE job_runs (
+    id UUID PRIMARY KEY,
+    job_name TEXT NOT NULL,
+    scheduled_for TIMESTAMPTZ NOT NULL,
+    instance_id TEXT NOT NULL,
+    status job_run_status NOT NULL DEFAULT 'RUNNING',
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ,
+    items_processed BIGINT,
+    error TEXT,
+    UNIQUE (job_name, scheduled_for)
+);
+
+CREATE INDEX job_runs_started_at_idx ON job_runs (started_at DESC);
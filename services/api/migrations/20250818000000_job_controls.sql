@@ -0,0 +1,22 @@
+-- Manual and dry runs from the admin API, and pauses that every replica honours.
+
+CREATE TYPE job_run_source AS ENUM ('SCHEDULE', 'MANUAL');
+
+ALTER TABLE job_runs
+    ADD COLUMN source job_run_source NOT NULL DEFAULT 'SCHEDULE',
+    ADD COLUMN dry_run BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN requested_by UUID;
+
+-- Only scheduled runs claim an occurrence; manual runs are keyed by request time and may repeat.
+ALTER TABLE job_runs DROP CONSTRAINT job_runs_job_name_scheduled_for_key;
+CREATE UNIQUE INDEX job_runs_occurrence_key ON job_runs (job_name, scheduled_for)
+    WHERE source = 'SCHEDULE';
+
+CREATE INDEX job_runs_job_name_started_at_idx ON job_runs (job_name, started_at DESC);
+
+-- A paused job is skipped when its schedule fires; it can still be run by hand.
+CREATE TABLE job_pauses (
+    job_name TEXT PRIMARY KEY,
+    paused_by UUID,
+    paused_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Token buckets for rate limiting, shared between replicas when the Postgres store is
+-- configured. A missing row is a full bucket, so rows untouched long enough to have
+-- refilled are purged.
+
+CREATE TABLE rate_limit_buckets (
+    key TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    -- Whether the last request took a token.
+    granted BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets (updated_at);
@@ -0,0 +1,41 @@
+-- Bulk catalog imports from the admin API. The upload is parsed up front; its rows are then
+-- applied in the background, and every row's outcome is kept as the import's report.
+
+CREATE TYPE book_import_format AS ENUM ('CSV', 'MARC21', 'MARCXML');
+CREATE TYPE book_import_status AS ENUM ('RUNNING', 'SUCCEEDED', 'FAILED');
+CREATE TYPE book_import_row_action AS ENUM ('CREATED', 'UPDATED', 'UNCHANGED', 'MERGED', 'FAILED');
+
+CREATE TABLE book_imports (
+    id UUID PRIMARY KEY,
+    format book_import_format NOT NULL,
+    -- A dry run applies each row in a transaction that is rolled back.
+    dry_run BOOLEAN NOT NULL,
+    status book_import_status NOT NULL DEFAULT 'RUNNING',
+    requested_by UUID,
+    total_rows INTEGER NOT NULL,
+    processed_rows INTEGER NOT NULL DEFAULT 0,
+    books_created INTEGER NOT NULL DEFAULT 0,
+    books_updated INTEGER NOT NULL DEFAULT 0,
+    books_unchanged INTEGER NOT NULL DEFAULT 0,
+    rows_failed INTEGER NOT NULL DEFAULT 0,
+    copies_added INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX book_imports_started_at_idx ON book_imports (started_at DESC);
+
+CREATE TABLE book_import_rows (
+    import_id UUID NOT NULL REFERENCES book_imports (id) ON DELETE CASCADE,
+    -- 1-based position of the record in the upload, not counting a CSV header.
+    row_number INTEGER NOT NULL,
+    isbn TEXT,
+    action book_import_row_action NOT NULL,
+    book_id UUID,
+    copies_added INTEGER NOT NULL DEFAULT 0,
+    message TEXT,
+    PRIMARY KEY (import_id, row_number)
+);
+
+CREATE INDEX book_import_rows_action_idx ON book_import_rows (import_id, action);
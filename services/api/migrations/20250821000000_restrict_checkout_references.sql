@@ -0,0 +1,12 @@
+-- Checkouts are circulation history and outlive the soft delete of their book or user, so
+-- a hard delete must not take them along. Purging skips anything a checkout references.
+
+ALTER TABLE checkouts
+    DROP CONSTRAINT checkouts_user_id_fkey,
+    ADD CONSTRAINT checkouts_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE RESTRICT;
+
+ALTER TABLE checkouts
+    DROP CONSTRAINT checkouts_book_id_fkey,
+    ADD CONSTRAINT checkouts_book_id_fkey
+        FOREIGN KEY (book_id) REFERENCES books (id) ON DELETE RESTRICT;
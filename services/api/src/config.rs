@@ -0,0 +1,967 @@
+//! Startup configuration: built-in defaults, then the selected profile's defaults, then an
+//! optional TOML file (with an optional `[profiles.<name>]` section), then environment
+//! variables. Everything is validated before the server starts.
+
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::{
+    middleware::{cors::CorsPolicies, rate_limit::RateLimitPolicies},
+    services::{idempotency, retention},
+};
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Dev,
+    Test,
+    Prod,
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" | "development" => Ok(Self::Dev),
+            "test" => Ok(Self::Test),
+            "prod" | "production" => Ok(Self::Prod),
+            other => Err(format!(
+                "unknown profile `{other}` (expected dev, test or prod)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dev => "dev",
+            Self::Test => "test",
+            Self::Prod => "prod",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub profile: Profile,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub log: LogConfig,
+    pub telemetry: TelemetryConfig,
+    pub auth: AuthConfig,
+    pub api: ApiConfig,
+    pub cors: CorsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub supabase: SupabaseConfig,
+    pub email: EmailConfig,
+    pub idempotency: IdempotencyConfig,
+    pub retention: RetentionConfig,
+    pub jobs: JobsConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// How long shutdown waits for in-flight requests before closing them.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// A `tracing` env-filter directive; `RUST_LOG` wins when set.
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per event, with the fields of the spans it happened in.
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Base URL of an OTLP/HTTP collector, e.g. `http://localhost:4318`. Traces are only
+    /// exported when this is set.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Base URL of the auth service that verifies bearer tokens.
+    pub service_url: String,
+}
+
+/// The versioned routes are always served under `/api/<version>`; the unversioned `/api/...`
+/// paths are kept as aliases of v1, marked deprecated, until `legacy_sunset`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+    /// How JSON keys are cased, for clients that don't ask for a casing in `Accept`.
+    pub casing: Casing,
+    pub legacy_routes: bool,
+    /// Sent in the aliases' `Deprecation` header.
+    pub legacy_deprecated_at: DateTime<Utc>,
+    /// Sent in the aliases' `Sunset` header: when clients should have moved to `/api/v1`.
+    pub legacy_sunset: DateTime<Utc>,
+}
+
+/// The casing of keys in JSON bodies. As `api.casing`, `snake_case` leaves bodies in the v1
+/// shape the OpenAPI document describes, and `camelCase` renames every key on the way out and
+/// accepts camelCase keys on the way in. Asked for in `Accept`, either casing is applied to
+/// every key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Casing {
+    #[serde(rename = "snake_case")]
+    SnakeCase,
+    #[serde(rename = "camelCase")]
+    CamelCase,
+}
+
+/// The default CORS policy. Origins may be exact (`https://app.example.com`), a subdomain
+/// pattern (`https://*.example.com`) or `*`; methods and headers may also be `*`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age_secs: u64,
+    /// Policies for particular routes, checked in order before the default.
+    #[serde(default)]
+    pub routes: Vec<CorsRouteConfig>,
+}
+
+/// A CORS policy for requests whose path starts with `path` and whose method (or, for a
+/// preflight, requested method) is one of `methods`. Unset fields come from the default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorsRouteConfig {
+    pub path: String,
+    pub methods: Vec<String>,
+    pub allowed_origins: Vec<String>,
+    pub allowed_headers: Option<Vec<String>>,
+    pub allow_credentials: Option<bool>,
+    pub max_age_secs: Option<u64>,
+}
+
+/// Token-bucket limits on `/api` requests, kept per user for requests with a valid bearer
+/// token and per client IP otherwise. A bucket holds `burst` requests and refills at
+/// `requests_per_minute`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Where buckets are kept: `memory` for this instance only, or `postgres` to share them
+    /// between replicas.
+    pub store: RateLimitStore,
+    /// Proxies whose `X-Forwarded-For` is believed, as addresses or CIDR ranges.
+    pub trusted_proxies: Vec<String>,
+    /// The limit for requests no group covers.
+    pub requests_per_minute: u32,
+    pub burst: u32,
+    /// Groups with limits of their own, checked in order before the default.
+    #[serde(default)]
+    pub groups: Vec<RateLimitGroupConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStore {
+    Memory,
+    Postgres,
+}
+
+/// A limit for requests whose path starts with one of `paths` and whose method is one of
+/// `methods`, or any method when `methods` is empty. Each group has its own buckets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitGroupConfig {
+    pub name: String,
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub methods: Vec<String>,
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SupabaseConfig {
+    pub url: Option<String>,
+    pub service_role_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub resend_api_url: String,
+    /// Overdue notices are not sent without a key.
+    pub resend_api_key: Option<String>,
+    pub from: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdempotencyConfig {
+    pub key_ttl_hours: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub soft_delete_days: i32,
+}
+
+/// Scheduled jobs. Schedules are six-field cron expressions (seconds first).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobsConfig {
+    pub enabled: bool,
+    /// How long shutdown waits for running jobs to finish or reach a checkpoint.
+    pub shutdown_timeout_secs: u64,
+    pub overdue_notifications: String,
+    pub soft_delete_purge: String,
+    pub inventory_check: String,
+    pub idempotency_purge: String,
+    /// Only scheduled with the `postgres` rate limit store.
+    pub rate_limit_purge: String,
+}
+
+impl Config {
+    /// Built-in settings for `profile`, before any file or environment overrides.
+    pub fn defaults(profile: Profile) -> Self {
+        let (log_filter, log_format, allowed_origins) = match profile {
+            Profile::Dev => (
+                "library_api=debug,tower_http=debug",
+                LogFormat::Text,
+                vec!["http://localhost:3000".to_string()],
+            ),
+            Profile::Test => (
+                "library_api=info",
+                LogFormat::Text,
+                vec!["http://localhost:3000".to_string()],
+            ),
+            Profile::Prod => (
+                "library_api=info,tower_http=info",
+                LogFormat::Json,
+                Vec::new(),
+            ),
+        };
+
+        Self {
+            profile,
+            server: ServerConfig {
+                host: "0.0.0.0".to_string(),
+                port: 8081,
+                shutdown_timeout_secs: 15,
+            },
+            database: DatabaseConfig {
+                url: String::new(),
+                max_connections: 10,
+            },
+            log: LogConfig {
+                filter: log_filter.to_string(),
+                format: log_format,
+            },
+            telemetry: TelemetryConfig {
+                otlp_endpoint: None,
+                service_name: "library-api".to_string(),
+            },
+            auth: AuthConfig {
+                service_url: "http://localhost:3001".to_string(),
+            },
+            api: ApiConfig {
+                casing: Casing::SnakeCase,
+                legacy_routes: true,
+                legacy_deprecated_at: Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap(),
+                legacy_sunset: Utc.with_ymd_and_hms(2027, 4, 30, 0, 0, 0).unwrap(),
+            },
+            cors: CorsConfig {
+                allowed_origins,
+                allowed_methods: ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
+                    .map(String::from)
+                    .to_vec(),
+                allowed_headers: ["content-type", "authorization", "idempotency-key"]
+                    .map(String::from)
+                    .to_vec(),
+                allow_credentials: true,
+                max_age_secs: 3600,
+                routes: Vec::new(),
+            },
+            rate_limit: RateLimitConfig {
+                enabled: profile != Profile::Test,
+                store: RateLimitStore::Memory,
+                // Loopback and the private ranges nginx reaches the API from.
+                trusted_proxies: [
+                    "127.0.0.0/8",
+                    "::1",
+                    "10.0.0.0/8",
+                    "172.16.0.0/12",
+                    "192.168.0.0/16",
+                ]
+                .map(String::from)
+                .to_vec(),
+                requests_per_minute: 300,
+                burst: 100,
+                // Lookups by title, author or email can be used to enumerate the catalogue
+                // and the patrons, so they get a tighter budget.
+                groups: vec![RateLimitGroupConfig {
+                    name: "search".to_string(),
+                    paths: ["/api/v1/books", "/api/v1/users", "/api/books", "/api/users"]
+                        .map(String::from)
+                        .to_vec(),
+                    methods: vec!["GET".to_string()],
+                    requests_per_minute: 60,
+                    burst: 20,
+                }],
+            },
+            supabase: SupabaseConfig {
+                url: None,
+                service_role_key: None,
+            },
+            email: EmailConfig {
+                resend_api_url: "https://api.resend.com".to_string(),
+                resend_api_key: None,
+                from: "Library System <noreply@library.com>".to_string(),
+            },
+            idempotency: IdempotencyConfig {
+                key_ttl_hours: idempotency::DEFAULT_TTL_HOURS,
+            },
+            retention: RetentionConfig {
+                soft_delete_days: retention::DEFAULT_RETENTION_DAYS,
+            },
+            jobs: JobsConfig {
+                enabled: profile != Profile::Test,
+                shutdown_timeout_secs: 15,
+                overdue_notifications: "0 0 0 * * *".to_string(),
+                soft_delete_purge: "0 30 0 * * *".to_string(),
+                inventory_check: "0 15 * * * *".to_string(),
+                idempotency_purge: "0 45 * * * *".to_string(),
+                rate_limit_purge: "0 */10 * * * *".to_string(),
+            },
+        }
+    }
+
+    /// Loads the configuration for this process from `cli`, the TOML file it names (or
+    /// `CONFIG_FILE`) and the environment.
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("CONFIG_FILE").map(PathBuf::from));
+        let file = path
+            .map(|path| {
+                std::fs::read_to_string(&path)
+                    .map_err(|e| ConfigError(vec![format!("cannot read {}: {e}", path.display())]))
+            })
+            .transpose()?;
+
+        Self::from_sources(cli.profile.as_deref(), file.as_deref(), |name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    fn from_sources(
+        profile: Option<&str>,
+        file: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+
+        let mut file = match file.map(str::parse::<Table>).transpose() {
+            Ok(file) => file.unwrap_or_default(),
+            Err(e) => {
+                problems.push(format!("invalid config file: {}", e.to_string().trim()));
+                Table::new()
+            }
+        };
+        let mut profiles = match file.remove("profiles") {
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => {
+                problems.push("`profiles` must be a table".to_string());
+                Table::new()
+            }
+            None => Table::new(),
+        };
+
+        let file_profile = file
+            .remove("profile")
+            .and_then(|p| p.as_str().map(str::to_owned));
+        let profile = match profile
+            .map(str::to_owned)
+            .or_else(|| env("APP_PROFILE"))
+            .or(file_profile)
+            .map(|p| p.parse::<Profile>())
+            .transpose()
+        {
+            Ok(profile) => profile.unwrap_or(Profile::Dev),
+            Err(e) => {
+                problems.push(e);
+                Profile::Dev
+            }
+        };
+
+        let defaults = match Value::try_from(Self::defaults(profile)) {
+            Ok(Value::Table(table)) => table,
+            _ => unreachable!("Config serializes to a table"),
+        };
+        let mut merged = defaults.clone();
+        merge(&mut merged, file);
+        match profiles.remove(&profile.to_string()) {
+            Some(Value::Table(overrides)) => merge(&mut merged, overrides),
+            Some(_) => problems.push(format!("`profiles.{profile}` must be a table")),
+            None => {}
+        }
+        merge(&mut merged, env_overrides(&env, &mut problems));
+
+        // Deserializing stops at the first error, so try each section on its own against the
+        // defaults. A section that fails is reported, falls back to its default and is left
+        // out of validation, so that the rest are still checked.
+        let mut invalid = Vec::new();
+        for (key, value) in merged.clone() {
+            let mut candidate = defaults.clone();
+            candidate.insert(key.clone(), value);
+            if let Err(e) = Value::Table(candidate).try_into::<Self>() {
+                problems.push(e.to_string().trim().replace('\n', " "));
+                match defaults.get(&key) {
+                    Some(default) => merged.insert(key.clone(), default.clone()),
+                    None => merged.remove(&key),
+                };
+                invalid.push(format!("{key}."));
+            }
+        }
+        let config = Value::Table(merged)
+            .try_into::<Self>()
+            .expect("every section deserializes");
+
+        problems.extend(
+            config
+                .problems()
+                .into_iter()
+                .filter(|problem| !invalid.iter().any(|key| problem.starts_with(key))),
+        );
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+
+    /// Everything wrong with this configuration.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.database.url.is_empty() {
+            problems.push("database.url is required (DATABASE_URL)".to_string());
+        } else if !self.database.url.starts_with("postgres://")
+            && !self.database.url.starts_with("postgresql://")
+        {
+            problems.push("database.url must be a postgres:// URL".to_string());
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections must be at least 1".to_string());
+        }
+        if self.server.port == 0 {
+            problems.push("server.port must not be 0".to_string());
+        }
+
+        let urls = [
+            ("auth.service_url", Some(&self.auth.service_url)),
+            ("email.resend_api_url", Some(&self.email.resend_api_url)),
+            ("supabase.url", self.supabase.url.as_ref()),
+            (
+                "telemetry.otlp_endpoint",
+                self.telemetry.otlp_endpoint.as_ref(),
+            ),
+        ];
+        for (name, url) in urls {
+            if let Some(url) = url {
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    problems.push(format!("{name} must be an http(s) URL, got `{url}`"));
+                }
+            }
+        }
+        if self.api.legacy_sunset <= self.api.legacy_deprecated_at {
+            problems.push("api.legacy_sunset must be after api.legacy_deprecated_at".to_string());
+        }
+        if let Err(cors_problems) = CorsPolicies::from_config(&self.cors) {
+            problems.extend(cors_problems);
+        }
+        if let Err(rate_limit_problems) = RateLimitPolicies::from_config(&self.rate_limit) {
+            problems.extend(rate_limit_problems);
+        }
+
+        if self.supabase.url.is_some() != self.supabase.service_role_key.is_some() {
+            problems.push(
+                "supabase.url and supabase.service_role_key must be set together".to_string(),
+            );
+        }
+        if self.profile == Profile::Prod && self.email.resend_api_key.is_none() {
+            problems.push("email.resend_api_key is required in prod (RESEND_API_KEY)".to_string());
+        }
+
+        if self.idempotency.key_ttl_hours < 1 {
+            problems.push("idempotency.key_ttl_hours must be at least 1".to_string());
+        }
+        if self.retention.soft_delete_days < 1 {
+            problems.push("retention.soft_delete_days must be at least 1".to_string());
+        }
+
+        let schedules = [
+            (
+                "jobs.overdue_notifications",
+                &self.jobs.overdue_notifications,
+            ),
+            ("jobs.soft_delete_purge", &self.jobs.soft_delete_purge),
+            ("jobs.inventory_check", &self.jobs.inventory_check),
+            ("jobs.idempotency_purge", &self.jobs.idempotency_purge),
+            ("jobs.rate_limit_purge", &self.jobs.rate_limit_purge),
+        ];
+        for (name, schedule) in schedules {
+            if let Err(e) = cron::Schedule::from_str(schedule) {
+                problems.push(format!("{name} is not a valid cron expression: {e}"));
+            }
+        }
+
+        problems
+    }
+
+    /// A copy that is safe to print, with passwords and API keys masked.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.database.url = redact_password(&config.database.url);
+        if config.supabase.service_role_key.is_some() {
+            config.supabase.service_role_key = Some(REDACTED.to_string());
+        }
+        if config.email.resend_api_key.is_some() {
+            config.email.resend_api_key = Some(REDACTED.to_string());
+        }
+        config
+    }
+
+    /// The redacted configuration as TOML, for `--print-config`.
+    pub fn to_redacted_toml(&self) -> String {
+        toml::to_string_pretty(&self.redacted()).expect("Config serializes to TOML")
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::defaults(Profile::Dev)
+    }
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Command-line flags.
+#[derive(Debug, Default)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub print_config: bool,
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, ConfigError> {
+        let mut cli = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ConfigError(vec![format!("{flag} needs a value")]))
+            };
+
+            match flag.as_str() {
+                "--config" => cli.config = Some(PathBuf::from(value()?)),
+                "--profile" => cli.profile = Some(value()?),
+                "--print-config" => cli.print_config = true,
+                other => {
+                    return Err(ConfigError(vec![format!(
+                    "unknown argument `{other}` (expected --config, --profile or --print-config)"
+                )]))
+                }
+            }
+        }
+
+        Ok(cli)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    Int,
+    Bool,
+    List,
+}
+
+/// Environment variables and the settings they override.
+const ENV_VARS: &[(&str, &str, Kind)] = &[
+    ("API_HOST", "server.host", Kind::Str),
+    ("API_PORT", "server.port", Kind::Int),
+    (
+        "SHUTDOWN_TIMEOUT_SECS",
+        "server.shutdown_timeout_secs",
+        Kind::Int,
+    ),
+    ("DATABASE_URL", "database.url", Kind::Str),
+    (
+        "DATABASE_MAX_CONNECTIONS",
+        "database.max_connections",
+        Kind::Int,
+    ),
+    ("RUST_LOG", "log.filter", Kind::Str),
+    ("LOG_FORMAT", "log.format", Kind::Str),
+    (
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "telemetry.otlp_endpoint",
+        Kind::Str,
+    ),
+    ("OTEL_SERVICE_NAME", "telemetry.service_name", Kind::Str),
+    ("AUTH_SERVICE_URL", "auth.service_url", Kind::Str),
+    ("API_CASING", "api.casing", Kind::Str),
+    ("API_LEGACY_ROUTES", "api.legacy_routes", Kind::Bool),
+    ("API_LEGACY_SUNSET", "api.legacy_sunset", Kind::Str),
+    ("CORS_ALLOWED_ORIGINS", "cors.allowed_origins", Kind::List),
+    ("CORS_ALLOWED_METHODS", "cors.allowed_methods", Kind::List),
+    ("CORS_ALLOWED_HEADERS", "cors.allowed_headers", Kind::List),
+    (
+        "CORS_ALLOW_CREDENTIALS",
+        "cors.allow_credentials",
+        Kind::Bool,
+    ),
+    ("CORS_MAX_AGE_SECS", "cors.max_age_secs", Kind::Int),
+    ("RATE_LIMIT_ENABLED", "rate_limit.enabled", Kind::Bool),
+    ("RATE_LIMIT_STORE", "rate_limit.store", Kind::Str),
+    (
+        "RATE_LIMIT_TRUSTED_PROXIES",
+        "rate_limit.trusted_proxies",
+        Kind::List,
+    ),
+    (
+        "RATE_LIMIT_REQUESTS_PER_MINUTE",
+        "rate_limit.requests_per_minute",
+        Kind::Int,
+    ),
+    ("RATE_LIMIT_BURST", "rate_limit.burst", Kind::Int),
+    ("SUPABASE_URL", "supabase.url", Kind::Str),
+    (
+        "SUPABASE_SERVICE_ROLE_KEY",
+        "supabase.service_role_key",
+        Kind::Str,
+    ),
+    ("RESEND_API_URL", "email.resend_api_url", Kind::Str),
+    ("RESEND_API_KEY", "email.resend_api_key", Kind::Str),
+    ("EMAIL_FROM", "email.from", Kind::Str),
+    (
+        "IDEMPOTENCY_KEY_TTL_HOURS",
+        "idempotency.key_ttl_hours",
+        Kind::Int,
+    ),
+    (
+        "SOFT_DELETE_RETENTION_DAYS",
+        "retention.soft_delete_days",
+        Kind::Int,
+    ),
+    ("JOBS_ENABLED", "jobs.enabled", Kind::Bool),
+    (
+        "JOBS_SHUTDOWN_TIMEOUT_SECS",
+        "jobs.shutdown_timeout_secs",
+        Kind::Int,
+    ),
+    ("OVERDUE_JOB_CRON", "jobs.overdue_notifications", Kind::Str),
+    (
+        "SOFT_DELETE_PURGE_JOB_CRON",
+        "jobs.soft_delete_purge",
+        Kind::Str,
+    ),
+    (
+        "INVENTORY_CHECK_JOB_CRON",
+        "jobs.inventory_check",
+        Kind::Str,
+    ),
+    (
+        "IDEMPOTENCY_PURGE_JOB_CRON",
+        "jobs.idempotency_purge",
+        Kind::Str,
+    ),
+    (
+        "RATE_LIMIT_PURGE_JOB_CRON",
+        "jobs.rate_limit_purge",
+        Kind::Str,
+    ),
+];
+
+fn env_overrides(env: &impl Fn(&str) -> Option<String>, problems: &mut Vec<String>) -> Table {
+    let mut overrides = Table::new();
+
+    for &(name, key, kind) in ENV_VARS {
+        // Empty values are treated as unset, matching how .env templates leave them blank.
+        let Some(raw) = env(name).filter(|v| !v.trim().is_empty()) else {
+            continue;
+        };
+        let value = match kind {
+            Kind::Str => Value::String(raw),
+            Kind::Int => match raw.trim().parse::<i64>() {
+                Ok(n) => Value::Integer(n),
+                Err(_) => {
+                    problems.push(format!("{name} must be an integer, got `{raw}`"));
+                    continue;
+                }
+            },
+            Kind::Bool => match raw.trim() {
+                "true" | "1" => Value::Boolean(true),
+                "false" | "0" => Value::Boolean(false),
+                _ => {
+                    problems.push(format!("{name} must be true or false, got `{raw}`"));
+                    continue;
+                }
+            },
+            Kind::List => Value::Array(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| Value::String(item.to_string()))
+                    .collect(),
+            ),
+        };
+
+        let (section, field) = key.split_once('.').expect("keys are section.field");
+        let section = overrides
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()));
+        if let Value::Table(section) = section {
+            section.insert(field.to_string(), value);
+        }
+    }
+
+    overrides
+}
+
+/// Recursively overlays `overrides` onto `base`; tables merge, everything else replaces.
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overrides)) => merge(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn redact_password(url: &str) -> String {
+    let Some(scheme_end) = url.find("://").map(|i| i + 3) else {
+        return url.to_string();
+    };
+    let authority_end = url[scheme_end..]
+        .find('/')
+        .map_or(url.len(), |i| scheme_end + i);
+    let Some(at) = url[scheme_end..authority_end].rfind('@') else {
+        return url.to_string();
+    };
+    let userinfo = &url[scheme_end..scheme_end + at];
+    match userinfo.split_once(':') {
+        Some((user, _)) => format!(
+            "{}{user}:{REDACTED}{}",
+            &url[..scheme_end],
+            &url[scheme_end + at..]
+        ),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn load(
+        profile: Option<&str>,
+        file: Option<&str>,
+        env: &[(&str, &str)],
+    ) -> Result<Config, ConfigError> {
+        let env: HashMap<_, _> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_sources(profile, file, |name| env.get(name).cloned())
+    }
+
+    const DB: (&str, &str) = ("DATABASE_URL", "postgres://app:secret@db:5432/library");
+
+    #[test]
+    fn test_layers_apply_in_order() {
+        let file = r#"
+            [server]
+            port = 9000
+            host = "127.0.0.1"
+
+            [profiles.prod.server]
+            port = 80
+
+            [profiles.prod.email]
+            resend_api_key = "re_file"
+        "#;
+
+        let dev = load(None, Some(file), &[DB]).unwrap();
+        assert_eq!(dev.profile, Profile::Dev);
+        assert_eq!(dev.server.port, 9000);
+        assert_eq!(dev.cors.allowed_origins, ["http://localhost:3000"]);
+
+        let prod = load(Some("prod"), Some(file), &[DB, ("API_HOST", "10.0.0.1")]).unwrap();
+        assert_eq!(prod.server.port, 80);
+        assert_eq!(prod.server.host, "10.0.0.1");
+        assert!(prod.cors.allowed_origins.is_empty());
+
+        let from_env = load(None, Some(file), &[DB, ("APP_PROFILE", "test")]).unwrap();
+        assert_eq!(from_env.profile, Profile::Test);
+        assert!(!from_env.jobs.enabled);
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let file = r#"
+            [jobs]
+            overdue_notifications = "every night"
+        "#;
+        let env = [
+            ("APP_PROFILE", "prod"),
+            ("API_PORT", "eighty"),
+            ("SUPABASE_URL", "https://xyz.supabase.co"),
+            ("CORS_ALLOWED_ORIGINS", "https://app.example.com, kiosk"),
+        ];
+
+        let problems = load(None, Some(file), &env).unwrap_err().0;
+        let expected = [
+            "API_PORT must be an integer",
+            "database.url is required",
+            "cors.allowed_origins has invalid origin `kiosk`",
+            "supabase.url and supabase.service_role_key must be set together",
+            "email.resend_api_key is required in prod",
+            "jobs.overdue_notifications is not a valid cron expression",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{problems:#?}");
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(problem.starts_with(expected), "{problem}");
+        }
+    }
+
+    #[test]
+    fn test_reports_deserialization_errors_with_the_other_problems() {
+        let file = r#"
+            [server]
+            port = "eighty"
+
+            [database]
+            url = "mysql://db/library"
+            max_conections = 5
+
+            [jobs]
+            overdue_notifications = "every night"
+        "#;
+
+        let problems = load(None, Some(file), &[]).unwrap_err().0;
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+        assert!(
+            problems.iter().any(|p| p.contains("server.port")),
+            "{problems:#?}"
+        );
+        assert!(problems.iter().any(|p| p.contains("max_conections")));
+        assert!(problems[2].starts_with("jobs.overdue_notifications is not a valid cron"));
+
+        let problems = load(Some("staging"), Some("[server"), &[("API_PORT", "x")])
+            .unwrap_err()
+            .0;
+        let expected = [
+            "invalid config file",
+            "unknown profile",
+            "API_PORT must be an integer",
+            "database.url is required",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{problems:#?}");
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(problem.starts_with(expected), "{problem}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = load(None, Some("[server]\nprot = 1\n"), &[DB]).unwrap_err();
+        assert!(err.to_string().contains("prot"), "{err}");
+    }
+
+    #[test]
+    fn test_print_config_redacts_secrets() {
+        let env = [
+            DB,
+            ("SUPABASE_URL", "https://xyz.supabase.co"),
+            ("SUPABASE_SERVICE_ROLE_KEY", "service-secret"),
+            ("RESEND_API_KEY", "re_secret"),
+        ];
+        let printed = load(None, None, &env).unwrap().to_redacted_toml();
+
+        assert!(!printed.contains("secret"), "{printed}");
+        assert!(printed.contains("postgres://app:[redacted]@db:5432/library"));
+        assert!(printed.contains("https://xyz.supabase.co"));
+    }
+
+    #[test]
+    fn test_cli_flags() {
+        let args = ["--profile=prod", "--config", "api.toml", "--print-config"];
+        let cli = Cli::parse(args.map(String::from)).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("prod"));
+        assert_eq!(cli.config, Some(PathBuf::from("api.toml")));
+        assert!(cli.print_config);
+
+        assert!(Cli::parse(["--verbose".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,1097 @@
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use sqlx::{Postgres, QueryBuilder};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::{
+    middleware::request_id,
+    models::{
+        AuditLogEntry, AuditLogQuery, Book, BookImport, BookImportRow, BookImportRowQuery,
+        ImportBooksQuery, InventoryDiscrepancy, InventoryRepairRequest, JobRun, JobRunQuery,
+        JobStatus, Page, PageQuery, RunJobQuery, User,
+    },
+    services::{
+        audit::{self, AuditContext, AuditEvent},
+        catalog_import::{self, ImportError},
+        inventory,
+    },
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/audit", get(list_audit_log))
+        .route("/books/deleted", get(list_deleted_books))
+        .route(
+            "/books/import",
+            post(import_books).layer(DefaultBodyLimit::max(catalog_import::MAX_UPLOAD_BYTES)),
+        )
+        .route("/books/imports", get(list_book_imports))
+        .route("/books/imports/:id", get(get_book_import))
+        .route("/books/imports/:id/rows", get(list_book_import_rows))
+        .route("/books/:id/restore", post(restore_book))
+        .route("/users/deleted", get(list_deleted_users))
+        .route("/users/:id/restore", post(restore_user))
+        .route(
+            "/inventory/discrepancies",
+            get(list_inventory_discrepancies),
+        )
+        .route("/inventory/repair", post(repair_inventory))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/runs", get(list_job_runs))
+        .route("/jobs/:name/run", post(run_job))
+        .route("/jobs/:name/pause", post(pause_job))
+        .route("/jobs/:name/resume", post(resume_job))
+}
+
+/// The OpenAPI description of these routes.
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_audit_log,
+    list_jobs,
+    run_job,
+    pause_job,
+    resume_job,
+    list_job_runs,
+    list_deleted_books,
+    restore_book,
+    import_books,
+    list_book_imports,
+    get_book_import,
+    list_book_import_rows,
+    list_deleted_users,
+    restore_user,
+    list_inventory_discrepancies,
+    repair_inventory,
+))]
+pub struct ApiDoc;
+
+fn push_audit_filters<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: &'a AuditLogQuery) {
+    builder.push(" WHERE TRUE");
+
+    if let Some(actor_id) = query.actor_id {
+        builder.push(" AND actor_id = ").push_bind(actor_id);
+    }
+    if let Some(ref entity_type) = query.entity_type {
+        builder.push(" AND entity_type = ").push_bind(entity_type);
+    }
+    if let Some(entity_id) = query.entity_id {
+        builder.push(" AND entity_id = ").push_bind(entity_id);
+    }
+    if let Some(ref action) = query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+    if let Some(from) = query.from {
+        builder.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder.push(" AND created_at < ").push_bind(to);
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "admin",
+    params(AuditLogQuery),
+    responses(
+        (status = 200, description = "Audit log entries, newest first", body = Page<AuditLogEntry>),
+    )
+)]
+async fn list_audit_log(
+    State(state): State<AppState>,
+    Query(query): Query<AuditLogQuery>,
+) -> Result<Json<Page<AuditLogEntry>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).min(500);
+    let offset = query.offset.unwrap_or(0);
+
+    let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM audit_log");
+    push_audit_filters(&mut count_query, &query);
+
+    let total_count: i64 = count_query
+        .build_query_scalar()
+        .fetch_one(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut entries_query = QueryBuilder::<Postgres>::new("SELECT * FROM audit_log");
+    push_audit_filters(&mut entries_query, &query);
+    entries_query
+        .push(" ORDER BY created_at DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+
+    let entries = entries_query
+        .build_query_as::<AuditLogEntry>()
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(entries, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every registered job", body = Vec<JobStatus>),
+    )
+)]
+async fn list_jobs(State(state): State<AppState>) -> Result<Json<Vec<JobStatus>>, StatusCode> {
+    let jobs = state
+        .jobs
+        .statuses()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(jobs))
+}
+
+/// Runs a job now and returns the finished run; `?dry_run=true` reports what it would do.
+/// Paused jobs can still be run this way. 409 if the job is already running anywhere.
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/run",
+    tag = "admin",
+    params(
+        ("name" = String, Path),
+        RunJobQuery,
+    ),
+    responses(
+        (status = 200, description = "The finished run", body = JobRun),
+        (status = 404, description = "No such job"),
+        (status = 409, description = "The job is already running"),
+        (status = 503, description = "The server is shutting down"),
+    )
+)]
+async fn run_job(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<RunJobQuery>,
+    ctx: AuditContext,
+) -> Result<Json<JobRun>, StatusCode> {
+    let run = state
+        .jobs
+        .run_now(&name, query.dry_run.unwrap_or(false), ctx.actor_id)
+        .await?;
+
+    Ok(Json(run))
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/pause",
+    tag = "admin",
+    params(
+        ("name" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "The paused job", body = JobStatus),
+        (status = 404, description = "No such job"),
+    )
+)]
+async fn pause_job(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ctx: AuditContext,
+) -> Result<Json<JobStatus>, StatusCode> {
+    Ok(Json(
+        state.jobs.set_paused(&name, true, ctx.actor_id).await?,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/jobs/{name}/resume",
+    tag = "admin",
+    params(
+        ("name" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "The resumed job", body = JobStatus),
+        (status = 404, description = "No such job"),
+    )
+)]
+async fn resume_job(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ctx: AuditContext,
+) -> Result<Json<JobStatus>, StatusCode> {
+    Ok(Json(
+        state.jobs.set_paused(&name, false, ctx.actor_id).await?,
+    ))
+}
+
+fn push_job_run_filters<'a>(builder: &mut QueryBuilder<'a, Postgres>, query: &'a JobRunQuery) {
+    builder.push(" WHERE TRUE");
+
+    if let Some(ref job_name) = query.job_name {
+        builder.push(" AND job_name = ").push_bind(job_name);
+    }
+    if let Some(status) = query.status {
+        builder.push(" AND status = ").push_bind(status);
+    }
+}
+
+/// Scheduled job run history, newest first.
+#[utoipa::path(
+    get,
+    path = "/jobs/runs",
+    tag = "admin",
+    params(JobRunQuery),
+    responses(
+        (status = 200, description = "Job runs, newest first", body = Page<JobRun>),
+    )
+)]
+async fn list_job_runs(
+    State(state): State<AppState>,
+    Query(query): Query<JobRunQuery>,
+) -> Result<Json<Page<JobRun>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).min(500);
+    let offset = query.offset.unwrap_or(0);
+
+    let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM job_runs");
+    push_job_run_filters(&mut count_query, &query);
+
+    let total_count: i64 = count_query
+        .build_query_scalar()
+        .fetch_one(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut runs_query = QueryBuilder::<Postgres>::new("SELECT * FROM job_runs");
+    push_job_run_filters(&mut runs_query, &query);
+    runs_query
+        .push(" ORDER BY started_at DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+
+    let runs = runs_query
+        .build_query_as::<JobRun>()
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(runs, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/books/deleted",
+    tag = "admin",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "Soft-deleted books", body = Page<Book>),
+    )
+)]
+async fn list_deleted_books(
+    State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<Book>>, StatusCode> {
+    let limit = query.limit.unwrap_or(20).min(100);
+    let offset = query.offset.unwrap_or(0);
+
+    let total_count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM books WHERE deleted_at IS NOT NULL")
+            .fetch_one(&state.db)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let books = sqlx::query_as::<_, Book>(
+        "SELECT * FROM books WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(books, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/books/{id}/restore",
+    tag = "admin",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+    ),
+    responses(
+        (status = 200, description = "The restored book", body = Book),
+        (status = 404, description = "No such deleted book"),
+        (status = 409, description = "A live book has the ISBN"),
+    )
+)]
+async fn restore_book(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Book>, StatusCode> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let deleted_book = sqlx::query_as::<_, Book>(
+        "SELECT * FROM books WHERE id = $1 AND deleted_at IS NOT NULL FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    // A live book may have taken over the ISBN since this one was deleted.
+    let book = sqlx::query_as::<_, Book>(
+        "UPDATE books SET deleted_at = NULL, updated_at = $2 WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .bind(state.clock.now())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    audit::record(
+        &mut *tx,
+        &ctx,
+        AuditEvent::new("book.restore", "book", book.id)
+            .before(&deleted_book)
+            .after(&book),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(book))
+}
+
+/// Starts importing books from a CSV, MARC21 or MARCXML upload. The upload is read at once,
+/// so an unreadable file is refused; its rows are applied in the background, and the
+/// returned import reports progress and, once finished, the outcome of every row.
+#[utoipa::path(
+    post,
+    path = "/books/import",
+    tag = "admin",
+    params(ImportBooksQuery),
+    request_body(
+        description = "The records to import",
+        content(
+            (String = "text/csv"),
+            (Vec<u8> = "application/marc"),
+            (String = "application/marcxml+xml"),
+        ),
+    ),
+    responses(
+        (status = 202, description = "The import, started", body = BookImport),
+        (status = 400, description = "The upload can't be read"),
+        (status = 413, description = "The upload is larger than 64 MiB"),
+        (status = 415, description = "The format is neither given nor implied by the Content-Type"),
+        (status = 503, description = "The server is shutting down"),
+    )
+)]
+async fn import_books(
+    State(state): State<AppState>,
+    Query(query): Query<ImportBooksQuery>,
+    ctx: AuditContext,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, Json<BookImport>), StatusCode> {
+    let format = query
+        .format
+        .or_else(|| {
+            headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(catalog_import::format_for)
+        })
+        .ok_or(ImportError::UnsupportedFormat)?;
+    let records = catalog_import::parse(format, &body).map_err(ImportError::from)?;
+
+    let runs = state.jobs.runs().clone();
+    if runs.token().is_cancelled() {
+        return Err(ImportError::ShuttingDown.into());
+    }
+
+    let import = catalog_import::create(
+        &state.db,
+        format,
+        query.dry_run.unwrap_or(false),
+        ctx.actor_id,
+        records.len(),
+        state.clock.now(),
+    )
+    .await
+    .map_err(ImportError::from)?;
+
+    let (db, clock, id) = (state.db.clone(), state.clock.clone(), import.id);
+    let task = catalog_import::run(
+        db.clone(),
+        clock.clone(),
+        ctx,
+        import.clone(),
+        records,
+        runs.token().clone(),
+    );
+    request_id::spawn(async move {
+        if runs.run("book_import", task).await.is_none() {
+            let error = Some("Stopped by shutdown before it started".to_string());
+            if let Err(e) = catalog_import::finish(&db, id, clock.now(), error).await {
+                tracing::warn!("Failed to mark book import {id} as stopped: {e}");
+            }
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(import)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/books/imports",
+    tag = "admin",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "Book imports, newest first", body = Page<BookImport>),
+    )
+)]
+async fn list_book_imports(
+    State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<BookImport>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).min(500);
+    let offset = query.offset.unwrap_or(0);
+
+    let total_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM book_imports")
+        .fetch_one(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let imports = sqlx::query_as::<_, BookImport>(
+        "SELECT * FROM book_imports ORDER BY started_at DESC LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(imports, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/books/imports/{id}",
+    tag = "admin",
+    params(
+        ("id" = Uuid, Path, description = "Import id"),
+    ),
+    responses(
+        (status = 200, description = "The import and its progress", body = BookImport),
+        (status = 404, description = "No such import"),
+    )
+)]
+async fn get_book_import(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BookImport>, StatusCode> {
+    sqlx::query_as::<_, BookImport>("SELECT * FROM book_imports WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn push_import_row_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    id: Uuid,
+    query: &'a BookImportRowQuery,
+) {
+    builder.push(" WHERE import_id = ").push_bind(id);
+
+    if let Some(action) = query.action {
+        builder.push(" AND action = ").push_bind(action);
+    }
+}
+
+/// The per-row report of an import, in upload order. Rows appear as they are processed.
+#[utoipa::path(
+    get,
+    path = "/books/imports/{id}/rows",
+    tag = "admin",
+    params(
+        ("id" = Uuid, Path, description = "Import id"),
+        BookImportRowQuery,
+    ),
+    responses(
+        (status = 200, description = "Row outcomes", body = Page<BookImportRow>),
+        (status = 404, description = "No such import"),
+    )
+)]
+async fn list_book_import_rows(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<BookImportRowQuery>,
+) -> Result<Json<Page<BookImportRow>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).min(500);
+    let offset = query.offset.unwrap_or(0);
+
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM book_imports WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&state.db)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !exists {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM book_import_rows");
+    push_import_row_filters(&mut count_query, id, &query);
+
+    let total_count: i64 = count_query
+        .build_query_scalar()
+        .fetch_one(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut rows_query = QueryBuilder::<Postgres>::new("SELECT * FROM book_import_rows");
+    push_import_row_filters(&mut rows_query, id, &query);
+    rows_query
+        .push(" ORDER BY row_number LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+
+    let rows = rows_query
+        .build_query_as::<BookImportRow>()
+        .fetch_all(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(rows, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/deleted",
+    tag = "admin",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "Soft-deleted users", body = Page<User>),
+    )
+)]
+async fn list_deleted_users(
+    State(state): State<AppState>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<User>>, StatusCode> {
+    let limit = query.limit.unwrap_or(20).min(100);
+    let offset = query.offset.unwrap_or(0);
+
+    let total_count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE deleted_at IS NOT NULL")
+            .fetch_one(&state.db)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let users = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC LIMIT $1 OFFSET $2",
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&state.db)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(Page::new(users, total_count, limit, offset)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/{id}/restore",
+    tag = "admin",
+    params(
+        ("id" = Uuid, Path, description = "User id"),
+    ),
+    responses(
+        (status = 200, description = "The restored user", body = User),
+        (status = 404, description = "No such deleted user"),
+        (status = 409, description = "A live user has the email"),
+    )
+)]
+async fn restore_user(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Path(id): Path<Uuid>,
+) -> Result<Json<User>, StatusCode> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let deleted_user = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE id = $1 AND deleted_at IS NOT NULL FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    // A live account may have registered the same email since this one was deleted.
+    let user = sqlx::query_as::<_, User>(
+        "UPDATE users SET deleted_at = NULL, updated_at = $2 WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .bind(state.clock.now())
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    audit::record(
+        &mut *tx,
+        &ctx,
+        AuditEvent::new("user.restore", "user", user.id)
+            .before(&deleted_user)
+            .after(&user),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(user))
+}
+
+#[utoipa::path(
+    get,
+    path = "/inventory/discrepancies",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Books whose counters disagree with their copies", body = Vec<InventoryDiscrepancy>),
+    )
+)]
+async fn list_inventory_discrepancies(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<InventoryDiscrepancy>>, StatusCode> {
+    let discrepancies = inventory::find_discrepancies(&state.db)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(discrepancies))
+}
+
+#[utoipa::path(
+    post,
+    path = "/inventory/repair",
+    tag = "admin",
+    request_body = InventoryRepairRequest,
+    responses(
+        (status = 200, description = "The repaired books", body = Vec<Book>),
+        (status = 404, description = "No such book"),
+    )
+)]
+async fn repair_inventory(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Json(req): Json<InventoryRepairRequest>,
+) -> Result<Json<Vec<Book>>, StatusCode> {
+    let book_ids = match req.book_id {
+        Some(book_id) => vec![book_id],
+        None => inventory::find_discrepancies(&state.db)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .into_iter()
+            .map(|d| d.book_id)
+            .collect(),
+    };
+
+    // Every book is repaired in one transaction, so an error leaves none of them changed.
+    // Locking them in id order keeps concurrent repairs from deadlocking.
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let existing_books =
+        sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = ANY($1) ORDER BY id FOR UPDATE")
+            .bind(&book_ids)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // A book with a discrepancy may have been purged since; one asked for by id must exist.
+    if req.book_id.is_some() && existing_books.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let mut repaired = Vec::with_capacity(existing_books.len());
+
+    for existing_book in existing_books {
+        let book = inventory::repair_book(&mut tx, existing_book.id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        audit::record(
+            &mut *tx,
+            &ctx,
+            AuditEvent::new("book.repair_inventory", "book", book.id)
+                .before(&existing_book)
+                .after(&book),
+        )
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        repaired.push(book);
+    }
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(repaired))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use chrono::Duration;
+    use serde_json::{json, Value};
+
+    use crate::testing::TestApp;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_admin_routes_require_an_admin() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+
+        let (status, _) = app.get("/api/v1/admin/jobs").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = app
+            .post("/api/admin/jobs/overdue_notifications/run", json!({}))
+            .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        app.auth.respond_with(StatusCode::UNAUTHORIZED);
+        app.sign_in(&fixtures.admin);
+        let (status, _) = app.get("/api/v1/admin/audit").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        app.auth.respond_with(StatusCode::OK);
+
+        app.sign_in(&fixtures.reader);
+        let (status, _) = app.get("/api/v1/admin/audit").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = app
+            .post("/api/v1/admin/jobs/overdue_notifications/run", json!({}))
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        app.sign_in(&fixtures.admin);
+        let (status, _) = app.get("/api/v1/admin/audit").await;
+        assert_eq!(status, StatusCode::OK);
+
+        app.sign_out();
+        let (status, _) = app.get("/api/v1/admin/audit").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_books_and_users_are_listed_and_restored() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.sign_in(&fixtures.admin);
+
+        let emma = format!("/api/books/{}", fixtures.emma.id);
+        let (status, _) = app.request(Method::DELETE, &emma, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.get(&emma).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, books) = app.get("/api/books").await;
+        assert_eq!(books["total"], 1);
+        let (_, deleted) = app.get("/api/admin/books/deleted").await;
+        assert_eq!(deleted["total"], 1);
+        assert_eq!(deleted["items"][0]["id"], json!(fixtures.emma.id));
+
+        // The ISBN is free again while the book is deleted, and restoring it then conflicts.
+        let book = json!({ "isbn": fixtures.emma.isbn, "title": "Emma", "author": "Austen" });
+        let (status, reissue) = app.post("/api/books", book.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.post("/api/books", book).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let restore = format!("/api/admin/books/{}/restore", fixtures.emma.id);
+        let (status, _) = app.post(&restore, json!({})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let reissue = format!("/api/books/{}", reissue["id"].as_str().unwrap());
+        app.request(Method::DELETE, &reissue, None).await;
+        let (status, restored) = app.post(&restore, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(restored["deleted_at"], Value::Null);
+        let (status, _) = app.get(&emma).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = app.post(&restore, json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let reader = format!("/api/users/{}", fixtures.reader.id);
+        let (status, _) = app.request(Method::DELETE, &reader, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, users) = app.get("/api/users").await;
+        assert_eq!(users["total"], 1);
+        let (_, deleted) = app.get("/api/admin/users/deleted").await;
+        assert_eq!(deleted["items"][0]["id"], json!(fixtures.reader.id));
+
+        let user = json!({ "email": fixtures.reader.email, "name": "Another Reed" });
+        let (status, _) = app.post("/api/users", user).await;
+        assert_eq!(status, StatusCode::OK);
+        let restore = format!("/api/admin/users/{}/restore", fixtures.reader.id);
+        let (status, _) = app.post(&restore, json!({})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_inventory_is_repaired_all_at_once() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.sign_in(&fixtures.admin);
+        sqlx::query("UPDATE books SET available_copies = 0")
+            .execute(app.pool())
+            .await
+            .unwrap();
+
+        let (status, discrepancies) = app.get("/api/admin/inventory/discrepancies").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(discrepancies.as_array().unwrap().len(), 2);
+
+        let (status, _) = app
+            .post(
+                "/api/admin/inventory/repair",
+                json!({ "book_id": Uuid::new_v4() }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, repaired) = app
+            .post(
+                "/api/admin/inventory/repair",
+                json!({ "book_id": fixtures.emma.id }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(repaired[0]["available_copies"], 1);
+
+        let (status, repaired) = app.post("/api/admin/inventory/repair", json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(repaired.as_array().unwrap().len(), 1);
+        assert_eq!(repaired[0]["id"], json!(fixtures.dune.id));
+        assert_eq!(repaired[0]["available_copies"], 2);
+
+        let (_, discrepancies) = app.get("/api/admin/inventory/discrepancies").await;
+        assert_eq!(discrepancies, json!([]));
+        let (_, audit) = app
+            .get("/api/admin/audit?action=book.repair_inventory")
+            .await;
+        assert_eq!(audit["total"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_can_be_listed_run_and_paused() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.post(
+            "/api/checkouts",
+            json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+        )
+        .await;
+        app.clock.advance(Duration::days(15));
+        app.sign_in(&fixtures.admin);
+
+        let (status, jobs) = app.get("/api/admin/jobs").await;
+        assert_eq!(status, StatusCode::OK);
+        let names: Vec<_> = jobs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|job| job["name"].as_str().unwrap())
+            .collect();
+        assert!(names.contains(&"overdue_notifications"));
+        assert!(names.contains(&"soft_delete_purge"));
+
+        let (status, run) = app
+            .post(
+                "/api/admin/jobs/overdue_notifications/run?dry_run=true",
+                json!({}),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(run["status"], "SUCCEEDED");
+        assert_eq!(run["source"], "MANUAL");
+        assert_eq!(run["dry_run"], true);
+        assert_eq!(run["items_processed"], 1);
+        assert!(app.resend.requests().is_empty());
+
+        let (_, status) = app
+            .post("/api/admin/jobs/overdue_notifications/pause", json!({}))
+            .await;
+        assert_eq!(status["paused"], true);
+        assert_eq!(status["next_run"], Value::Null);
+        assert_eq!(status["last_run"]["id"], run["id"]);
+
+        let (status, run) = app
+            .post("/api/admin/jobs/overdue_notifications/run", json!({}))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(run["items_processed"], 1);
+        assert_eq!(app.resend.requests().len(), 1);
+
+        let (_, status) = app
+            .post("/api/admin/jobs/overdue_notifications/resume", json!({}))
+            .await;
+        assert_eq!(status["paused"], false);
+
+        let (status, _) = app.post("/api/admin/jobs/nope/run", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Polls the import until it stops running.
+    async fn finished_import(app: &TestApp, id: &Value) -> Value {
+        for _ in 0..200 {
+            let (_, import) = app
+                .get(&format!(
+                    "/api/admin/books/imports/{}",
+                    id.as_str().unwrap()
+                ))
+                .await;
+            if import["status"] != "RUNNING" {
+                return import;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(25)).await;
+        }
+        panic!("the import didn't finish");
+    }
+
+    #[tokio::test]
+    async fn test_books_are_imported_from_csv() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.sign_in(&fixtures.admin);
+        let csv = "isbn,title,author,copies,barcode,shelf_location\n\
+            0-441-01359-7,Dune,Frank Herbert,3,,\n\
+            978-0-14-143951-8,Pride and Prejudice,Jane Austen,,B-1001,PR AUS\n\
+            9780141439518,,,,B-1002,PR AUS\n\
+            12345,Nothing,Nobody,,,\n\
+            9780141439587,Emma,Jane Austen,,,\n";
+
+        let (status, _) = app
+            .upload("/api/admin/books/import", "application/pdf", csv)
+            .await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let (status, _) = app
+            .upload("/api/admin/books/import", "text/csv", "isbn,titel\n1,2\n")
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // A dry run reports what would happen and changes nothing.
+        let (status, import) = app
+            .upload("/api/admin/books/import?dry_run=true", "text/csv", csv)
+            .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(import["total_rows"], 5);
+        let import = finished_import(&app, &import["id"]).await;
+        assert_eq!(import["status"], "SUCCEEDED");
+        assert_eq!(import["dry_run"], true);
+        assert_eq!(import["processed_rows"], 5);
+        assert_eq!(import["books_created"], 1);
+        assert_eq!(import["copies_added"], 3);
+
+        let (_, dune) = app.get(&format!("/api/books/{}", fixtures.dune.id)).await;
+        assert_eq!(dune["total_copies"], 2);
+        let (_, page) = app.get("/api/books?query=pride").await;
+        assert_eq!(page["total"], 0);
+
+        let (_, import) = app
+            .upload("/api/admin/books/import", "text/csv; charset=utf-8", csv)
+            .await;
+        let import = finished_import(&app, &import["id"]).await;
+        assert_eq!(import["status"], "SUCCEEDED");
+        assert_eq!(import["books_created"], 1);
+        assert_eq!(import["books_updated"], 1);
+        assert_eq!(import["books_unchanged"], 1);
+        assert_eq!(import["rows_failed"], 1);
+        assert_eq!(import["copies_added"], 3);
+
+        let (_, rows) = app
+            .get(&format!(
+                "/api/admin/books/imports/{}/rows",
+                import["id"].as_str().unwrap()
+            ))
+            .await;
+        let actions: Vec<_> = rows["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            actions,
+            ["UPDATED", "CREATED", "MERGED", "FAILED", "UNCHANGED"]
+        );
+        assert_eq!(rows["items"][0]["isbn"], "9780441013593");
+        assert_eq!(
+            rows["items"][2]["message"],
+            "Merged into row 2, which has the same ISBN"
+        );
+
+        let (_, dune) = app.get(&format!("/api/books/{}", fixtures.dune.id)).await;
+        assert_eq!(dune["total_copies"], 3);
+        let (_, page) = app.get("/api/books?query=pride").await;
+        assert_eq!(page["items"][0]["total_copies"], 2);
+        assert_eq!(page["items"][0]["isbn"], "9780141439518");
+
+        // Importing the same file again finds everything in place.
+        let (_, import) = app
+            .upload("/api/admin/books/import?format=CSV", "text/plain", csv)
+            .await;
+        let import = finished_import(&app, &import["id"]).await;
+        assert_eq!(import["books_unchanged"], 3);
+        assert_eq!(import["copies_added"], 0);
+
+        let (_, imports) = app.get("/api/admin/books/imports").await;
+        assert_eq!(imports["total"], 3);
+    }
+}
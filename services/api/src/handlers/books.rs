@@ -5,248 +5,442 @@ use axum::{
     routing::get,
     Router,
 };
-use serde_json::{json, Value};
-use sqlx::Row;
+use utoipa::OpenApi;
 use uuid::Uuid;
 
 use crate::{
-    models::{Book, BookSearchQuery, CreateBookRequest, UpdateBookRequest},
+    models::{
+        Book, BookCopy, BookExportQuery, BookSearchQuery, CreateBookRequest, CreateCopyRequest,
+        DeleteQuery, Message, Page, UpdateBookRequest,
+    },
+    services::{
+        audit::AuditContext,
+        export::{self, Export},
+    },
     AppState,
 };
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/", get(list_books).post(create_book))
+        .route("/export", get(export_books))
         .route("/:id", get(get_book).put(update_book).delete(delete_book))
         .route("/isbn/:isbn", get(get_book_by_isbn))
+        .route("/:id/copies", get(list_book_copies).post(add_book_copy))
 }
 
+/// The OpenAPI description of these routes.
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_books,
+    export_books,
+    get_book,
+    get_book_by_isbn,
+    create_book,
+    update_book,
+    delete_book,
+    list_book_copies,
+    add_book_copy,
+))]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "books",
+    params(BookSearchQuery),
+    responses(
+        (status = 200, description = "Matching books", body = Page<Book>),
+    )
+)]
 async fn list_books(
     State(state): State<AppState>,
     Query(query): Query<BookSearchQuery>,
-) -> Result<Json<Value>, StatusCode> {
+) -> Result<Json<Page<Book>>, StatusCode> {
     let limit = query.limit.unwrap_or(20).min(100);
     let offset = query.offset.unwrap_or(0);
 
-    let mut where_conditions = Vec::<String>::new();
-    let mut search_params = Vec::new();
-
-    if let Some(ref q) = query.query {
-        where_conditions.push("(title ILIKE $1 OR author ILIKE $1)".to_string());
-        search_params.push(format!("%{q}%"));
-    }
-
-    if let Some(ref isbn) = query.isbn {
-        let param_num = search_params.len() + 1;
-        where_conditions.push(format!("isbn = ${param_num}"));
-        search_params.push(isbn.clone());
-    }
-
-    if let Some(ref author) = query.author {
-        let param_num = search_params.len() + 1;
-        where_conditions.push(format!("author ILIKE ${param_num}"));
-        search_params.push(format!("%{author}%"));
-    }
-
-    if let Some(ref genre) = query.genre {
-        let param_num = search_params.len() + 1;
-        where_conditions.push(format!("genre = ${param_num}"));
-        search_params.push(genre.clone());
-    }
-
-    let where_clause = if where_conditions.is_empty() {
-        String::new()
-    } else {
-        format!(" WHERE {}", where_conditions.join(" AND "))
-    };
-
-    let count_sql = format!("SELECT COUNT(*) FROM books{where_clause}");
-    let mut count_query = sqlx::query(&count_sql);
-
-    for param in &search_params {
-        count_query = count_query.bind(param);
-    }
+    let total_count = state.books.count(&query).await?;
+    let books = state.books.list(&query, limit, offset).await?;
 
-    let total_count: i64 = count_query
-        .fetch_one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(0);
-
-    let books_sql = format!("SELECT * FROM books{where_clause} ORDER BY created_at DESC LIMIT {limit} OFFSET {offset}");
-
-    let mut books_query = sqlx::query_as::<_, Book>(&books_sql);
-
-    for param in &search_params {
-        books_query = books_query.bind(param);
-    }
-
-    let books = books_query
-        .fetch_all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let has_more = (offset + limit) < total_count;
+    Ok(Json(Page::new(books, total_count, limit, offset)))
+}
 
-    Ok(Json(json!({
-        "items": books,
-        "total": total_count,
-        "limit": limit,
-        "offset": offset,
-        "hasMore": has_more
-    })))
+/// Every book matching the filters of the list, streamed as a download. `limit` and
+/// `offset` apply only when given.
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "books",
+    params(BookSearchQuery, BookExportQuery),
+    responses(
+        (status = 200, description = "Matching books, newest first", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+            (Vec<u8> = "application/marc"),
+            (String = "application/marcxml+xml"),
+        )),
+    )
+)]
+async fn export_books(
+    State(state): State<AppState>,
+    Query(query): Query<BookSearchQuery>,
+    Query(options): Query<BookExportQuery>,
+) -> Export {
+    export::books(
+        state.db.clone(),
+        query,
+        options.format.unwrap_or_default(),
+        state.clock.now(),
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "books",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+    ),
+    responses(
+        (status = 200, description = "The book", body = Book),
+        (status = 404, description = "No such book"),
+    )
+)]
 async fn get_book(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<Book>, StatusCode> {
-    let book = sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = $1")
-        .bind(id)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    let book = state.books.find(id).await?.ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(Json(book))
 }
 
+#[utoipa::path(
+    get,
+    path = "/isbn/{isbn}",
+    tag = "books",
+    params(
+        ("isbn" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "The book", body = Book),
+        (status = 404, description = "No such book"),
+    )
+)]
 async fn get_book_by_isbn(
     State(state): State<AppState>,
     Path(isbn): Path<String>,
 ) -> Result<Json<Book>, StatusCode> {
-    let book = sqlx::query_as::<_, Book>("SELECT * FROM books WHERE isbn = $1")
-        .bind(isbn)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    let book = state
+        .books
+        .find_by_isbn(&isbn)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(Json(book))
 }
 
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "books",
+    request_body = CreateBookRequest,
+    responses(
+        (status = 200, description = "The new book", body = Book),
+        (status = 400, description = "Negative `total_copies`"),
+        (status = 409, description = "A book with this ISBN exists"),
+    )
+)]
 async fn create_book(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Json(req): Json<CreateBookRequest>,
 ) -> Result<Json<Book>, StatusCode> {
-    let total_copies = req.total_copies.unwrap_or(1);
-    let available_copies = req.available_copies.unwrap_or(total_copies);
-
-    let book = sqlx::query_as::<_, Book>(
-        r#"
-        INSERT INTO books (id, isbn, title, author, publisher, published_year, genre, description, cover_url, total_copies, available_copies)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
-        RETURNING *
-        "#
-    )
-    .bind(Uuid::new_v4())
-    .bind(req.isbn)
-    .bind(req.title)
-    .bind(req.author)
-    .bind(req.publisher)
-    .bind(req.published_year)
-    .bind(req.genre)
-    .bind(req.description)
-    .bind(req.cover_url)
-    .bind(total_copies)
-    .bind(available_copies)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => StatusCode::CONFLICT,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
+    if req.total_copies.is_some_and(|n| n < 0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let book = state.books.create(req, &ctx).await?;
 
     Ok(Json(book))
 }
 
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    tag = "books",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+    ),
+    request_body = UpdateBookRequest,
+    responses(
+        (status = 200, description = "The updated book", body = Book),
+        (status = 404, description = "No such book"),
+    )
+)]
 async fn update_book(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateBookRequest>,
 ) -> Result<Json<Book>, StatusCode> {
-    let _existing_book = sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = $1")
-        .bind(id)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
-
-    let book = sqlx::query_as::<_, Book>(
-        r#"
-        UPDATE books 
-        SET title = COALESCE($2, title),
-            author = COALESCE($3, author),
-            publisher = COALESCE($4, publisher),
-            published_year = COALESCE($5, published_year),
-            genre = COALESCE($6, genre),
-            description = COALESCE($7, description),
-            cover_url = COALESCE($8, cover_url),
-            total_copies = COALESCE($9, total_copies),
-            available_copies = COALESCE($10, available_copies),
-            updated_at = NOW()
-        WHERE id = $1
-        RETURNING *
-        "#,
-    )
-    .bind(id)
-    .bind(req.title)
-    .bind(req.author)
-    .bind(req.publisher)
-    .bind(req.published_year)
-    .bind(req.genre)
-    .bind(req.description)
-    .bind(req.cover_url)
-    .bind(req.total_copies)
-    .bind(req.available_copies)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let book = state.books.update(id, req, &ctx).await?;
 
     Ok(Json(book))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tag = "books",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "Deleted", body = Message),
+        (status = 404, description = "No such book"),
+        (status = 409, description = "The book has copies on loan, and `force` is not set"),
+    )
+)]
 async fn delete_book(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Path(id): Path<Uuid>,
-) -> Result<Json<Value>, StatusCode> {
-    let result = sqlx::query("DELETE FROM books WHERE id = $1")
-        .bind(id)
-        .execute(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if result.rows_affected() == 0 {
-        return Err(StatusCode::NOT_FOUND);
-    }
+    Query(options): Query<DeleteQuery>,
+) -> Result<Json<Message>, StatusCode> {
+    let force = options.force.unwrap_or(false);
+    state
+        .books
+        .delete(id, force, state.clock.now(), &ctx)
+        .await?;
+
+    Ok(Json(Message::new("Book deleted successfully")))
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/copies",
+    tag = "copies",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+    ),
+    responses(
+        (status = 200, description = "The book's copies", body = Vec<BookCopy>),
+        (status = 404, description = "No such book"),
+    )
+)]
+async fn list_book_copies(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<BookCopy>>, StatusCode> {
+    let copies = state.books.list_copies(id).await?;
 
-    Ok(Json(json!({ "message": "Book deleted successfully" })))
+    Ok(Json(copies))
+}
+
+#[utoipa::path(
+    post,
+    path = "/{id}/copies",
+    tag = "copies",
+    params(
+        ("id" = Uuid, Path, description = "Book id"),
+    ),
+    request_body = CreateCopyRequest,
+    responses(
+        (status = 200, description = "The new copy", body = BookCopy),
+        (status = 404, description = "No such book"),
+        (status = 409, description = "The barcode is taken"),
+    )
+)]
+async fn add_book_copy(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Path(id): Path<Uuid>,
+    Json(req): Json<CreateCopyRequest>,
+) -> Result<Json<BookCopy>, StatusCode> {
+    let copy = state.books.add_copy(id, req, &ctx).await?;
+
+    Ok(Json(copy))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use crate::{app, repositories::memory::MemoryStore, testing::TestApp, AppState};
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (status, body)
+    }
+
+    fn post_json(uri: &str, body: Value) -> Request<Body> {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
 
     #[tokio::test]
     async fn test_list_books_empty_query() {
-        let query = BookSearchQuery {
-            query: None,
-            isbn: None,
-            author: None,
-            genre: None,
-            limit: None,
-            offset: None,
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+
+        let (status, body) = send(
+            &app,
+            Request::get("/api/books").body(Body::empty()).unwrap(),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({ "items": [], "total": 0, "limit": 20, "offset": 0, "hasMore": false })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_created_books_are_searchable_and_unique_by_isbn() {
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+        let dune = json!({
+            "isbn": "9780441013593",
+            "title": "Dune",
+            "author": "Frank Herbert",
+            "total_copies": 2
+        });
+
+        let (status, book) = send(&app, post_json("/api/books", dune.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(book["available_copies"], 2);
+
+        let (status, _) = send(&app, post_json("/api/books", dune)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let emma = json!({ "isbn": "9780141439587", "title": "Emma", "author": "Jane Austen" });
+        send(&app, post_json("/api/books", emma)).await;
+
+        let (_, page) = send(
+            &app,
+            Request::get("/api/books?query=herbert")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["title"], "Dune");
+
+        let uri = format!("/api/books/{}/copies", book["id"].as_str().unwrap());
+        let (status, copies) = send(&app, Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(copies.as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_books_are_hidden() {
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+        let (_, book) = send(
+            &app,
+            post_json(
+                "/api/books",
+                json!({ "isbn": "1", "title": "Gone", "author": "A" }),
+            ),
+        )
+        .await;
+        let uri = format!("/api/books/{}", book["id"].as_str().unwrap());
+
+        let (status, _) = send(&app, Request::delete(&uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(&app, Request::get(&uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_books_in_postgres() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
         };
+        let fixtures = app.seed().await;
 
-        // This would normally test with a real database connection
-        // For now, just verify the query structure is valid
-        assert!(query.limit.is_none());
-        assert!(query.offset.is_none());
+        let (status, page) = app.get("/api/books?author=herbert").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["id"], json!(fixtures.dune.id));
+
+        let (_, page) = app.get("/api/books?genre=Fiction&limit=1").await;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["hasMore"], true);
+
+        let (status, book) = app.get("/api/books/isbn/9780141439587").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(book["available_copies"], 1);
+
+        let (status, _) = app.get("/api/books/isbn/0000000000").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_books_are_exported_with_the_list_filters() {
+        use crate::{models::BookImportFormat, services::catalog_import};
+
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+
+        let (status, content_type, body) = app.download("/api/books/export?author=herbert").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/csv; charset=utf-8");
+        let csv = String::from_utf8(body).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id,isbn,title,author,publisher,"));
+        assert!(lines[1].contains(",9780441013593,Dune,Frank Herbert,"));
+
+        let (_, content_type, body) = app
+            .download("/api/books/export?format=jsonl&genre=Fiction")
+            .await;
+        assert_eq!(content_type, "application/x-ndjson");
+        let books: Vec<Value> = String::from_utf8(body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(books.len(), 2);
+        assert_eq!(books[0]["id"], json!(fixtures.emma.id));
+        let (_, _, body) = app
+            .download("/api/books/export?format=jsonl&limit=1&offset=1")
+            .await;
+        assert_eq!(String::from_utf8(body).unwrap().lines().count(), 1);
+
+        // MARC exports read back through the import, copies and all.
+        let (_, content_type, body) = app.download("/api/books/export?format=marc21").await;
+        assert_eq!(content_type, "application/marc");
+        let records = catalog_import::parse(BookImportFormat::Marc21, &body).unwrap();
+        assert_eq!(records.len(), 2);
+        let dune = records[1].as_ref().unwrap();
+        assert_eq!(dune.isbn.as_deref(), Some("9780441013593"));
+        assert_eq!(dune.title.as_deref(), Some("Dune"));
+        assert_eq!(dune.holdings.len(), 2);
+
+        let (_, _, body) = app
+            .download("/api/books/export?format=marcxml&query=emma")
+            .await;
+        let records = catalog_import::parse(BookImportFormat::Marcxml, &body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].as_ref().unwrap().author.as_deref(),
+            Some("Jane Austen")
+        );
     }
 }
@@ -5,16 +5,21 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use chrono::{Duration, Utc};
-use serde_json::{json, Value};
-use sqlx::Row;
+use utoipa::OpenApi;
 use uuid::Uuid;
 
 use crate::{
+    metrics::METRICS,
+    middleware::request_id,
     models::{
-        Checkout, CheckoutBook, CheckoutBookRequest, CheckoutSearchQuery, CheckoutStatus,
-        CheckoutUser, CheckoutWithDetails, CreateCheckoutRequest, RenewCheckoutRequest,
-        ReturnBookRequest,
+        CheckoutBookRequest, CheckoutSearchQuery, CheckoutWithDetails, CreateCheckoutRequest,
+        ExportQuery, Page, RenewCheckoutRequest, ReturnBookRequest,
+    },
+    repositories::CheckoutFilter,
+    services::{
+        audit::AuditContext,
+        circulation::{self, CopySelector, LoanOutcome, LoanRequest},
+        export::{self, Export},
     },
     AppState,
 };
@@ -30,168 +35,111 @@ pub fn router() -> Router<AppState> {
         .route("/user/:user_id", get(get_user_checkouts))
 }
 
+/// Routes that hand out patron data in bulk, which only admins may use.
+pub fn admin_router() -> Router<AppState> {
+    Router::new().route("/export", get(export_checkouts))
+}
+
+/// The OpenAPI description of these routes.
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_checkouts,
+    export_checkouts,
+    get_checkout,
+    get_user_checkouts,
+    create_checkout,
+    checkout_book,
+    return_book,
+    renew_checkout,
+    get_overdue_checkouts,
+))]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "checkouts",
+    params(CheckoutSearchQuery),
+    responses(
+        (status = 200, description = "Matching checkouts", body = Page<CheckoutWithDetails>),
+    )
+)]
 async fn list_checkouts(
     State(state): State<AppState>,
     Query(query): Query<CheckoutSearchQuery>,
-) -> Result<Json<Value>, StatusCode> {
+) -> Result<Json<Page<CheckoutWithDetails>>, StatusCode> {
     let limit = query.limit.unwrap_or(20).min(100);
     let offset = query.offset.unwrap_or(0);
 
-    let mut conditions = Vec::new();
-    let mut where_clause = String::new();
-
-    if let Some(user_id) = query.user_id {
-        conditions.push(format!("c.user_id = '{user_id}'"));
-    }
-    if let Some(book_id) = query.book_id {
-        conditions.push(format!("c.book_id = '{book_id}'"));
-    }
-    if let Some(ref status) = query.status {
-        conditions.push(format!("c.status = '{status:?}'").to_uppercase());
-    }
-    if let Some(true) = query.overdue {
-        conditions.push("c.due_date < NOW() AND c.status = 'ACTIVE'".to_string());
-    }
-
-    if !conditions.is_empty() {
-        where_clause = format!(" WHERE {}", conditions.join(" AND "));
-    }
-
-    let count_sql = format!("SELECT COUNT(*) FROM checkouts c{where_clause}");
-
-    let total_count: i64 = sqlx::query(&count_sql)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(0);
-
-    let checkouts_sql = format!(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        {where_clause}
-        ORDER BY c.created_at DESC
-        LIMIT {limit} OFFSET {offset}
-        "#
-    );
-
-    let rows = sqlx::query(&checkouts_sql)
-        .fetch_all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut checkouts = Vec::new();
-    for row in rows {
-        let checkout = Checkout {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            book_id: row.get("book_id"),
-            status: row.get("status"),
-            checked_out_at: row.get("checked_out_at"),
-            due_date: row.get("due_date"),
-            returned_at: row.get("returned_at"),
-            renewal_count: row.get("renewal_count"),
-            max_renewals: row.get("max_renewals"),
-            overdue_email_sent: row.get("overdue_email_sent"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
-        };
+    let filter = circulation::filter_for(&query, state.clock.now());
+    let (checkouts, total_count) =
+        circulation::list(state.checkouts.as_ref(), &filter, limit, offset).await?;
 
-        let user = CheckoutUser {
-            id: row.get("user_id"),
-            name: row.get("user_name"),
-            email: row.get("user_email"),
-        };
-
-        let book = CheckoutBook {
-            id: row.get("book_id"),
-            title: row.get("book_title"),
-            author: row.get("book_author"),
-            isbn: row.get("book_isbn"),
-        };
-
-        checkouts.push(CheckoutWithDetails {
-            checkout,
-            user,
-            book,
-        });
-    }
-
-    let has_more = (offset + limit) < total_count;
+    Ok(Json(Page::new(checkouts, total_count, limit, offset)))
+}
 
-    Ok(Json(json!({
-        "items": checkouts,
-        "total": total_count,
-        "limit": limit,
-        "offset": offset,
-        "hasMore": has_more
-    })))
+/// Every checkout matching the filters of the list, streamed as a download for an admin.
+/// Borrowers' names and email addresses are masked unless `redact` says otherwise. `limit` and
+/// `offset` apply only when given.
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "checkouts",
+    params(CheckoutSearchQuery, ExportQuery),
+    responses(
+        (status = 200, description = "Matching checkouts, newest first", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+    )
+)]
+async fn export_checkouts(
+    State(state): State<AppState>,
+    Query(query): Query<CheckoutSearchQuery>,
+    Query(options): Query<ExportQuery>,
+) -> Export {
+    export::checkouts(
+        state.db.clone(),
+        query,
+        options.format.unwrap_or_default(),
+        options.redact.unwrap_or_default(),
+        state.clock.now(),
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "checkouts",
+    params(
+        ("id" = Uuid, Path, description = "Checkout id"),
+    ),
+    responses(
+        (status = 200, description = "The checkout", body = CheckoutWithDetails),
+        (status = 404, description = "No such checkout"),
+    )
+)]
 async fn get_checkout(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<CheckoutWithDetails>, StatusCode> {
-    let row = sqlx::query(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        WHERE c.id = $1
-        "#,
-    )
-    .bind(id)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
-
-    let checkout = Checkout {
-        id: row.get("id"),
-        user_id: row.get("user_id"),
-        book_id: row.get("book_id"),
-        status: row.get("status"),
-        checked_out_at: row.get("checked_out_at"),
-        due_date: row.get("due_date"),
-        returned_at: row.get("returned_at"),
-        renewal_count: row.get("renewal_count"),
-        max_renewals: row.get("max_renewals"),
-        overdue_email_sent: row.get("overdue_email_sent"),
-        created_at: row.get("created_at"),
-        updated_at: row.get("updated_at"),
-    };
-
-    let user = CheckoutUser {
-        id: row.get("user_id"),
-        name: row.get("user_name"),
-        email: row.get("user_email"),
-    };
+    let checkout = circulation::get(state.checkouts.as_ref(), id).await?;
 
-    let book = CheckoutBook {
-        id: row.get("book_id"),
-        title: row.get("book_title"),
-        author: row.get("book_author"),
-        isbn: row.get("book_isbn"),
-    };
-
-    Ok(Json(CheckoutWithDetails {
-        checkout,
-        user,
-        book,
-    }))
+    Ok(Json(checkout))
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/{user_id}",
+    tag = "checkouts",
+    params(
+        ("user_id" = Uuid, Path),
+        CheckoutSearchQuery,
+    ),
+    responses(
+        (status = 200, description = "The user's checkouts", body = Vec<CheckoutWithDetails>),
+    )
+)]
 async fn get_user_checkouts(
     State(state): State<AppState>,
     Path(user_id): Path<Uuid>,
@@ -200,498 +148,688 @@ async fn get_user_checkouts(
     let limit = query.limit.unwrap_or(20).min(100);
     let offset = query.offset.unwrap_or(0);
 
-    let mut conditions = vec![format!("c.user_id = '{user_id}'")];
-
-    if let Some(ref status) = query.status {
-        conditions.push(format!("c.status = '{status:?}'").to_uppercase());
-    }
-    if let Some(true) = query.overdue {
-        conditions.push("c.due_date < NOW() AND c.status = 'ACTIVE'".to_string());
-    }
-
-    let where_clause = format!(" WHERE {}", conditions.join(" AND "));
-
-    let checkouts_sql = format!(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        {where_clause}
-        ORDER BY c.created_at DESC
-        LIMIT {limit} OFFSET {offset}
-        "#
-    );
-
-    let rows = sqlx::query(&checkouts_sql)
-        .fetch_all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut checkouts = Vec::new();
-    for row in rows {
-        let checkout = Checkout {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            book_id: row.get("book_id"),
-            status: row.get("status"),
-            checked_out_at: row.get("checked_out_at"),
-            due_date: row.get("due_date"),
-            returned_at: row.get("returned_at"),
-            renewal_count: row.get("renewal_count"),
-            max_renewals: row.get("max_renewals"),
-            overdue_email_sent: row.get("overdue_email_sent"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
-        };
-
-        let user = CheckoutUser {
-            id: row.get("user_id"),
-            name: row.get("user_name"),
-            email: row.get("user_email"),
-        };
-
-        let book = CheckoutBook {
-            id: row.get("book_id"),
-            title: row.get("book_title"),
-            author: row.get("book_author"),
-            isbn: row.get("book_isbn"),
-        };
-
-        checkouts.push(CheckoutWithDetails {
-            checkout,
-            user,
-            book,
-        });
-    }
+    let filter = CheckoutFilter {
+        user_id: Some(user_id),
+        book_id: None,
+        ..circulation::filter_for(&query, state.clock.now())
+    };
+    let (checkouts, _) =
+        circulation::list(state.checkouts.as_ref(), &filter, limit, offset).await?;
 
     Ok(Json(checkouts))
 }
 
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "checkouts",
+    request_body = CreateCheckoutRequest,
+    responses(
+        (status = 200, description = "The new checkout", body = CheckoutWithDetails),
+        (status = 404, description = "No such user, book or copy"),
+        (status = 409, description = "No copy is available, or the user is at their limit"),
+    )
+)]
 async fn create_checkout(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Json(req): Json<CreateCheckoutRequest>,
 ) -> Result<Json<CheckoutWithDetails>, StatusCode> {
-    let mut tx = state
-        .db
-        .begin()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let user = sqlx::query_as::<_, crate::models::User>(
-        "SELECT * FROM users WHERE id = $1 AND is_active = true",
-    )
-    .bind(req.user_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
-
-    let book = sqlx::query_as::<_, crate::models::Book>("SELECT * FROM books WHERE id = $1")
-        .bind(req.book_id)
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
-
-    if book.available_copies <= 0 {
-        return Err(StatusCode::CONFLICT);
-    }
-
-    let active_checkouts: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM checkouts WHERE user_id = $1 AND status = 'ACTIVE'",
+    let loan = circulation::checkout(
+        state.checkouts.as_ref(),
+        &ctx,
+        LoanRequest {
+            user_id: req.user_id,
+            copy: CopySelector::Book {
+                book_id: req.book_id,
+                copy_id: req.copy_id,
+            },
+            due_date: req.due_date,
+        },
+        state.clock.now(),
     )
-    .bind(req.user_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
 
-    if active_checkouts >= user.max_checkouts as i64 {
-        return Err(StatusCode::CONFLICT);
-    }
+    METRICS.circulation_event("checkout");
+    sync_checkout_creation(&state, &loan);
 
-    let due_date = req
-        .due_date
-        .unwrap_or_else(|| Utc::now() + Duration::days(14));
-
-    let checkout_id = Uuid::new_v4();
-    let checkout = sqlx::query_as::<_, Checkout>(
-        r#"
-        INSERT INTO checkouts (id, user_id, book_id, due_date)
-        VALUES ($1, $2, $3, $4)
-        RETURNING *
-        "#,
-    )
-    .bind(checkout_id)
-    .bind(req.user_id)
-    .bind(req.book_id)
-    .bind(due_date)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    sqlx::query("UPDATE books SET available_copies = available_copies - 1 WHERE id = $1")
-        .bind(req.book_id)
-        .execute(&mut *tx)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    tx.commit()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let checkout_user = CheckoutUser {
-        id: user.id,
-        name: user.name,
-        email: user.email,
-    };
-
-    let checkout_book = CheckoutBook {
-        id: book.id,
-        title: book.title,
-        author: book.author,
-        isbn: book.isbn,
-    };
-
-    Ok(Json(CheckoutWithDetails {
-        checkout,
-        user: checkout_user,
-        book: checkout_book,
-    }))
+    Ok(Json(loan.checkout))
 }
 
+#[utoipa::path(
+    post,
+    path = "/checkout",
+    tag = "checkouts",
+    request_body = CheckoutBookRequest,
+    responses(
+        (status = 200, description = "The new checkout", body = CheckoutWithDetails),
+        (status = 400, description = "Neither a barcode nor an ISBN"),
+        (status = 404, description = "No such user, book or copy"),
+        (status = 409, description = "No copy is available, or the user is at their limit"),
+    )
+)]
 async fn checkout_book(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Json(req): Json<CheckoutBookRequest>,
 ) -> Result<Json<CheckoutWithDetails>, StatusCode> {
-    let mut tx = state
-        .db
-        .begin()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let book = sqlx::query_as::<_, crate::models::Book>("SELECT * FROM books WHERE isbn = $1")
-        .bind(&req.isbn)
-        .fetch_one(&mut *tx)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
-
-    if book.available_copies <= 0 {
-        return Err(StatusCode::CONFLICT);
-    }
+    let copy = match (req.barcode, req.isbn) {
+        (Some(barcode), _) => CopySelector::Barcode(barcode),
+        (None, Some(isbn)) => CopySelector::Isbn(isbn),
+        (None, None) => return Err(StatusCode::BAD_REQUEST),
+    };
 
-    let user = sqlx::query_as::<_, crate::models::User>(
-        "SELECT * FROM users WHERE id = $1 AND is_active = true",
-    )
-    .bind(req.user_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
-
-    let active_checkouts: i64 = sqlx::query_scalar(
-        "SELECT COUNT(*) FROM checkouts WHERE user_id = $1 AND status = 'ACTIVE'",
+    let loan = circulation::checkout(
+        state.checkouts.as_ref(),
+        &ctx,
+        LoanRequest {
+            user_id: req.user_id,
+            copy,
+            due_date: None,
+        },
+        state.clock.now(),
     )
-    .bind(req.user_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
 
-    if active_checkouts >= user.max_checkouts as i64 {
-        return Err(StatusCode::CONFLICT);
-    }
+    METRICS.circulation_event("checkout");
+    sync_checkout_creation(&state, &loan);
 
-    let due_date = Utc::now() + Duration::days(14);
+    Ok(Json(loan.checkout))
+}
 
-    let checkout_id = Uuid::new_v4();
-    let checkout = sqlx::query_as::<_, Checkout>(
-        r#"
-        INSERT INTO checkouts (id, user_id, book_id, due_date)
-        VALUES ($1, $2, $3, $4)
-        RETURNING *
-        "#,
+#[utoipa::path(
+    post,
+    path = "/return",
+    tag = "checkouts",
+    request_body = ReturnBookRequest,
+    responses(
+        (status = 200, description = "The returned checkout", body = CheckoutWithDetails),
+        (status = 404, description = "No such active checkout"),
     )
-    .bind(checkout_id)
-    .bind(req.user_id)
-    .bind(book.id)
-    .bind(due_date)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    sqlx::query("UPDATE books SET available_copies = available_copies - 1 WHERE id = $1")
-        .bind(book.id)
-        .execute(&mut *tx)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+)]
+async fn return_book(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Json(req): Json<ReturnBookRequest>,
+) -> Result<Json<CheckoutWithDetails>, StatusCode> {
+    let loan = circulation::return_book(
+        state.checkouts.as_ref(),
+        &ctx,
+        req.checkout_id,
+        state.clock.now(),
+    )
+    .await?;
 
-    tx.commit()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    METRICS.circulation_event("return");
 
     // Sync with Supabase for real-time updates
     if let Some(ref supabase_sync) = state.supabase_sync {
-        tokio::spawn({
+        request_id::spawn({
             let sync = supabase_sync.clone();
-            let checkout = checkout.clone();
-            let book_id = book.id;
-            let available_copies = book.available_copies - 1;
-            let total_copies = book.total_copies;
+            let checkout = loan.checkout.checkout.clone();
             async move {
-                sync.sync_checkout_creation(&checkout).await;
-                sync.sync_book_update(book_id, available_copies, total_copies)
-                    .await;
+                sync.sync_checkout_update(&checkout).await;
             }
         });
     }
 
-    let checkout_user = CheckoutUser {
-        id: user.id,
-        name: user.name,
-        email: user.email,
-    };
-
-    let checkout_book = CheckoutBook {
-        id: book.id,
-        title: book.title,
-        author: book.author,
-        isbn: book.isbn,
-    };
-
-    Ok(Json(CheckoutWithDetails {
-        checkout,
-        user: checkout_user,
-        book: checkout_book,
-    }))
+    Ok(Json(loan.checkout))
 }
 
-async fn return_book(
+#[utoipa::path(
+    post,
+    path = "/renew",
+    tag = "checkouts",
+    request_body = RenewCheckoutRequest,
+    responses(
+        (status = 200, description = "The renewed checkout", body = CheckoutWithDetails),
+        (status = 404, description = "No such active checkout"),
+        (status = 409, description = "No renewals left"),
+    )
+)]
+async fn renew_checkout(
     State(state): State<AppState>,
-    Json(req): Json<ReturnBookRequest>,
+    ctx: AuditContext,
+    Json(req): Json<RenewCheckoutRequest>,
 ) -> Result<Json<CheckoutWithDetails>, StatusCode> {
-    let mut tx = state
-        .db
-        .begin()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let row = sqlx::query(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        WHERE c.id = $1 AND c.status = 'ACTIVE'
-        "#,
+    let checkout = circulation::renew(
+        state.checkouts.as_ref(),
+        &ctx,
+        req.checkout_id,
+        state.clock.now(),
     )
-    .bind(req.checkout_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
-
-    let book_id: Uuid = row.get("book_id");
-
-    let checkout = sqlx::query_as::<_, Checkout>(
-        "UPDATE checkouts SET status = 'RETURNED', returned_at = NOW(), updated_at = NOW() WHERE id = $1 RETURNING *"
+    .await?;
+    METRICS.circulation_event("renew");
+
+    Ok(Json(checkout))
+}
+
+#[utoipa::path(
+    get,
+    path = "/overdue",
+    tag = "checkouts",
+    responses(
+        (status = 200, description = "Checkouts past their due date", body = Vec<CheckoutWithDetails>),
     )
-    .bind(req.checkout_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    sqlx::query("UPDATE books SET available_copies = available_copies + 1 WHERE id = $1")
-        .bind(book_id)
-        .execute(&mut *tx)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+)]
+async fn get_overdue_checkouts(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<CheckoutWithDetails>>, StatusCode> {
+    let checkouts = circulation::overdue(state.checkouts.as_ref(), state.clock.now()).await?;
 
-    tx.commit()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(checkouts))
+}
 
+fn sync_checkout_creation(state: &AppState, loan: &LoanOutcome) {
     // Sync with Supabase for real-time updates
     if let Some(ref supabase_sync) = state.supabase_sync {
-        tokio::spawn({
+        request_id::spawn({
             let sync = supabase_sync.clone();
-            let checkout = checkout.clone();
+            let checkout = loan.checkout.checkout.clone();
+            let book_id = loan.book.id;
+            let available_copies = loan.book.available_copies;
+            let total_copies = loan.book.total_copies;
             async move {
-                sync.sync_checkout_update(&checkout).await;
+                sync.sync_checkout_creation(&checkout).await;
+                sync.sync_book_update(book_id, available_copies, total_copies)
+                    .await;
             }
         });
     }
+}
 
-    let user = CheckoutUser {
-        id: row.get("user_id"),
-        name: row.get("user_name"),
-        email: row.get("user_email"),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::CopyCondition,
+        services::inventory,
+        testing::{TestApp, TestDb},
     };
-
-    let book = CheckoutBook {
-        id: row.get("book_id"),
-        title: row.get("book_title"),
-        author: row.get("book_author"),
-        isbn: row.get("book_isbn"),
+    use axum::{
+        body::Body,
+        http::{Method, Request},
     };
+    use chrono::Duration;
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tokio_util::sync::CancellationToken;
+    use tower::ServiceExt;
+
+    async fn insert_user(db: &PgPool, max_checkouts: i32) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO users (id, email, name, max_checkouts) VALUES ($1, $2, 'Test', $3)",
+        )
+        .bind(id)
+        .bind(format!("{id}@example.com"))
+        .bind(max_checkouts)
+        .execute(db)
+        .await
+        .unwrap();
+        id
+    }
 
-    Ok(Json(CheckoutWithDetails {
-        checkout,
-        user,
-        book,
-    }))
-}
-
-async fn renew_checkout(
-    State(state): State<AppState>,
-    Json(req): Json<RenewCheckoutRequest>,
-) -> Result<Json<CheckoutWithDetails>, StatusCode> {
-    let mut tx = state
-        .db
-        .begin()
+    async fn insert_book(db: &PgPool, copies: usize) -> Uuid {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO books (id, isbn, title, author, total_copies, available_copies) VALUES ($1, $2, 'Test', 'Author', 0, 0)",
+        )
+        .bind(id)
+        .bind(id.simple().to_string())
+        .execute(db)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let row = sqlx::query(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        WHERE c.id = $1 AND c.status = 'ACTIVE'
-        "#,
-    )
-    .bind(req.checkout_id)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
-
-    let renewal_count: i32 = row.get("renewal_count");
-    let max_renewals: i32 = row.get("max_renewals");
-
-    if renewal_count >= max_renewals {
-        return Err(StatusCode::CONFLICT);
+        .unwrap();
+
+        let mut conn = db.acquire().await.unwrap();
+        for _ in 0..copies {
+            inventory::insert_copy(&mut *conn, id, None, None, CopyCondition::default())
+                .await
+                .unwrap();
+        }
+        inventory::refresh_availability(&mut *conn, id)
+            .await
+            .unwrap();
+        id
     }
 
-    let current_due_date: chrono::DateTime<Utc> = row.get("due_date");
-    let new_due_date = current_due_date + Duration::days(14);
+    async fn post_checkout(db: PgPool, user_id: Uuid, book_id: Uuid) -> StatusCode {
+        let app = router().with_state(AppState::new(db, None));
+        let body = json!({ "user_id": user_id, "book_id": book_id }).to_string();
+        let request = Request::post("/")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
 
-    let checkout = sqlx::query_as::<_, Checkout>(
-        r#"
-        UPDATE checkouts 
-        SET due_date = $2, renewal_count = renewal_count + 1, updated_at = NOW() 
-        WHERE id = $1 
-        RETURNING *
-        "#,
-    )
-    .bind(req.checkout_id)
-    .bind(new_due_date)
-    .fetch_one(&mut *tx)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        app.oneshot(request).await.unwrap().status()
+    }
 
-    tx.commit()
+    async fn assert_consistent(db: &PgPool, book_id: Uuid) {
+        let discrepancies = inventory::find_discrepancies(db).await.unwrap();
+        assert!(
+            discrepancies.iter().all(|d| d.book_id != book_id),
+            "inventory drifted for book {book_id}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_checkouts_never_oversell_copies() {
+        let Some(test_db) = TestDb::provision().await else {
+            return;
+        };
+        let db = test_db.pool.clone();
+
+        let book_id = insert_book(&db, 3).await;
+        let mut user_ids = Vec::new();
+        for _ in 0..12 {
+            user_ids.push(insert_user(&db, 5).await);
+        }
+
+        let tasks: Vec<_> = user_ids
+            .into_iter()
+            .map(|user_id| tokio::spawn(post_checkout(db.clone(), user_id, book_id)))
+            .collect();
+
+        let mut succeeded = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                StatusCode::OK => succeeded += 1,
+                StatusCode::CONFLICT => {}
+                other => panic!("unexpected status {other}"),
+            }
+        }
+
+        assert_eq!(succeeded, 3);
+
+        let available: i32 = sqlx::query_scalar("SELECT available_copies FROM books WHERE id = $1")
+            .bind(book_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(available, 0);
+        assert_consistent(&db, book_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_checkouts_respect_user_limit() {
+        let Some(test_db) = TestDb::provision().await else {
+            return;
+        };
+        let db = test_db.pool.clone();
+
+        let user_id = insert_user(&db, 2).await;
+        let mut book_ids = Vec::new();
+        for _ in 0..8 {
+            book_ids.push(insert_book(&db, 1).await);
+        }
+
+        let tasks: Vec<_> = book_ids
+            .iter()
+            .map(|&book_id| tokio::spawn(post_checkout(db.clone(), user_id, book_id)))
+            .collect();
+
+        let mut succeeded = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                StatusCode::OK => succeeded += 1,
+                StatusCode::CONFLICT => {}
+                other => panic!("unexpected status {other}"),
+            }
+        }
+
+        assert_eq!(succeeded, 2);
+
+        let active: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM checkouts WHERE user_id = $1 AND status = 'ACTIVE'",
+        )
+        .bind(user_id)
+        .fetch_one(&db)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .unwrap();
+        assert_eq!(active, 2);
+        for book_id in book_ids {
+            assert_consistent(&db, book_id).await;
+        }
+    }
 
-    let user = CheckoutUser {
-        id: row.get("user_id"),
-        name: row.get("user_name"),
-        email: row.get("user_email"),
-    };
+    #[tokio::test]
+    async fn test_retried_renew_with_idempotency_key_renews_once() {
+        let Some(test_db) = TestDb::provision().await else {
+            return;
+        };
+        let db = test_db.pool.clone();
+
+        let user_id = insert_user(&db, 5).await;
+        let book_id = insert_book(&db, 1).await;
+        assert_eq!(
+            post_checkout(db.clone(), user_id, book_id).await,
+            StatusCode::OK
+        );
+
+        let checkout_id: Uuid = sqlx::query_scalar("SELECT id FROM checkouts WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+
+        let state = AppState::new(db.clone(), None);
+        let app = router()
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                crate::middleware::idempotency::idempotency,
+            ))
+            .with_state(state);
+
+        let key = Uuid::new_v4().to_string();
+        let renew = |checkout_id: Uuid| {
+            Request::post("/renew")
+                .header("content-type", "application/json")
+                .header("idempotency-key", &key)
+                .body(Body::from(
+                    json!({ "checkout_id": checkout_id }).to_string(),
+                ))
+                .unwrap()
+        };
 
-    let book = CheckoutBook {
-        id: row.get("book_id"),
-        title: row.get("book_title"),
-        author: row.get("book_author"),
-        isbn: row.get("book_isbn"),
-    };
+        let first = app.clone().oneshot(renew(checkout_id)).await.unwrap();
+        let retry = app.clone().oneshot(renew(checkout_id)).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+
+        let renewal_count: i32 =
+            sqlx::query_scalar("SELECT renewal_count FROM checkouts WHERE id = $1")
+                .bind(checkout_id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(renewal_count, 1);
+
+        let reused = app.oneshot(renew(Uuid::new_v4())).await.unwrap();
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
 
-    Ok(Json(CheckoutWithDetails {
-        checkout,
-        user,
-        book,
-    }))
-}
+    #[tokio::test]
+    async fn test_checkout_through_router_without_database() {
+        let store = crate::repositories::memory::MemoryStore::new();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 1).await;
+        let app = crate::app(AppState::in_memory(&store));
+
+        let checkout = || {
+            let body = json!({ "user_id": user.id, "book_id": book.id }).to_string();
+            Request::post("/api/checkouts")
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
 
-async fn get_overdue_checkouts(
-    State(state): State<AppState>,
-) -> Result<Json<Vec<CheckoutWithDetails>>, StatusCode> {
-    let rows = sqlx::query(
-        r#"
-        SELECT 
-            c.*,
-            u.id as user_id, u.name as user_name, u.email as user_email,
-            b.id as book_id, b.title as book_title, b.author as book_author, b.isbn as book_isbn
-        FROM checkouts c
-        JOIN users u ON c.user_id = u.id
-        JOIN books b ON c.book_id = b.id
-        WHERE c.due_date < NOW() AND c.status = 'ACTIVE'
-        ORDER BY c.due_date ASC
-        "#,
-    )
-    .fetch_all(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let mut checkouts = Vec::new();
-    for row in rows {
-        let checkout = Checkout {
-            id: row.get("id"),
-            user_id: row.get("user_id"),
-            book_id: row.get("book_id"),
-            status: CheckoutStatus::Overdue,
-            checked_out_at: row.get("checked_out_at"),
-            due_date: row.get("due_date"),
-            returned_at: row.get("returned_at"),
-            renewal_count: row.get("renewal_count"),
-            max_renewals: row.get("max_renewals"),
-            overdue_email_sent: row.get("overdue_email_sent"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
+        let first = app.clone().oneshot(checkout()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = app.clone().oneshot(checkout()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+
+        let snapshot = store.snapshot().await;
+        assert_eq!(snapshot.books[&book.id].available_copies, 0);
+        assert_eq!(snapshot.checkouts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_is_synced_to_supabase() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
         };
+        let fixtures = app.seed().await;
+
+        let (status, checkout) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let requests = app.supabase.wait_for(2).await;
+        let created = requests
+            .iter()
+            .find(|r| r.method == Method::POST && r.path == "/rest/v1/checkouts")
+            .expect("checkout synced");
+        assert_eq!(created.body["id"], checkout["id"]);
+
+        let book_path = format!("/rest/v1/books?id=eq.{}", fixtures.dune.id);
+        let book_update = requests
+            .iter()
+            .find(|r| r.method == Method::PATCH && r.path == book_path)
+            .expect("availability synced");
+        assert_eq!(book_update.body["available_copies"], 1);
+    }
 
-        let user = CheckoutUser {
-            id: row.get("user_id"),
-            name: row.get("user_name"),
-            email: row.get("user_email"),
+    #[tokio::test]
+    async fn test_returning_a_lost_copy_keeps_it_lost() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
         };
+        let fixtures = app.seed().await;
+
+        let (_, checkout) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+            )
+            .await;
+        let copy_id: Uuid = checkout["copy_id"].as_str().unwrap().parse().unwrap();
+        sqlx::query("UPDATE book_copies SET status = 'LOST' WHERE id = $1")
+            .bind(copy_id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+
+        let (status, _) = app
+            .post(
+                "/api/checkouts/return",
+                json!({ "checkout_id": checkout["id"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let status: String =
+            sqlx::query_scalar("SELECT status::TEXT FROM book_copies WHERE id = $1")
+                .bind(copy_id)
+                .fetch_one(app.pool())
+                .await
+                .unwrap();
+        assert_eq!(status, "LOST");
+        let (_, book) = app.get(&format!("/api/books/{}", fixtures.dune.id)).await;
+        assert_eq!(book["total_copies"], 2);
+        assert_eq!(book["available_copies"], 1);
+    }
 
-        let book = CheckoutBook {
-            id: row.get("book_id"),
-            title: row.get("book_title"),
-            author: row.get("book_author"),
-            isbn: row.get("book_isbn"),
+    #[tokio::test]
+    async fn test_request_id_reaches_the_supabase_sync() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
         };
+        let fixtures = app.seed().await;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/checkouts")
+            .header("content-type", "application/json")
+            .header("x-request-id", "checkout-42")
+            .body(Body::from(
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }).to_string(),
+            ))
+            .unwrap();
+        let response = app.router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-request-id"], "checkout-42");
+
+        let requests = app.supabase.wait_for(2).await;
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            assert_eq!(request.headers["x-request-id"], "checkout-42");
+        }
+    }
 
-        checkouts.push(CheckoutWithDetails {
-            checkout,
-            user,
-            book,
-        });
+    #[tokio::test]
+    async fn test_overdue_listing_follows_the_clock() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        let (_, checkout) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+            )
+            .await;
+
+        let (_, overdue) = app.get("/api/checkouts/overdue").await;
+        assert_eq!(overdue, json!([]));
+
+        app.clock.advance(Duration::days(15));
+        let (_, overdue) = app.get("/api/checkouts/overdue").await;
+        assert_eq!(overdue[0]["id"], checkout["id"]);
+        assert_eq!(overdue[0]["status"], "OVERDUE");
+        let (_, page) = app.get("/api/checkouts?overdue=true").await;
+        assert_eq!(page["total"], 1);
+
+        let (status, _) = app
+            .post(
+                "/api/checkouts/renew",
+                json!({ "checkout_id": checkout["id"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, overdue) = app.get("/api/checkouts/overdue").await;
+        assert_eq!(overdue, json!([]));
     }
 
-    Ok(Json(checkouts))
+    #[tokio::test]
+    async fn test_overdue_job_emails_each_late_borrower_once() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        let (status, _) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.emma.id }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        app.clock.advance(Duration::days(13));
+        app.run_overdue_job().await;
+        assert!(app.resend.requests().is_empty());
+
+        app.clock.advance(Duration::days(2));
+        app.run_overdue_job().await;
+        app.clock.advance(Duration::days(1));
+        app.run_overdue_job().await;
+
+        let emails = app.resend.requests();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].path, "/emails");
+        assert_eq!(emails[0].body["to"], json!([fixtures.reader.email]));
+        assert_eq!(emails[0].body["subject"], "Overdue Book: Emma");
+    }
+
+    #[tokio::test]
+    async fn test_overdue_job_stops_for_shutdown_and_resumes_next_run() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        for book in [&fixtures.dune, &fixtures.emma] {
+            app.post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": book.id }),
+            )
+            .await;
+        }
+        app.clock.advance(Duration::days(15));
+
+        let stop = CancellationToken::new();
+        stop.cancel();
+        app.run_overdue_job_until(&stop).await;
+        assert!(app.resend.requests().is_empty());
+
+        app.run_overdue_job().await;
+        assert_eq!(app.resend.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_overdue_job_records_failed_deliveries() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.post(
+            "/api/checkouts",
+            json!({ "user_id": fixtures.reader.id, "book_id": fixtures.emma.id }),
+        )
+        .await;
+        app.resend.respond_with(StatusCode::SERVICE_UNAVAILABLE);
+
+        app.clock.advance(Duration::days(15));
+        app.run_overdue_job().await;
+
+        let (failures, notified): (i64, bool) = sqlx::query_as(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM overdue_email_failures),
+                (SELECT bool_or(overdue_email_sent) FROM checkouts)
+            "#,
+        )
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+        assert_eq!(failures, 1);
+        assert!(!notified);
+    }
+
+    #[tokio::test]
+    async fn test_checkouts_are_exported_with_the_list_filters() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        let (_, checkout) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+            )
+            .await;
+
+        let (status, _, _) = app.download("/api/v1/checkouts/export?redact=none").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        app.sign_in(&fixtures.reader);
+        let (status, _, _) = app.download("/api/v1/checkouts/export?redact=none").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        app.sign_in(&fixtures.admin);
+
+        let (status, _, body) = app
+            .download("/api/checkouts/export?format=jsonl&redact=none&status=ACTIVE")
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = String::from_utf8(body).unwrap();
+        let rows: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["id"], checkout["id"]);
+        assert_eq!(rows[0]["user_email"], "reader@example.com");
+        assert_eq!(rows[0]["book_isbn"], "9780441013593");
+
+        let (_, _, body) = app.download("/api/checkouts/export").await;
+        let csv = String::from_utf8(body).unwrap();
+        assert!(csv.starts_with("id,user_id,user_name,user_email,book_id,"));
+        assert!(csv.contains(",R.,r***@example.com,"));
+
+        let (_, _, body) = app.download("/api/checkouts/export?status=RETURNED").await;
+        assert!(body.is_empty());
+    }
 }
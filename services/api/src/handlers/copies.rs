@@ -0,0 +1,188 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+    routing::get,
+    Router,
+};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::{
+    models::{BookCopy, CopyStatus, UpdateCopyRequest},
+    services::{
+        audit::{self, AuditContext, AuditEvent},
+        inventory,
+    },
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/:id", get(get_copy).put(update_copy))
+        .route("/barcode/:barcode", get(get_copy_by_barcode))
+}
+
+/// The OpenAPI description of these routes.
+#[derive(OpenApi)]
+#[openapi(paths(get_copy, get_copy_by_barcode, update_copy,))]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "copies",
+    params(
+        ("id" = Uuid, Path, description = "Copy id"),
+    ),
+    responses(
+        (status = 200, description = "The copy", body = BookCopy),
+        (status = 404, description = "No such copy"),
+    )
+)]
+async fn get_copy(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BookCopy>, StatusCode> {
+    let copy = sqlx::query_as::<_, BookCopy>("SELECT * FROM book_copies WHERE id = $1")
+        .bind(id)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(Json(copy))
+}
+
+#[utoipa::path(
+    get,
+    path = "/barcode/{barcode}",
+    tag = "copies",
+    params(
+        ("barcode" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "The copy", body = BookCopy),
+        (status = 404, description = "No such copy"),
+    )
+)]
+async fn get_copy_by_barcode(
+    State(state): State<AppState>,
+    Path(barcode): Path<String>,
+) -> Result<Json<BookCopy>, StatusCode> {
+    let copy = sqlx::query_as::<_, BookCopy>("SELECT * FROM book_copies WHERE barcode = $1")
+        .bind(barcode)
+        .fetch_one(&state.db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    Ok(Json(copy))
+}
+
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    tag = "copies",
+    params(
+        ("id" = Uuid, Path, description = "Copy id"),
+    ),
+    request_body = UpdateCopyRequest,
+    responses(
+        (status = 200, description = "The updated copy", body = BookCopy),
+        (status = 400, description = "Copies are put on loan by checking them out"),
+        (status = 404, description = "No such copy"),
+        (status = 409, description = "The copy is on loan"),
+    )
+)]
+async fn update_copy(
+    State(state): State<AppState>,
+    ctx: AuditContext,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateCopyRequest>,
+) -> Result<Json<BookCopy>, StatusCode> {
+    let mut tx = state
+        .db
+        .begin()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Take the book lock before the copy lock, the same order checkouts use.
+    let book_id: Uuid = sqlx::query_scalar("SELECT book_id FROM book_copies WHERE id = $1")
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })?;
+
+    sqlx::query("SELECT id FROM books WHERE id = $1 FOR UPDATE")
+        .bind(book_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let existing_copy =
+        sqlx::query_as::<_, BookCopy>("SELECT * FROM book_copies WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            })?;
+
+    // Loans move copies in and out of ON_LOAN; staff can't do it by hand.
+    if let Some(status) = req.status {
+        if status == CopyStatus::OnLoan {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if existing_copy.status == CopyStatus::OnLoan && status != existing_copy.status {
+            return Err(StatusCode::CONFLICT);
+        }
+    }
+
+    let copy = sqlx::query_as::<_, BookCopy>(
+        r#"
+        UPDATE book_copies
+        SET shelf_location = COALESCE($2, shelf_location),
+            condition = COALESCE($3, condition),
+            status = COALESCE($4, status),
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(req.shelf_location)
+    .bind(req.condition)
+    .bind(req.status)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    inventory::refresh_availability(&mut *tx, copy.book_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    audit::record(
+        &mut *tx,
+        &ctx,
+        AuditEvent::new("copy.update", "copy", copy.id)
+            .before(&existing_copy)
+            .after(&copy),
+    )
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    tx.commit()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(copy))
+}
@@ -0,0 +1,91 @@
+use axum::{extract::State, http::StatusCode, response::Json, routing::get, Router};
+use serde_json::{json, Value};
+
+use crate::{
+    services::health::{self, Readiness},
+    AppState,
+};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(live))
+        .route("/live", get(live))
+        .route("/ready", get(ready))
+}
+
+/// The process is up and serving requests. Dependencies aren't checked, so a database outage
+/// doesn't get the instance restarted.
+async fn live() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Whether this instance should receive traffic, with a report per component. 503 when the
+/// database, migrations or scheduler are down; a failing optional backend still returns 200
+/// with a `degraded` status.
+async fn ready(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = health::readiness(&state).await;
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TestApp;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness_reports_each_component() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+
+        let (status, report) = app.get("/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "ready");
+        assert_eq!(report["checks"]["database"]["status"], "up");
+        assert_eq!(report["checks"]["migrations"]["status"], "up");
+        assert_eq!(
+            report["checks"]["migrations"]["details"]["version"],
+            report["checks"]["migrations"]["details"]["expected"]
+        );
+        assert_eq!(report["checks"]["scheduler"]["status"], "disabled");
+        assert_eq!(report["checks"]["supabase"]["status"], "up");
+        assert_eq!(report["checks"]["email"]["status"], "up");
+
+        app.resend.respond_with(StatusCode::BAD_GATEWAY);
+        let (status, report) = app.get("/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "degraded");
+        assert_eq!(report["checks"]["email"]["status"], "degraded");
+        assert_eq!(report["checks"]["supabase"]["status"], "up");
+    }
+
+    #[tokio::test]
+    async fn test_pending_migrations_make_the_instance_unready() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+            .execute(app.pool())
+            .await
+            .unwrap();
+
+        let (status, report) = app.get("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "unavailable");
+        assert_eq!(report["checks"]["migrations"]["status"], "down");
+        assert_eq!(
+            report["checks"]["migrations"]["error"],
+            "migrations pending"
+        );
+
+        let (status, _) = app.get("/health/live").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -1,3 +1,76 @@
+pub mod admin;
 pub mod books;
 pub mod checkouts;
+pub mod copies;
+pub mod health;
 pub mod users;
+
+use axum::{Extension, Router};
+
+use crate::{middleware, openapi, AppState};
+
+/// A version of the `/api` contract, mounted at `/api/<version>`. Versions are served side
+/// by side: when a resource's contract changes, the new version nests a new router for it
+/// and keeps nesting the old routers for everything else. Handlers that differ only slightly
+/// can take `Extension<ApiVersion>` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    pub const ALL: [Self; 1] = [Self::V1];
+
+    /// The version the unversioned `/api/...` aliases serve.
+    pub const LEGACY: Self = Self::V1;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::V1 => "v1",
+        }
+    }
+
+    pub fn prefix(self) -> String {
+        format!("/api/{}", self.name())
+    }
+
+    /// The routes of this version, relative to its prefix.
+    pub fn router(self, state: &AppState) -> Router<AppState> {
+        let routes = match self {
+            Self::V1 => Router::new()
+                .nest("/books", books::router())
+                .nest("/copies", copies::router())
+                .nest(
+                    "/users",
+                    users::router().merge(admin_only(users::admin_router(), state)),
+                )
+                .nest(
+                    "/checkouts",
+                    checkouts::router()
+                        .route_layer(axum::middleware::from_fn_with_state(
+                            state.clone(),
+                            middleware::idempotency::idempotency,
+                        ))
+                        .merge(admin_only(checkouts::admin_router(), state)),
+                )
+                .nest("/admin", admin_only(admin::router(), state)),
+        };
+
+        // The OpenAPI document is JSON too, but describes the contract rather than following it.
+        routes
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::casing::casing,
+            ))
+            .merge(openapi::router(self))
+            .layer(Extension(self))
+    }
+}
+
+/// `router`'s routes, open only to active admins.
+fn admin_only(router: Router<AppState>, state: &AppState) -> Router<AppState> {
+    router.route_layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::auth::require_admin,
+    ))
+}
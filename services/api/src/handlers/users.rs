@@ -1,16 +1,26 @@
+use std::net::SocketAddr;
+
 use axum::{
-    extract::{Path, Query, State},
-    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::Json,
     routing::get,
     Router,
 };
-use serde_json::{json, Value};
-use sqlx::Row;
+use utoipa::OpenApi;
 use uuid::Uuid;
 
 use crate::{
-    models::{CreateUserRequest, UpdateUserRequest, User, UserRole, UserSearchQuery},
+    middleware::auth::{bearer_token, verify_token},
+    models::{
+        CreateUserRequest, DeleteQuery, ExportQuery, Message, Page, UpdateUserRequest, User,
+        UserRole, UserSearchQuery,
+    },
+    repositories::NewUser,
+    services::{
+        audit::AuditContext,
+        export::{self, Export},
+    },
     AppState,
 };
 
@@ -22,276 +32,352 @@ pub fn router() -> Router<AppState> {
         .route("/me", get(get_current_user))
 }
 
+/// Routes that hand out patron data in bulk, which only admins may use.
+pub fn admin_router() -> Router<AppState> {
+    Router::new().route("/export", get(export_users))
+}
+
+/// The OpenAPI description of these routes.
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_users,
+    export_users,
+    get_user,
+    get_user_by_email,
+    get_current_user,
+    create_user,
+    update_user,
+    delete_user,
+))]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "users",
+    params(UserSearchQuery),
+    responses(
+        (status = 200, description = "Matching users", body = Page<User>),
+    )
+)]
 async fn list_users(
     State(state): State<AppState>,
     Query(query): Query<UserSearchQuery>,
-) -> Result<Json<Value>, StatusCode> {
+) -> Result<Json<Page<User>>, StatusCode> {
     let limit = query.limit.unwrap_or(20).min(100);
     let offset = query.offset.unwrap_or(0);
 
-    let mut where_conditions = Vec::<String>::new();
-    let mut search_params = Vec::new();
-
-    if let Some(ref q) = query.query {
-        where_conditions.push("(name ILIKE $1 OR email ILIKE $1)".to_string());
-        search_params.push(format!("%{q}%"));
-    }
-    if let Some(ref role) = query.role {
-        let param_num = search_params.len() + 1;
-        where_conditions.push(format!("role = ${param_num}"));
-        search_params.push(format!("{role:?}").to_uppercase());
-    }
-    if let Some(is_active) = query.is_active {
-        let param_num = search_params.len() + 1;
-        where_conditions.push(format!("is_active = ${param_num}"));
-        search_params.push(is_active.to_string());
-    }
-
-    let where_clause = if where_conditions.is_empty() {
-        String::new()
-    } else {
-        format!(" WHERE {}", where_conditions.join(" AND "))
-    };
-
-    let count_sql = format!("SELECT COUNT(*) FROM users{where_clause}");
-    let mut count_query = sqlx::query(&count_sql);
+    let total_count = state.users.count(&query).await?;
+    let users = state.users.list(&query, limit, offset).await?;
 
-    for param in &search_params {
-        count_query = count_query.bind(param);
-    }
-
-    let total_count: i64 = count_query
-        .fetch_one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .get(0);
-
-    let users_sql = format!("SELECT * FROM users{where_clause} ORDER BY created_at DESC LIMIT {limit} OFFSET {offset}");
-
-    let mut users_query = sqlx::query_as::<_, User>(&users_sql);
-
-    for param in &search_params {
-        users_query = users_query.bind(param);
-    }
-
-    let users = users_query
-        .fetch_all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let has_more = (offset + limit) < total_count;
+    Ok(Json(Page::new(users, total_count, limit, offset)))
+}
 
-    Ok(Json(json!({
-        "items": users,
-        "total": total_count,
-        "limit": limit,
-        "offset": offset,
-        "hasMore": has_more
-    })))
+/// Every user matching the filters of the list, streamed as a download for an admin. Names
+/// and email addresses are masked unless `redact` says otherwise. `limit` and `offset` apply only
+/// when given.
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "users",
+    params(UserSearchQuery, ExportQuery),
+    responses(
+        (status = 200, description = "Matching users, newest first", content(
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+    )
+)]
+async fn export_users(
+    State(state): State<AppState>,
+    Query(query): Query<UserSearchQuery>,
+    Query(options): Query<ExportQuery>,
+) -> Export {
+    export::users(
+        state.db.clone(),
+        query,
+        options.format.unwrap_or_default(),
+        options.redact.unwrap_or_default(),
+        state.clock.now(),
+    )
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "users",
+    params(
+        ("id" = Uuid, Path, description = "User id"),
+    ),
+    responses(
+        (status = 200, description = "The user", body = User),
+        (status = 404, description = "No such user"),
+    )
+)]
 async fn get_user(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<User>, StatusCode> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-        .bind(id)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    let user = state.users.find(id).await?.ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(Json(user))
 }
 
+#[utoipa::path(
+    get,
+    path = "/email/{email}",
+    tag = "users",
+    params(
+        ("email" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "The user", body = User),
+        (status = 404, description = "No such user"),
+    )
+)]
 async fn get_user_by_email(
     State(state): State<AppState>,
     Path(email): Path<String>,
 ) -> Result<Json<User>, StatusCode> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
-        .bind(email)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
+    let user = state
+        .users
+        .find_by_email(&email)
+        .await?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(Json(user))
 }
 
+#[utoipa::path(
+    get,
+    path = "/me",
+    tag = "users",
+    responses(
+        (status = 200, description = "The signed-in user, created on first sign-in", body = User),
+        (status = 401, description = "Missing or invalid bearer token"),
+        (status = 403, description = "The account is deleted"),
+    )
+)]
 async fn get_current_user(
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
 ) -> Result<Json<User>, StatusCode> {
-    let auth_header = headers
-        .get(AUTHORIZATION)
-        .and_then(|header| header.to_str().ok());
-
-    let token = match auth_header {
-        Some(auth_value) if auth_value.starts_with("Bearer ") => &auth_value[7..],
-        _ => return Err(StatusCode::UNAUTHORIZED),
-    };
+    let token = bearer_token(&headers).ok_or(StatusCode::UNAUTHORIZED)?;
 
     // Validate token with auth service
-    let auth_service_url = "http://localhost:3001";
-
-    let client = reqwest::Client::new();
-    let response = client
-        .post(format!("{auth_service_url}/verify-token"))
-        .json(&serde_json::json!({ "token": token }))
-        .send()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if !response.status().is_success() {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
-
-    let verification_result: Value = response
-        .json()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if verification_result.get("valid").and_then(|v| v.as_bool()) != Some(true) {
-        return Err(StatusCode::UNAUTHORIZED);
-    }
+    let verified_user = verify_token(&state.config.auth.service_url, token).await?;
 
     // Extract user ID from verification result
-    let user_id_str = verification_result
-        .get("user")
-        .and_then(|u| u.get("id"))
+    let user_id_str = verified_user
+        .get("id")
         .and_then(|v| v.as_str())
         .ok_or(StatusCode::UNAUTHORIZED)?;
 
     let user_id = uuid::Uuid::parse_str(user_id_str).map_err(|_| StatusCode::BAD_REQUEST)?;
 
     // Get user from database
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-        .bind(user_id)
-        .fetch_optional(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user = state.users.find_including_deleted(user_id).await?;
 
     match user {
+        Some(user) if user.deleted_at.is_some() => Err(StatusCode::FORBIDDEN),
         Some(user) => Ok(Json(user)),
         None => {
             // User doesn't exist in our database yet, create from verification data
-            let email = verification_result
-                .get("user")
-                .and_then(|u| u.get("email"))
+            let email = verified_user
+                .get("email")
                 .and_then(|v| v.as_str())
                 .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
 
-            let name = verification_result
-                .get("user")
-                .and_then(|u| u.get("name"))
+            let name = verified_user
+                .get("name")
                 .and_then(|v| v.as_str())
                 .unwrap_or(email);
 
-            let new_user = sqlx::query_as::<_, User>(
-                r#"
-                INSERT INTO users (id, email, name, role, max_checkouts)
-                VALUES ($1, $2, $3, $4, $5)
-                RETURNING *
-                "#,
-            )
-            .bind(user_id)
-            .bind(email)
-            .bind(name)
-            .bind(UserRole::User) // Default to User role
-            .bind(5) // Default checkout limit
-            .fetch_one(&state.db)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let ctx = AuditContext::new(
+                Some(user_id),
+                &headers,
+                connect_info.map(|ConnectInfo(addr)| addr),
+                &state.trusted_proxies,
+            );
+
+            let new_user = NewUser {
+                id: user_id,
+                email: email.to_string(),
+                name: name.to_string(),
+                role: UserRole::User, // Default to User role
+                max_checkouts: 5,     // Default checkout limit
+            };
+            let new_user = state.users.create(new_user, "user.provision", &ctx).await?;
 
             Ok(Json(new_user))
         }
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "users",
+    request_body = CreateUserRequest,
+    responses(
+        (status = 200, description = "The new user", body = User),
+        (status = 409, description = "The email is taken"),
+    )
+)]
 async fn create_user(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Json(req): Json<CreateUserRequest>,
 ) -> Result<Json<User>, StatusCode> {
-    let role = req.role.unwrap_or(UserRole::User);
-    let max_checkouts = req.max_checkouts.unwrap_or(5);
-
-    let user = sqlx::query_as::<_, User>(
-        r#"
-        INSERT INTO users (id, email, name, role, max_checkouts)
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING *
-        "#,
-    )
-    .bind(Uuid::new_v4())
-    .bind(req.email)
-    .bind(req.name)
-    .bind(role)
-    .bind(max_checkouts)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|e| match e {
-        sqlx::Error::Database(db_err) if db_err.constraint().is_some() => StatusCode::CONFLICT,
-        _ => StatusCode::INTERNAL_SERVER_ERROR,
-    })?;
+    let user = NewUser {
+        id: Uuid::new_v4(),
+        email: req.email,
+        name: req.name,
+        role: req.role.unwrap_or(UserRole::User),
+        max_checkouts: req.max_checkouts.unwrap_or(5),
+    };
+    let user = state.users.create(user, "user.create", &ctx).await?;
 
     Ok(Json(user))
 }
 
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    tag = "users",
+    params(
+        ("id" = Uuid, Path, description = "User id"),
+    ),
+    request_body = UpdateUserRequest,
+    responses(
+        (status = 200, description = "The updated user", body = User),
+        (status = 404, description = "No such user"),
+    )
+)]
 async fn update_user(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateUserRequest>,
 ) -> Result<Json<User>, StatusCode> {
-    let _existing_user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-        .bind(id)
-        .fetch_one(&state.db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        })?;
-
-    let user = sqlx::query_as::<_, User>(
-        r#"
-        UPDATE users 
-        SET name = COALESCE($2, name),
-            role = COALESCE($3, role),
-            is_active = COALESCE($4, is_active),
-            max_checkouts = COALESCE($5, max_checkouts),
-            updated_at = NOW()
-        WHERE id = $1
-        RETURNING *
-        "#,
-    )
-    .bind(id)
-    .bind(req.name)
-    .bind(req.role)
-    .bind(req.is_active)
-    .bind(req.max_checkouts)
-    .fetch_one(&state.db)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let user = state.users.update(id, req, &ctx).await?;
 
     Ok(Json(user))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    tag = "users",
+    params(
+        ("id" = Uuid, Path, description = "User id"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "Deleted", body = Message),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "The user has books on loan, and `force` is not set"),
+    )
+)]
 async fn delete_user(
     State(state): State<AppState>,
+    ctx: AuditContext,
     Path(id): Path<Uuid>,
-) -> Result<Json<Value>, StatusCode> {
-    let result = sqlx::query("DELETE FROM users WHERE id = $1")
-        .bind(id)
-        .execute(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    if result.rows_affected() == 0 {
-        return Err(StatusCode::NOT_FOUND);
+    Query(options): Query<DeleteQuery>,
+) -> Result<Json<Message>, StatusCode> {
+    let force = options.force.unwrap_or(false);
+    state
+        .users
+        .delete(id, force, state.clock.now(), &ctx)
+        .await?;
+
+    Ok(Json(Message::new("User deleted successfully")))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::testing::TestApp;
+
+    #[tokio::test]
+    async fn test_filter_users_by_role_and_reject_duplicate_email() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+
+        let (status, page) = app.get("/api/users?role=ADMIN&is_active=true").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["id"], json!(fixtures.admin.id));
+
+        let (status, _) = app
+            .post(
+                "/api/users",
+                json!({ "email": fixtures.reader.email, "name": "Copycat" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
     }
 
-    Ok(Json(json!({ "message": "User deleted successfully" })))
+    #[tokio::test]
+    async fn test_user_exports_are_redacted() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.sign_in(&fixtures.admin);
+
+        let (status, _, body) = app.download("/api/users/export?role=ADMIN").await;
+        assert_eq!(status, StatusCode::OK);
+        let csv = String::from_utf8(body).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id,name,email,role,"));
+        assert!(lines[1].contains(",A.,a***@example.com,ADMIN,"));
+
+        let (_, _, body) = app
+            .download("/api/users/export?role=ADMIN&redact=none")
+            .await;
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains(",Ada,admin@example.com,ADMIN,"));
+
+        let (_, _, body) = app
+            .download("/api/users/export?format=jsonl&redact=omit")
+            .await;
+        let users: Vec<serde_json::Value> = String::from_utf8(body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(users.len(), 2);
+        assert!(users
+            .iter()
+            .all(|user| user.get("email").is_none() && user.get("name").is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_user_exports_require_an_admin() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+
+        let (status, _, _) = app.download("/api/v1/users/export?redact=none").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        app.sign_in(&fixtures.reader);
+        let (status, _, _) = app.download("/api/v1/users/export?redact=none").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = app.download("/api/users/export").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        app.sign_in(&fixtures.admin);
+        let (status, _, _) = app.download("/api/v1/users/export?redact=none").await;
+        assert_eq!(status, StatusCode::OK);
+    }
 }
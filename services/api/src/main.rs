@@ -1,114 +1,311 @@
-use axum::{
-    http::{
-        header::{AUTHORIZATION, CONTENT_TYPE},
-        Method, StatusCode,
-    },
-    response::Json,
-    routing::get,
-    Router,
-};
-use serde_json::{json, Value};
-use sqlx::PgPool;
-use tokio_cron_scheduler::{Job, JobScheduler};
-use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
+use axum::{routing::get, Router};
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, PgPool};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio_cron_scheduler::JobScheduler;
+use tokio_util::sync::CancellationToken;
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{info, warn, Level};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod config;
 mod handlers;
+mod metrics;
 mod middleware;
 mod models;
+mod openapi;
+mod repositories;
 mod services;
+mod shutdown;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+
+use config::{Cli, Config, LogFormat};
+use handlers::{health, ApiVersion};
+use middleware::rate_limit::{RateLimiter, TrustedProxies};
+use repositories::{
+    postgres::{PgBookRepository, PgCheckoutRepository, PgUserRepository},
+    BookRepository, CheckoutRepository, UserRepository,
+};
+use services::{
+    clock::{Clock, SystemClock},
+    jobs::{JobRegistry, JobRunner},
+    supabase_sync::SupabaseSync,
+};
+use shutdown::JobRuns;
 
-use handlers::{books, checkouts, users};
-use services::supabase_sync::SupabaseSync;
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
+    pub books: Arc<dyn BookRepository>,
+    pub users: Arc<dyn UserRepository>,
+    pub checkouts: Arc<dyn CheckoutRepository>,
     pub supabase_sync: Option<SupabaseSync>,
+    pub clock: Arc<dyn Clock>,
+    pub config: Arc<Config>,
+    pub jobs: Arc<JobRegistry>,
+    /// `None` when rate limiting is disabled.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// From `config`, for finding client addresses whether or not rate limiting is enabled.
+    pub trusted_proxies: Arc<TrustedProxies>,
 }
 
-async fn health_check() -> Result<Json<Value>, StatusCode> {
-    Ok(Json(json!({ "status": "ok" })))
+impl AppState {
+    pub fn new(db: PgPool, supabase_sync: Option<SupabaseSync>) -> Self {
+        Self {
+            books: Arc::new(PgBookRepository::new(db.clone())),
+            users: Arc::new(PgUserRepository::new(db.clone())),
+            checkouts: Arc::new(PgCheckoutRepository::new(db.clone())),
+            jobs: Arc::new(JobRegistry::empty(db.clone())),
+            db,
+            supabase_sync,
+            clock: Arc::new(SystemClock),
+            config: Arc::new(Config::default()),
+            rate_limiter: None,
+            trusted_proxies: trusted_proxies(&Config::default()),
+        }
+    }
+
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    pub fn with_config(self, config: Arc<Config>) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies(&config),
+            config,
+            ..self
+        }
+    }
+
+    pub fn with_jobs(self, jobs: Arc<JobRegistry>) -> Self {
+        Self { jobs, ..self }
+    }
+
+    pub fn with_rate_limiter(self, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            rate_limiter: Some(rate_limiter),
+            ..self
+        }
+    }
+
+    /// Books, users and checkouts backed by `store`. Handlers that still query `db`
+    /// directly will fail, since the pool never connects.
+    #[cfg(test)]
+    pub fn in_memory(store: &repositories::memory::MemoryStore) -> Self {
+        use repositories::memory::{
+            MemoryBookRepository, MemoryCheckoutRepository, MemoryUserRepository,
+        };
+
+        let db = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .expect("valid connection string");
+
+        Self {
+            jobs: Arc::new(JobRegistry::empty(db.clone())),
+            db,
+            books: Arc::new(MemoryBookRepository::new(store.clone())),
+            users: Arc::new(MemoryUserRepository::new(store.clone())),
+            checkouts: Arc::new(MemoryCheckoutRepository::new(store.clone())),
+            supabase_sync: None,
+            clock: Arc::new(SystemClock),
+            config: Arc::new(Config::default()),
+            rate_limiter: None,
+            trusted_proxies: trusted_proxies(&Config::default()),
+        }
+    }
 }
 
-async fn setup_scheduler(db: PgPool) -> Result<(), Box<dyn std::error::Error>> {
-    let sched = JobScheduler::new().await?;
+/// The proxies `config` trusts. Invalid entries stop [`Config::load`], so none are left here.
+fn trusted_proxies(config: &Config) -> Arc<TrustedProxies> {
+    Arc::new(TrustedProxies::from_config(&config.rate_limit.trusted_proxies).unwrap_or_default())
+}
 
-    let job = Job::new_async("0 0 0 * * *", move |_uuid, _l| {
-        let db = db.clone();
-        Box::pin(async move {
-            info!("Running overdue check job");
-            if let Err(e) = services::email::send_overdue_notifications(&db).await {
-                warn!("Failed to send overdue notifications: {}", e);
-            }
-        })
-    })?;
+/// Every API version under its prefix, plus the unversioned aliases of the legacy version.
+fn api_routes(state: &AppState) -> Router<AppState> {
+    let mut routes = Router::new();
+    for version in ApiVersion::ALL {
+        routes = routes.nest(&version.prefix(), version.router(state));
+    }
 
-    sched.add(job).await?;
-    sched.start().await?;
+    if state.config.api.legacy_routes {
+        let legacy = ApiVersion::LEGACY
+            .router(state)
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::deprecation::legacy_alias,
+            ));
+        routes = routes.nest("/api", legacy);
+    }
+    routes
+}
 
-    Ok(())
+/// All routes, without the CORS layer.
+pub fn app(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics::export))
+        .nest("/health", health::router())
+        .merge(api_routes(&state))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::rate_limit::rate_limit,
+        ))
+        .layer(axum::middleware::from_fn(
+            middleware::metrics::track_requests,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(middleware::request_id::make_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(axum::middleware::from_fn(
+            middleware::request_id::request_id,
+        ))
+        .with_state(state)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv::dotenv().ok();
 
+    let cli = Cli::parse(std::env::args().skip(1))?;
+    let config = Config::load(&cli)?;
+
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml());
+        return Ok(());
+    }
+
+    let tracer_provider = telemetry::tracer_provider(&config.telemetry)?;
+    let json_logs = config.log.format == LogFormat::Json;
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "library_api=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::EnvFilter::new(&config.log.filter))
+        .with((!json_logs).then(tracing_subscriber::fmt::layer))
+        .with(json_logs.then(|| {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_span_list(false)
+        }))
+        .with(tracer_provider.as_ref().map(telemetry::layer))
         .init();
 
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let api_port = std::env::var("API_PORT").unwrap_or_else(|_| "8081".to_string());
+    info!("Starting with the {} profile", config.profile);
+    if let Some(endpoint) = &config.telemetry.otlp_endpoint {
+        info!("Exporting traces to {}", endpoint);
+    }
 
-    let pool = sqlx::PgPool::connect(&database_url).await?;
+    let pool = PgPoolOptions::new()
+        .max_connections(config.database.max_connections)
+        .connect(&config.database.url)
+        .await?;
 
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    MIGRATOR.run(&pool).await?;
 
-    setup_scheduler(pool.clone()).await?;
+    let shutdown = CancellationToken::new();
+    let job_runs = JobRuns::new(shutdown.clone());
 
-    let supabase_sync = services::supabase_sync::get_supabase_sync();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let runner = JobRunner::new(pool.clone(), clock.clone(), job_runs.clone());
+    let jobs = Arc::new(JobRegistry::standard(pool.clone(), &config, runner)?);
+    let scheduler = if config.jobs.enabled {
+        let scheduler = JobScheduler::new().await?;
+        jobs.schedule(&scheduler).await?;
+        scheduler.start().await?;
+        Some(scheduler)
+    } else {
+        warn!("Scheduled jobs are disabled; they can still be run from the admin API");
+        None
+    };
+
+    let supabase_sync = SupabaseSync::from_config(&config.supabase);
     if supabase_sync.is_some() {
         info!("Supabase sync initialized successfully");
     } else {
         warn!("Supabase sync not configured - real-time updates will not be synced");
     }
 
-    let app_state = AppState {
-        db: pool,
-        supabase_sync,
-    };
+    let config = Arc::new(config);
+    let mut app_state = AppState::new(pool.clone(), supabase_sync)
+        .with_clock(clock)
+        .with_config(config.clone())
+        .with_jobs(jobs);
+    if config.rate_limit.enabled {
+        let rate_limiter = RateLimiter::from_config(&config.rate_limit, pool.clone())
+            .map_err(config::ConfigError)?;
+        app_state = app_state.with_rate_limiter(Arc::new(rate_limiter));
+    } else {
+        warn!("Rate limiting is disabled");
+    }
+
+    let cors =
+        middleware::cors::CorsPolicies::from_config(&config.cors).map_err(config::ConfigError)?;
+    let app = app(app_state).layer(axum::middleware::from_fn_with_state(
+        Arc::new(cors),
+        middleware::cors::cors,
+    ));
 
-    let cors = CorsLayer::new()
-        .allow_methods([
-            Method::GET,
-            Method::POST,
-            Method::PUT,
-            Method::DELETE,
-            Method::OPTIONS,
-        ])
-        .allow_headers([CONTENT_TYPE, AUTHORIZATION])
-        .allow_credentials(true)
-        .allow_origin(["http://localhost:3000".parse().unwrap()]);
-
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .nest("/api/books", books::router())
-        .nest("/api/users", users::router())
-        .nest("/api/checkouts", checkouts::router())
-        .layer(cors)
-        .with_state(app_state);
-
-    let bind_addr = format!("0.0.0.0:{api_port}");
+    let bind_addr = format!("{}:{}", config.server.host, config.server.port);
     let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
     info!("Server running on http://{}", bind_addr);
 
-    axum::serve(listener, app).await?;
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::signal().await;
+            info!("Shutting down: no longer accepting connections");
+            shutdown.cancel();
+        }
+    });
+
+    // Stop accepting on the signal, then give in-flight requests the timeout to finish.
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    tokio::select! {
+        result = server => result?,
+        () = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => warn!("Requests still running after {:?}; closing them", drain_timeout),
+    }
+
+    if let Some(mut scheduler) = scheduler {
+        scheduler.shutdown().await?;
+        let jobs_timeout = Duration::from_secs(config.jobs.shutdown_timeout_secs);
+        if job_runs.drain(jobs_timeout).await {
+            info!("Scheduled jobs stopped");
+        } else {
+            warn!(
+                "Scheduled jobs still running after {:?}; abandoning them",
+                jobs_timeout
+            );
+        }
+    }
+
+    if tokio::time::timeout(drain_timeout, pool.close())
+        .await
+        .is_err()
+    {
+        warn!(
+            "Database connections still in use after {:?}",
+            drain_timeout
+        );
+    }
+    if let Some(provider) = tracer_provider {
+        // Flushing blocks on the exporter's HTTP client.
+        match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to flush traces: {}", e),
+            Err(e) => warn!("Failed to flush traces: {}", e),
+        }
+    }
+    info!("Shutdown complete");
 
     Ok(())
 }
@@ -0,0 +1,191 @@
+//! Prometheus metrics, served in the text exposition format at `/metrics`.
+//!
+//! Counters and histograms are recorded where things happen: HTTP requests by
+//! [`crate::middleware::metrics::track_requests`], loans in the checkout handlers, overdue
+//! emails in [`crate::services::email`] and Supabase calls in
+//! [`crate::services::supabase_sync`]. Gauges that describe current state (pool usage,
+//! overdue loans) are read when the endpoint is scraped.
+
+use std::sync::LazyLock;
+
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tracing::warn;
+
+use crate::{repositories::CheckoutFilter, AppState};
+
+/// The process-wide metrics.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Labelled by method, route template and status code.
+    pub http_requests: IntCounterVec,
+    /// Labelled by method and route template.
+    pub http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+    /// Completed loans, returns and renewals, labelled `event`.
+    pub circulation_events: IntCounterVec,
+    overdue_checkouts: IntGauge,
+    /// Overdue notices, labelled `outcome`: `sent` or `failed`.
+    pub overdue_emails: IntCounterVec,
+    /// Labelled by Supabase table.
+    pub supabase_sync_duration: HistogramVec,
+    /// Labelled by Supabase table.
+    pub supabase_sync_failures: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::new(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time to produce an HTTP response",
+                ),
+                &["method", "route"],
+            )
+            .unwrap(),
+            db_pool_connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Database pool connections by state"),
+                &["state"],
+            )
+            .unwrap(),
+            db_pool_max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Maximum size of the database pool",
+            )
+            .unwrap(),
+            circulation_events: IntCounterVec::new(
+                Opts::new(
+                    "library_circulation_events_total",
+                    "Checkouts, returns and renewals",
+                ),
+                &["event"],
+            )
+            .unwrap(),
+            overdue_checkouts: IntGauge::new(
+                "library_overdue_checkouts",
+                "Active loans past their due date",
+            )
+            .unwrap(),
+            overdue_emails: IntCounterVec::new(
+                Opts::new(
+                    "library_overdue_emails_total",
+                    "Overdue notices sent or failed",
+                ),
+                &["outcome"],
+            )
+            .unwrap(),
+            supabase_sync_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "supabase_sync_duration_seconds",
+                    "Time taken by Supabase sync requests",
+                ),
+                &["table"],
+            )
+            .unwrap(),
+            supabase_sync_failures: IntCounterVec::new(
+                Opts::new(
+                    "supabase_sync_failures_total",
+                    "Failed Supabase sync requests",
+                ),
+                &["table"],
+            )
+            .unwrap(),
+        };
+
+        let registry = &metrics.registry;
+        registry
+            .register(Box::new(metrics.http_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.db_pool_max_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.circulation_events.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.overdue_checkouts.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.overdue_emails.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.supabase_sync_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.supabase_sync_failures.clone()))
+            .unwrap();
+
+        // Export the known series at zero so dashboards have them before the first event.
+        for event in ["checkout", "return", "renew"] {
+            metrics.circulation_events.with_label_values(&[event]);
+        }
+        for outcome in ["sent", "failed"] {
+            metrics.overdue_emails.with_label_values(&[outcome]);
+        }
+
+        metrics
+    }
+
+    /// Counts a completed `checkout`, `return` or `renew`.
+    pub fn circulation_event(&self, event: &str) {
+        self.circulation_events.with_label_values(&[event]).inc();
+    }
+
+    /// Refreshes the gauges from `state` and encodes every metric.
+    async fn render(&self, state: &AppState) -> Result<String, prometheus::Error> {
+        let pool = &state.db;
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(i64::from(pool.size()) - idle);
+        self.db_pool_max_connections
+            .set(i64::from(pool.options().get_max_connections()));
+
+        let overdue = CheckoutFilter {
+            overdue_at: Some(state.clock.now()),
+            ..Default::default()
+        };
+        // A failed count leaves the previous value rather than failing the scrape.
+        match state.checkouts.count(&overdue).await {
+            Ok(count) => self.overdue_checkouts.set(count),
+            Err(e) => warn!("Failed to count overdue checkouts for metrics: {:?}", e),
+        }
+
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+pub async fn export(State(state): State<AppState>) -> Response {
+    match METRICS.render(&state).await {
+        Ok(body) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => {
+            warn!("Failed to encode metrics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
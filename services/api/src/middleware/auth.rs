@@ -1 +1,105 @@
-// Authentication middleware - currently unused but reserved for future implementation
+use axum::{
+    extract::{Request, State},
+    http::{header::AUTHORIZATION, Extensions, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+use tracing::{field::display, Instrument, Span};
+use uuid::Uuid;
+
+use crate::{middleware::request_id, models::UserRole, telemetry, AppState};
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Validates a bearer token with the auth service at `auth_url` and returns the `user` object
+/// it reports.
+pub async fn verify_token(auth_url: &str, token: &str) -> Result<Value, StatusCode> {
+    let client = reqwest::Client::new();
+    let url = format!("{auth_url}/verify-token");
+    let response = async {
+        request_id::propagate(client.post(&url))
+            .json(&serde_json::json!({ "token": token }))
+            .send()
+            .await
+    }
+    .instrument(telemetry::client_span("auth", "POST", &url))
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if !response.status().is_success() {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let verification_result: Value = response
+        .json()
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if verification_result.get("valid").and_then(|v| v.as_bool()) != Some(true) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    verification_result
+        .get("user")
+        .cloned()
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+/// Resolves the caller's user id from the bearer token, or `None` for anonymous or
+/// unverifiable requests. The id is recorded on the request's span.
+pub async fn authenticated_user_id(auth_url: &str, headers: &HeaderMap) -> Option<Uuid> {
+    let token = bearer_token(headers)?;
+    let user = verify_token(auth_url, token).await.ok()?;
+    let id = user
+        .get("id")
+        .and_then(|v| v.as_str())
+        .and_then(|id| Uuid::parse_str(id).ok())?;
+
+    Span::current().record("user_id", display(id));
+    Some(id)
+}
+
+/// The caller's user id, once a middleware has verified the request's bearer token, so that
+/// later extractors don't ask the auth service again.
+#[derive(Debug, Clone, Copy)]
+pub struct Caller(pub Option<Uuid>);
+
+/// The caller already resolved for this request, or [`authenticated_user_id`].
+pub async fn caller_id(
+    auth_url: &str,
+    extensions: &Extensions,
+    headers: &HeaderMap,
+) -> Option<Uuid> {
+    match extensions.get::<Caller>() {
+        Some(Caller(id)) => *id,
+        None => authenticated_user_id(auth_url, headers).await,
+    }
+}
+
+/// Lets through only active admins: 401 without a verified bearer token, 403 for anyone
+/// else. The caller is stored on the request as a [`Caller`].
+pub async fn require_admin(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let id = caller_id(
+        &state.config.auth.service_url,
+        request.extensions(),
+        request.headers(),
+    )
+    .await
+    .ok_or(StatusCode::UNAUTHORIZED)?;
+    request.extensions_mut().insert(Caller(Some(id)));
+
+    match state.users.find(id).await? {
+        Some(user) if user.role == UserRole::Admin && user.is_active => Ok(next.run(request).await),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
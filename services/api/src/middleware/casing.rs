@@ -0,0 +1,440 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::{stream, StreamExt};
+use serde_json::{Map, Value};
+
+use crate::{config::Casing, AppState};
+
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+const NDJSON: &str = "application/x-ndjson";
+
+/// Applies the casing policy to JSON and JSON Lines bodies. A client that names a casing, with
+/// the `profile` of an `application/json` or `application/x-ndjson` media range in `Accept`
+/// as in `Accept: application/json; profile="camelCase"`, gets every response key in that
+/// casing; one that doesn't gets the v1 shape, keys as the models write them, unless
+/// `api.casing` is `camelCase`. Under `camelCase`, request keys are also converted to
+/// snake_case before the handler sees them.
+pub async fn casing(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let casing = requested(request.headers()).or(match state.config.api.casing {
+        Casing::CamelCase => Some(Casing::CamelCase),
+        Casing::SnakeCase => None,
+    });
+
+    let request = if casing == Some(Casing::CamelCase) && is_json(request.headers()) {
+        let (parts, body) = request.into_parts();
+        let body = match to_bytes(body, MAX_BODY_BYTES).await {
+            Ok(body) => body,
+            Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+        };
+        // A body that isn't JSON is left for the handler to reject.
+        let body = match serde_json::from_slice(&body) {
+            Ok(value) => Body::from(rename_keys(value, to_snake_case).to_string()),
+            Err(_) => Body::from(body),
+        };
+        let mut request = Request::from_parts(parts, body);
+        request.headers_mut().remove(CONTENT_LENGTH);
+        request
+    } else {
+        request
+    };
+
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+    let rename = match casing {
+        Some(Casing::SnakeCase) => to_snake_case,
+        Some(Casing::CamelCase) => to_camel_case,
+        None => return response,
+    };
+    if is_ndjson(response.headers()) {
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, rename_lines(body, rename));
+    }
+    if !is_json(response.headers()) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let body = match serde_json::from_slice(&body) {
+        Ok(value) => Body::from(rename_keys(value, rename).to_string()),
+        Err(_) => Body::from(body),
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, body)
+}
+
+/// The casing named by the first `application/json` or `application/x-ndjson` range in
+/// `Accept` with a profile we know. Unknown profiles are ignored rather than refused.
+fn requested(headers: &HeaderMap) -> Option<Casing> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next()?;
+            if !["application/json", NDJSON]
+                .iter()
+                .any(|known| media_type.eq_ignore_ascii_case(known))
+            {
+                return None;
+            }
+            params.find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if !name.trim().eq_ignore_ascii_case("profile") {
+                    return None;
+                }
+                match value.trim().trim_matches('"') {
+                    "camelCase" => Some(Casing::CamelCase),
+                    "snake_case" => Some(Casing::SnakeCase),
+                    _ => None,
+                }
+            })
+        })
+        .next()
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+fn is_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(NDJSON))
+}
+
+/// Renames the keys of each line of a JSON Lines body as it streams through, so that exports
+/// of any size are never held in memory whole. Lines that aren't JSON pass through as they
+/// are.
+fn rename_lines(body: Body, rename: fn(&str) -> String) -> Body {
+    let chunks = body.into_data_stream();
+    Body::from_stream(stream::unfold(
+        (chunks, Vec::new(), false),
+        move |(mut chunks, mut pending, done)| async move {
+            if done {
+                return None;
+            }
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    pending.extend_from_slice(&chunk);
+                    // Hold back a line that is still arriving.
+                    let complete = pending
+                        .iter()
+                        .rposition(|&b| b == b'\n')
+                        .map_or(0, |i| i + 1);
+                    let lines: Vec<u8> = pending.drain(..complete).collect();
+                    Some((
+                        Ok(rename_each_line(&lines, rename)),
+                        (chunks, pending, false),
+                    ))
+                }
+                Some(Err(e)) => Some((Err(e), (chunks, pending, true))),
+                None if pending.is_empty() => None,
+                None => Some((
+                    Ok(rename_each_line(&pending, rename)),
+                    (chunks, Vec::new(), true),
+                )),
+            }
+        },
+    ))
+}
+
+fn rename_each_line(lines: &[u8], rename: fn(&str) -> String) -> Bytes {
+    let mut renamed = Vec::with_capacity(lines.len());
+    for line in lines.split_inclusive(|&b| b == b'\n') {
+        let (json, newline) = match line.strip_suffix(b"\n") {
+            Some(json) => (json, &b"\n"[..]),
+            None => (line, &b""[..]),
+        };
+        match serde_json::from_slice(json) {
+            Ok(value) => {
+                renamed.extend_from_slice(rename_keys(value, rename).to_string().as_bytes());
+                renamed.extend_from_slice(newline);
+            }
+            Err(_) => renamed.extend_from_slice(line),
+        }
+    }
+    Bytes::from(renamed)
+}
+
+/// Renames the keys of every object in `value`, however deeply nested.
+fn rename_keys(value: Value, rename: fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (rename(&key), rename_keys(value, rename)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| rename_keys(item, rename))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// `checked_out_at` becomes `checkedOutAt`. Leading underscores are kept.
+fn to_camel_case(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+/// `checkedOutAt` becomes `checked_out_at`; keys already in snake_case are unchanged.
+fn to_snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_uppercase() {
+            if !snake.is_empty() {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header::CONTENT_TYPE, Request},
+        Router,
+    };
+    use serde_json::json;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, repositories::memory::MemoryStore};
+
+    const CAMEL: &str = "application/json; profile=\"camelCase\"";
+    const SNAKE: &str = "application/json; profile=snake_case";
+
+    /// Keeps the keys and replaces every value with its JSON type, so that a snapshot
+    /// records the contract rather than the data.
+    fn shape(value: &Value) -> Value {
+        match value {
+            Value::Object(object) => Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), shape(value)))
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(items.iter().map(shape).collect()),
+            Value::String(_) => json!("string"),
+            Value::Number(_) => json!("number"),
+            Value::Bool(_) => json!("boolean"),
+            Value::Null => json!("null"),
+        }
+    }
+
+    /// Compares `value`'s shape with `snapshots/<name>.json`, which is rewritten instead
+    /// when `UPDATE_SNAPSHOTS` is set.
+    fn assert_snapshot(name: &str, value: &Value) {
+        let path = format!("{}/snapshots/{name}.json", env!("CARGO_MANIFEST_DIR"));
+        let actual = format!("{}\n", serde_json::to_string_pretty(&shape(value)).unwrap());
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            expected == actual,
+            "{name} no longer matches {path}:\n{actual}\nrun `UPDATE_SNAPSHOTS=1 cargo test casing` if the change is intended"
+        );
+    }
+
+    async fn get(app: &Router, uri: &str, accept: Option<&str>) -> Value {
+        let mut request = Request::get(uri);
+        if let Some(accept) = accept {
+            request = request.header(ACCEPT, accept);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[VARY], "accept");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_key_conversion() {
+        assert_eq!(to_camel_case("checked_out_at"), "checkedOutAt");
+        assert_eq!(to_camel_case("has_more"), "hasMore");
+        assert_eq!(to_camel_case("_meta"), "_meta");
+        assert_eq!(to_snake_case("checkedOutAt"), "checked_out_at");
+        assert_eq!(to_snake_case("user_id"), "user_id");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/html, application/json;profile=camelCase"),
+        );
+        assert_eq!(requested(&headers), Some(Casing::CamelCase));
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/x-ndjson; profile=snake_case"),
+        );
+        assert_eq!(requested(&headers), Some(Casing::SnakeCase));
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json; profile=\"kebab\""),
+        );
+        assert_eq!(requested(&headers), None);
+    }
+
+    /// Locks the response contract of the main models and envelopes: the v1 shape clients
+    /// get without asking, and each casing they can ask for.
+    #[tokio::test]
+    async fn test_response_contract_snapshots() {
+        let store = MemoryStore::new();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 1).await;
+        let app = app(AppState::in_memory(&store));
+
+        // A camelCase client writes camelCase too.
+        let body = json!({ "userId": user.id, "bookId": book.id }).to_string();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::post("/api/v1/checkouts")
+                    .header(CONTENT_TYPE, "application/json")
+                    .header(ACCEPT, CAMEL)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (casing, accept) in [
+            ("v1", None),
+            ("snake_case", Some(SNAKE)),
+            ("camelCase", Some(CAMEL)),
+        ] {
+            let book = get(&app, &format!("/api/v1/books/{}", book.id), accept).await;
+            assert_snapshot(&format!("book.{casing}"), &book);
+            let user = get(&app, &format!("/api/v1/users/{}", user.id), accept).await;
+            assert_snapshot(&format!("user.{casing}"), &user);
+            let checkouts = get(&app, "/api/v1/checkouts", accept).await;
+            assert_snapshot(&format!("checkouts_page.{casing}"), &checkouts);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configured_casing_is_the_default() {
+        let mut state = AppState::in_memory(&MemoryStore::new());
+        let mut config = (*state.config).clone();
+        config.api.casing = Casing::CamelCase;
+        state = state.with_config(Arc::new(config));
+        let app = app(state);
+
+        let page = get(&app, "/api/v1/books", None).await;
+        assert_eq!(page["hasMore"], false);
+        let page = get(&app, "/api/v1/books", Some(SNAKE)).await;
+        assert_eq!(page["has_more"], false);
+    }
+
+    #[tokio::test]
+    async fn test_v1_shape_is_kept_without_a_profile() {
+        let store = MemoryStore::new();
+        store.insert_book("Dune", 1).await;
+        let app = app(AppState::in_memory(&store));
+
+        let page = get(&app, "/api/v1/books", None).await;
+        assert_eq!(page["hasMore"], false);
+        assert!(page["items"][0].get("available_copies").is_some());
+    }
+
+    /// Export lines arrive in chunks that needn't end where the lines do.
+    async fn json_lines() -> Response {
+        let chunks = [
+            "{\"user_id\":1,\"bo",
+            "ok\":{\"due_date\":2}}\n{\"user_id\":3}\n",
+            "not json\n{\"has_more\":true}",
+        ];
+        (
+            [(CONTENT_TYPE, NDJSON)],
+            Body::from_stream(futures_util::stream::iter(
+                chunks.map(Ok::<_, std::convert::Infallible>),
+            )),
+        )
+            .into_response()
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_are_renamed_line_by_line() {
+        let state = AppState::in_memory(&MemoryStore::new());
+        let app = Router::new()
+            .route("/", axum::routing::get(json_lines))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), casing))
+            .with_state(state);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/")
+                    .header(ACCEPT, "application/x-ndjson; profile=camelCase")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&body).unwrap().lines().collect();
+        assert_eq!(lines.len(), 4);
+        let first: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first, json!({ "userId": 1, "book": { "dueDate": 2 } }));
+        assert_eq!(lines[1], r#"{"userId":3}"#);
+        assert_eq!(lines[2], "not json");
+        assert_eq!(lines[3], r#"{"hasMore":true}"#);
+        assert!(!body.ends_with(b"\n"));
+
+        // Without a profile the lines are left as they were written.
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.starts_with(br#"{"user_id":1,"book""#));
+    }
+}
@@ -0,0 +1,425 @@
+//! CORS policies from configuration, chosen per request by path and method.
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Request, State},
+    http::{header::ACCESS_CONTROL_REQUEST_METHOD, HeaderName, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::config::CorsConfig;
+
+/// An entry in an `allowed_origins` list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OriginPattern {
+    Any,
+    Exact(String),
+    /// `https://*.example.com`: any subdomain, at any depth, with the same scheme and port,
+    /// but not `https://example.com` itself.
+    Subdomain {
+        scheme: String,
+        suffix: String,
+    },
+}
+
+impl FromStr for OriginPattern {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(Self::Any);
+        }
+
+        let s = s.to_ascii_lowercase();
+        let (scheme, host) = s
+            .split_once("://")
+            .filter(|(scheme, _)| matches!(*scheme, "http" | "https"))
+            .ok_or(())?;
+        if host.is_empty() || host.contains('/') {
+            return Err(());
+        }
+
+        match host.strip_prefix("*.") {
+            Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => Ok(Self::Subdomain {
+                scheme: scheme.to_string(),
+                suffix: format!(".{suffix}"),
+            }),
+            None if !host.contains('*') => Ok(Self::Exact(s)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl OriginPattern {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(allowed) => origin.eq_ignore_ascii_case(allowed),
+            Self::Subdomain { scheme, suffix } => {
+                let origin = origin.to_ascii_lowercase();
+                origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|host| host.strip_suffix(suffix.as_str()))
+                    .is_some_and(|subdomain| {
+                        !subdomain.is_empty()
+                            && !subdomain.starts_with('.')
+                            && subdomain
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                    })
+            }
+        }
+    }
+}
+
+/// A policy for part of the API. `methods` of `None` covers every method.
+struct RoutePolicy {
+    path: String,
+    methods: Option<Vec<Method>>,
+    layer: CorsLayer,
+}
+
+impl RoutePolicy {
+    fn covers(&self, path: &str, method: Option<&Method>) -> bool {
+        let under_path = path
+            .strip_prefix(self.path.trim_end_matches('/'))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        let for_method = match (&self.methods, method) {
+            (None, _) => true,
+            (Some(methods), Some(method)) => methods.contains(method),
+            (Some(_), None) => false,
+        };
+        under_path && for_method
+    }
+}
+
+/// The configured default policy and per-route overrides.
+pub struct CorsPolicies {
+    default: CorsLayer,
+    routes: Vec<RoutePolicy>,
+}
+
+impl CorsPolicies {
+    /// Builds the policies, or lists every problem with `config`.
+    pub fn from_config(config: &CorsConfig) -> Result<Self, Vec<String>> {
+        let mut problems = Vec::new();
+
+        let default = Settings {
+            name: "cors".to_string(),
+            origins: &config.allowed_origins,
+            methods_key: "allowed_methods",
+            methods: &config.allowed_methods,
+            headers: &config.allowed_headers,
+            credentials: config.allow_credentials,
+            max_age_secs: config.max_age_secs,
+        }
+        .layer(&mut problems);
+
+        let routes = config
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(i, route)| {
+                let name = format!("cors.routes[{i}]");
+                if !route.path.starts_with('/') {
+                    problems.push(format!(
+                        "{name}.path must start with `/`, got `{}`",
+                        route.path
+                    ));
+                }
+                // Invalid methods are reported when the layer is built.
+                let methods = parse_methods("", &route.methods, &mut Vec::new());
+                let layer = Settings {
+                    name,
+                    origins: &route.allowed_origins,
+                    methods_key: "methods",
+                    methods: &route.methods,
+                    headers: route
+                        .allowed_headers
+                        .as_ref()
+                        .unwrap_or(&config.allowed_headers),
+                    credentials: route.allow_credentials.unwrap_or(config.allow_credentials),
+                    max_age_secs: route.max_age_secs.unwrap_or(config.max_age_secs),
+                }
+                .layer(&mut problems);
+
+                RoutePolicy {
+                    path: route.path.clone(),
+                    methods,
+                    layer,
+                }
+            })
+            .collect();
+
+        if problems.is_empty() {
+            Ok(Self { default, routes })
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// The policy for `request`. A preflight is matched on the method it asks about.
+    fn select(&self, request: &Request) -> &CorsLayer {
+        let method = if request.method() == Method::OPTIONS {
+            request
+                .headers()
+                .get(ACCESS_CONTROL_REQUEST_METHOD)
+                .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        } else {
+            Some(request.method().clone())
+        };
+        let path = request.uri().path();
+
+        self.routes
+            .iter()
+            .find(|route| route.covers(path, method.as_ref()))
+            .map_or(&self.default, |route| &route.layer)
+    }
+}
+
+/// Applies the CORS policy that [`CorsPolicies`] selects for each request.
+pub async fn cors(
+    State(policies): State<Arc<CorsPolicies>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let layer = policies.select(&request).clone();
+    layer
+        .layer(next)
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {})
+}
+
+struct Settings<'a> {
+    /// Config path used in problems, e.g. `cors.routes[0]`.
+    name: String,
+    origins: &'a [String],
+    methods_key: &'static str,
+    methods: &'a [String],
+    headers: &'a [String],
+    credentials: bool,
+    max_age_secs: u64,
+}
+
+impl Settings<'_> {
+    fn layer(self, problems: &mut Vec<String>) -> CorsLayer {
+        let name = &self.name;
+
+        let mut origins = Vec::new();
+        for origin in self.origins {
+            match origin.parse::<OriginPattern>() {
+                Ok(pattern) => origins.push(pattern),
+                Err(()) => problems.push(format!(
+                    "{name}.allowed_origins has invalid origin `{origin}`"
+                )),
+            }
+        }
+        let any_origin = origins.contains(&OriginPattern::Any);
+        let allow_origin = if any_origin {
+            AllowOrigin::any()
+        } else {
+            AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+                origin
+                    .to_str()
+                    .is_ok_and(|origin| origins.iter().any(|pattern| pattern.matches(origin)))
+            })
+        };
+
+        let any_method = self.methods.iter().any(|method| method == "*");
+        let allow_methods = if any_method {
+            AllowMethods::any()
+        } else {
+            parse_methods(
+                &format!("{name}.{}", self.methods_key),
+                self.methods,
+                problems,
+            )
+            .unwrap_or_default()
+            .into()
+        };
+
+        let any_header = self.headers.iter().any(|header| header == "*");
+        let allow_headers = if any_header {
+            AllowHeaders::any()
+        } else {
+            let mut headers = Vec::new();
+            for header in self.headers {
+                match HeaderName::from_str(header) {
+                    Ok(header) => headers.push(header),
+                    Err(_) => problems.push(format!(
+                        "{name}.allowed_headers has invalid header `{header}`"
+                    )),
+                }
+            }
+            headers.into()
+        };
+
+        // Browsers ignore `*` on credentialed requests, and tower-http refuses the combination.
+        if self.credentials {
+            for (field, wildcard) in [
+                ("allowed_origins", any_origin),
+                ("allowed_methods", any_method),
+                ("allowed_headers", any_header),
+            ] {
+                if wildcard {
+                    problems.push(format!(
+                        "{name}.{field} cannot be `*` when credentials are allowed"
+                    ));
+                }
+            }
+        }
+
+        let layer = CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(allow_methods)
+            .allow_headers(allow_headers)
+            .max_age(Duration::from_secs(self.max_age_secs));
+        if self.credentials && !any_origin && !any_method && !any_header {
+            layer.allow_credentials(true)
+        } else {
+            layer
+        }
+    }
+}
+
+/// The methods in `methods`, or `None` for `*`. Problems are reported against `key`.
+fn parse_methods(key: &str, methods: &[String], problems: &mut Vec<String>) -> Option<Vec<Method>> {
+    if methods.iter().any(|method| method == "*") {
+        return None;
+    }
+
+    let mut parsed = Vec::new();
+    for method in methods {
+        match Method::from_str(&method.to_ascii_uppercase()) {
+            Ok(method) => parsed.push(method),
+            Err(_) => problems.push(format!("{key} has invalid method `{method}`")),
+        }
+    }
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{
+            header::{
+                ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_METHODS,
+                ACCESS_CONTROL_ALLOW_ORIGIN, ORIGIN,
+            },
+            HeaderMap,
+        },
+        routing::get,
+        Router,
+    };
+
+    use super::*;
+    use crate::config::{Config, CorsRouteConfig};
+
+    #[test]
+    fn test_origin_patterns() {
+        let pattern = |s: &str| s.parse::<OriginPattern>().unwrap();
+
+        let exact = pattern("https://App.example.com");
+        assert!(exact.matches("https://app.example.com"));
+        assert!(!exact.matches("https://app.example.com:8443"));
+
+        let subdomains = pattern("https://*.example.com");
+        assert!(subdomains.matches("https://staging.example.com"));
+        assert!(subdomains.matches("https://pr-12.preview.example.com"));
+        assert!(!subdomains.matches("https://example.com"));
+        assert!(!subdomains.matches("http://staging.example.com"));
+        assert!(!subdomains.matches("https://evil.com?.example.com"));
+        assert!(!subdomains.matches("https://staging.example.com.evil.com"));
+
+        for invalid in [
+            "kiosk",
+            "ftp://example.com",
+            "https://a.*.example.com",
+            "https://*.",
+        ] {
+            assert!(invalid.parse::<OriginPattern>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_wildcards_with_credentials_are_rejected() {
+        let mut config = Config::default().cors;
+        config.allowed_origins = vec!["*".to_string()];
+
+        let problems = CorsPolicies::from_config(&config).err().unwrap();
+        assert_eq!(
+            problems,
+            ["cors.allowed_origins cannot be `*` when credentials are allowed"]
+        );
+    }
+
+    async fn send(router: &Router, method: Method, uri: &str, origin: &str) -> HeaderMap {
+        let mut request = Request::builder()
+            .method(&method)
+            .uri(uri)
+            .header(ORIGIN, origin);
+        if method == Method::OPTIONS {
+            request = request.header(ACCESS_CONTROL_REQUEST_METHOD, "GET");
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response.headers().clone()
+    }
+
+    #[tokio::test]
+    async fn test_route_overrides_apply_by_path_and_method() {
+        let mut config = Config::default().cors;
+        config.allowed_origins = vec!["https://*.library.example".to_string()];
+        config.routes = vec![CorsRouteConfig {
+            path: "/api/books".to_string(),
+            methods: vec!["GET".to_string()],
+            allowed_origins: vec!["*".to_string()],
+            allowed_headers: None,
+            allow_credentials: Some(false),
+            max_age_secs: None,
+        }];
+        let policies = Arc::new(CorsPolicies::from_config(&config).unwrap());
+
+        let router = Router::new()
+            .route("/api/books", get(|| async {}).post(|| async {}))
+            .route("/api/users", get(|| async {}))
+            .layer(axum::middleware::from_fn_with_state(policies, cors));
+
+        // The public catalog is readable from anywhere, without credentials.
+        let headers = send(&router, Method::GET, "/api/books", "https://elsewhere.org").await;
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let headers = send(
+            &router,
+            Method::OPTIONS,
+            "/api/books",
+            "https://elsewhere.org",
+        )
+        .await;
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET");
+
+        // Writes to the catalog and other routes fall back to the default policy.
+        let headers = send(&router, Method::POST, "/api/books", "https://elsewhere.org").await;
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let kiosk = "https://kiosk.library.example";
+        let headers = send(&router, Method::GET, "/api/users", kiosk).await;
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], kiosk);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let headers = send(&router, Method::GET, "/api/users", "https://elsewhere.org").await;
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+}
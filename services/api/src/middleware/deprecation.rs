@@ -0,0 +1,83 @@
+use axum::{
+    extract::{Request, State},
+    http::{header::LINK, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{handlers::ApiVersion, AppState};
+
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Marks responses from the unversioned `/api/...` aliases as deprecated: `Deprecation`
+/// (RFC 9745) gives when, `Sunset` (RFC 8594) when the aliases may go away, and `Link`
+/// points at the same resource under the version the aliases serve.
+pub async fn legacy_alias(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    // Nested under `/api`, so the path is already relative to it.
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        ApiVersion::LEGACY.prefix(),
+        request.uri().path()
+    );
+
+    let mut response = next.run(request).await;
+    let config = &state.config.api;
+    let headers = response.headers_mut();
+    if let Ok(date) =
+        HeaderValue::from_str(&format!("@{}", config.legacy_deprecated_at.timestamp()))
+    {
+        headers.insert(DEPRECATION, date);
+    }
+    if let Ok(date) = HeaderValue::from_str(
+        &config
+            .legacy_sunset
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string(),
+    ) {
+        headers.insert(SUNSET, date);
+    }
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.append(LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{app, repositories::memory::MemoryStore};
+
+    #[tokio::test]
+    async fn test_legacy_aliases_are_deprecated() {
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/api/v1/books").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(DEPRECATION).is_none());
+
+        let uri = format!("/api/books/{}", Uuid::nil());
+        let response = app
+            .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[DEPRECATION], "@1792281600");
+        assert_eq!(response.headers()[SUNSET], "Fri, 30 Apr 2027 00:00:00 GMT");
+        assert_eq!(
+            response.headers()[LINK],
+            format!("</api/v1/books/{}>; rel=\"successor-version\"", Uuid::nil())
+        );
+    }
+}
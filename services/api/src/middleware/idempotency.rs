@@ -0,0 +1,189 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    middleware::{auth::caller_id, request_id},
+    services::idempotency::{self, Claim, StoredResponse},
+    AppState,
+};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+const MAX_KEY_LENGTH: usize = 255;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Makes `POST` requests carrying an `Idempotency-Key` header safe to retry.
+///
+/// The first request with a key runs normally and its response is stored per user; a retry
+/// with the same key and body gets the stored response back instead of running again. Reusing
+/// a key for a different request is rejected with 422, and a retry that arrives while the
+/// original is still running gets 409. Server errors, a panicking handler among them, aren't
+/// stored, so those can be retried.
+pub async fn idempotency(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+
+    let Some(key) = request.headers().get(&IDEMPOTENCY_KEY) else {
+        return next.run(request).await;
+    };
+
+    let key = match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_owned(),
+        _ => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    // Anonymous callers share one namespace.
+    let user_id = caller_id(
+        &state.config.auth.service_url,
+        request.extensions(),
+        request.headers(),
+    )
+    .await
+    .unwrap_or(Uuid::nil());
+
+    let (parts, body) = request.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+
+    let request_hash = idempotency::fingerprint(parts.method.as_str(), parts.uri.path(), &body);
+    let ttl_hours = state.config.idempotency.key_ttl_hours;
+
+    match idempotency::claim(&state.db, user_id, &key, &request_hash, ttl_hours).await {
+        Ok(Claim::New) => {}
+        Ok(Claim::Replay(stored)) => return replay(stored),
+        Ok(Claim::InProgress) => return StatusCode::CONFLICT.into_response(),
+        Ok(Claim::Mismatch) => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let request = Request::from_parts(parts, Body::from(body));
+    let db = state.db.clone();
+    let task_key = key.clone();
+
+    // Run to completion even if the client hangs up, so its retry finds a stored response
+    // rather than a key that stays in progress until it expires.
+    let task = request_id::spawn(async move {
+        let key = task_key;
+        let response = next.run(request).await;
+
+        if response.status().is_server_error() {
+            release(&db, user_id, &key).await;
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = match to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(_) => {
+                release(&db, user_id, &key).await;
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+        let stored = StoredResponse {
+            status: parts.status.as_u16() as i32,
+            content_type: parts
+                .headers
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+            body: body.to_vec(),
+        };
+
+        if let Err(e) = idempotency::complete(&db, user_id, &key, &stored).await {
+            warn!("Failed to store idempotent response: {}", e);
+        }
+
+        Response::from_parts(parts, Body::from(body))
+    });
+
+    match task.await {
+        Ok(response) => response,
+        // The handler panicked, so nothing will be stored; let a retry run it again.
+        Err(_) => {
+            release(&state.db, user_id, &key).await;
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn release(db: &PgPool, user_id: Uuid, key: &str) {
+    if let Err(e) = idempotency::release(db, user_id, key).await {
+        warn!("Failed to release idempotency key: {}", e);
+    }
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = u16::try_from(stored.status)
+        .ok()
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+    let mut response = (status, stored.body).into_response();
+    response.headers_mut().remove(CONTENT_TYPE);
+    if let Some(content_type) = stored
+        .content_type
+        .and_then(|v| HeaderValue::from_str(&v).ok())
+    {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response
+        .headers_mut()
+        .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::testing::TestDb;
+
+    async fn panics() -> StatusCode {
+        panic!("handler failed")
+    }
+
+    #[tokio::test]
+    async fn test_key_is_released_when_the_handler_panics() {
+        let Some(test_db) = TestDb::provision().await else {
+            return;
+        };
+        let state = AppState::new(test_db.pool.clone(), None);
+        let app = Router::new()
+            .route("/", post(panics))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                idempotency,
+            ))
+            .with_state(state);
+
+        for _ in 0..2 {
+            let request = Request::post("/")
+                .header(IDEMPOTENCY_KEY, "retry-me")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        let keys: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM idempotency_keys")
+            .fetch_one(&test_db.pool)
+            .await
+            .unwrap();
+        assert_eq!(keys, 0);
+    }
+}
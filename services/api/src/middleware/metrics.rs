@@ -0,0 +1,77 @@
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::metrics::METRICS;
+
+/// Counts and times every request. Requests are labelled with the route template, such as
+/// `/api/books/:id`, so ids don't become label values; requests that match no route share
+/// the `unmatched` label.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_owned();
+    let method = request.method().clone();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    METRICS
+        .http_requests
+        .with_label_values(&[method.as_str(), &route, response.status().as_str()])
+        .inc();
+    METRICS
+        .http_request_duration
+        .with_label_values(&[method.as_str(), &route])
+        .observe(elapsed);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{app, repositories::memory::MemoryStore, AppState};
+
+    #[tokio::test]
+    async fn test_requests_are_counted_by_route_template() {
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+
+        let uri = format!("/api/books/{}", Uuid::new_v4());
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body
+            .contains(r#"http_requests_total{method="GET",route="/api/books/:id",status="404"}"#));
+        assert!(body.contains(
+            r#"http_request_duration_seconds_bucket{method="GET",route="/api/books/:id""#
+        ));
+        assert!(body.contains("library_overdue_checkouts 0"));
+        assert!(body.contains("db_pool_max_connections"));
+    }
+}
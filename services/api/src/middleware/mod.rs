@@ -1 +1,8 @@
 pub mod auth;
+pub mod casing;
+pub mod cors;
+pub mod deprecation;
+pub mod idempotency;
+pub mod metrics;
+pub mod rate_limit;
+pub mod request_id;
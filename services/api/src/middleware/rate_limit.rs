@@ -0,0 +1,547 @@
+//! Rate limits on `/api` from configuration, keyed by the authenticated user or, for
+//! anonymous requests, the client IP.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::warn;
+
+use crate::{
+    config::{RateLimitConfig, RateLimitStore},
+    middleware::auth::{authenticated_user_id, bearer_token, Caller},
+    services::rate_limit::{BucketStore, Decision, MemoryBuckets, PgBuckets, Rule},
+    AppState,
+};
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+pub const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// An entry in `trusted_proxies`: an address, or a range in CIDR notation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network = address.parse::<IpAddr>().map_err(|_| ())?.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| ())?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(());
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A rule for part of the API. `methods` of `None` covers every method.
+struct Group {
+    paths: Vec<String>,
+    methods: Option<Vec<Method>>,
+    rule: Rule,
+}
+
+impl Group {
+    fn covers(&self, path: &str, method: &Method) -> bool {
+        let under_path = self.paths.iter().any(|prefix| {
+            path.strip_prefix(prefix.trim_end_matches('/'))
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        let for_method = self
+            .methods
+            .as_ref()
+            .is_none_or(|methods| methods.contains(method));
+        under_path && for_method
+    }
+}
+
+/// The proxies whose `X-Forwarded-For` is believed, from `rate_limit.trusted_proxies`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpRange>);
+
+impl TrustedProxies {
+    /// Parses `proxies`, or lists every entry that isn't an address or range.
+    pub fn from_config(proxies: &[String]) -> Result<Self, Vec<String>> {
+        let mut ranges = Vec::new();
+        let mut problems = Vec::new();
+        for proxy in proxies {
+            match proxy.parse::<IpRange>() {
+                Ok(range) => ranges.push(range),
+                Err(()) => problems.push(format!(
+                    "rate_limit.trusted_proxies has invalid address or range `{proxy}`"
+                )),
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(Self(ranges))
+        } else {
+            Err(problems)
+        }
+    }
+
+    fn trusts(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The address of the client. When the connection comes from a trusted proxy, that is
+    /// the last `X-Forwarded-For` hop not added by a trusted proxy; earlier hops were
+    /// written by the client and can't be believed.
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = peer?.to_canonical();
+        if !self.trusts(client) {
+            return Some(client);
+        }
+
+        let hops: Vec<&str> = headers
+            .get_all(&X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip.to_canonical();
+            if !self.trusts(client) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The configured default rule, per-group rules and trusted proxies.
+pub struct RateLimitPolicies {
+    default: Rule,
+    groups: Vec<Group>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl RateLimitPolicies {
+    /// Builds the policies, or lists every problem with `config`.
+    pub fn from_config(config: &RateLimitConfig) -> Result<Self, Vec<String>> {
+        let mut problems = Vec::new();
+
+        let default = Rule {
+            name: "default".to_string(),
+            requests_per_minute: config.requests_per_minute,
+            burst: config.burst,
+        };
+        check_rule("rate_limit", &default, &mut problems);
+
+        let mut groups = Vec::new();
+        for (i, group) in config.groups.iter().enumerate() {
+            let name = format!("rate_limit.groups[{i}]");
+            if group.name.is_empty() || group.name == default.name {
+                problems.push(format!(
+                    "{name}.name must be set and not `{}`",
+                    default.name
+                ));
+            }
+            if group.paths.is_empty() {
+                problems.push(format!("{name}.paths must not be empty"));
+            }
+            for path in &group.paths {
+                if !path.starts_with('/') {
+                    problems.push(format!("{name}.paths must start with `/`, got `{path}`"));
+                }
+            }
+            let mut methods = Vec::new();
+            for method in &group.methods {
+                match Method::from_str(&method.to_ascii_uppercase()) {
+                    Ok(method) => methods.push(method),
+                    Err(_) => {
+                        problems.push(format!("{name}.methods has invalid method `{method}`"))
+                    }
+                }
+            }
+
+            let rule = Rule {
+                name: group.name.clone(),
+                requests_per_minute: group.requests_per_minute,
+                burst: group.burst,
+            };
+            check_rule(&name, &rule, &mut problems);
+            groups.push(Group {
+                paths: group.paths.clone(),
+                methods: (!methods.is_empty()).then_some(methods),
+                rule,
+            });
+        }
+
+        let trusted_proxies =
+            TrustedProxies::from_config(&config.trusted_proxies).unwrap_or_else(|proxy_problems| {
+                problems.extend(proxy_problems);
+                TrustedProxies::default()
+            });
+
+        if problems.is_empty() {
+            Ok(Self {
+                default,
+                groups,
+                trusted_proxies,
+            })
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// The rule for a request: the first group that covers it, or the default.
+    fn rule_for(&self, path: &str, method: &Method) -> &Rule {
+        self.groups
+            .iter()
+            .find(|group| group.covers(path, method))
+            .map_or(&self.default, |group| &group.rule)
+    }
+
+    /// Every rule, the default first.
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        std::iter::once(&self.default).chain(self.groups.iter().map(|group| &group.rule))
+    }
+}
+
+fn check_rule(name: &str, rule: &Rule, problems: &mut Vec<String>) {
+    if rule.requests_per_minute == 0 {
+        problems.push(format!("{name}.requests_per_minute must be at least 1"));
+    }
+    if rule.burst == 0 {
+        problems.push(format!("{name}.burst must be at least 1"));
+    }
+}
+
+/// The policies and the store their buckets are kept in.
+pub struct RateLimiter {
+    policies: RateLimitPolicies,
+    store: Arc<dyn BucketStore>,
+}
+
+impl RateLimiter {
+    pub fn new(policies: RateLimitPolicies, store: Arc<dyn BucketStore>) -> Self {
+        Self { policies, store }
+    }
+
+    /// The limiter `config` describes, with buckets in `db` for the `postgres` store.
+    pub fn from_config(config: &RateLimitConfig, db: PgPool) -> Result<Self, Vec<String>> {
+        let store: Arc<dyn BucketStore> = match config.store {
+            RateLimitStore::Memory => Arc::new(MemoryBuckets::new()),
+            RateLimitStore::Postgres => Arc::new(PgBuckets::new(db)),
+        };
+        Ok(Self::new(RateLimitPolicies::from_config(config)?, store))
+    }
+
+    /// Takes a token from the bucket called `key`, or `None` when the store failed.
+    async fn take(&self, key: &str, rule: &Rule, now: DateTime<Utc>) -> Option<Decision> {
+        match self.store.take(key, rule, now).await {
+            Ok(decision) => Some(decision),
+            Err(e) => {
+                warn!("Rate limit store failed; allowing the request: {:#}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Takes a token for every `/api` request and refuses it with `429 Too Many Requests` when
+/// the caller's bucket is empty. Every request takes a token from its client IP's bucket, and
+/// one whose bearer token verifies then takes one from its user's bucket as well, which is
+/// the one reported. The token is only verified once the IP's bucket has admitted the
+/// request, so made-up tokens can't get past the limiter and each turn into a call to the
+/// auth service. If the store fails the request is let through rather than taking the API
+/// down with it.
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = state.rate_limiter.clone() else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if !(path == "/api" || path.starts_with("/api/")) {
+        return next.run(request).await;
+    }
+    let rule = limiter.policies.rule_for(path, request.method());
+    let now = state.clock.now();
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = match limiter
+        .policies
+        .trusted_proxies
+        .client_ip(peer, request.headers())
+    {
+        Some(ip) => format!("ip:{ip}"),
+        None => "ip:unknown".to_string(),
+    };
+    let Some(mut decision) = limiter
+        .take(&format!("{}:{client}", rule.name), rule, now)
+        .await
+    else {
+        return next.run(request).await;
+    };
+
+    if decision.allowed && bearer_token(request.headers()).is_some() {
+        let user_id =
+            authenticated_user_id(&state.config.auth.service_url, request.headers()).await;
+        request.extensions_mut().insert(Caller(user_id));
+        if let Some(id) = user_id {
+            let key = format!("{}:user:{id}", rule.name);
+            match limiter.take(&key, rule, now).await {
+                Some(user_decision) => decision = user_decision,
+                None => return next.run(request).await,
+            }
+        }
+    }
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+        response
+    };
+    add_headers(response.headers_mut(), rule, &decision);
+    response
+}
+
+fn add_headers(headers: &mut HeaderMap, rule: &Rule, decision: &Decision) {
+    headers.insert(RATELIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RATELIMIT_RESET, HeaderValue::from(decision.reset_secs));
+    if let Ok(policy) = HeaderValue::from_str(&rule.policy()) {
+        headers.insert(RATELIMIT_POLICY, policy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Request},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        app,
+        config::{Config, RateLimitGroupConfig},
+        repositories::memory::MemoryStore,
+        services::clock::TestClock,
+        testing::HttpStub,
+    };
+
+    fn config() -> RateLimitConfig {
+        let mut config = Config::default().rate_limit;
+        config.trusted_proxies = vec!["10.0.0.0/8".to_string()];
+        config.groups = vec![RateLimitGroupConfig {
+            name: "search".to_string(),
+            paths: vec!["/api/books".to_string()],
+            methods: vec!["get".to_string()],
+            requests_per_minute: 60,
+            burst: 2,
+        }];
+        config
+    }
+
+    fn request(uri: &str, peer: &str, forwarded_for: Option<&str>) -> Request<Body> {
+        let mut request = Request::get(uri);
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header(X_FORWARDED_FOR, forwarded_for);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    }
+
+    #[test]
+    fn test_client_ip_skips_trusted_proxies() {
+        let proxies = TrustedProxies::from_config(&config().trusted_proxies).unwrap();
+        let client = |peer: &str, forwarded_for: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(X_FORWARDED_FOR, forwarded_for.parse().unwrap());
+            proxies
+                .client_ip(Some(peer.parse().unwrap()), &headers)
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(client("10.0.0.2", "203.0.113.7"), "203.0.113.7");
+        assert_eq!(
+            client("10.0.0.2", "1.1.1.1, 203.0.113.7, 10.0.0.9"),
+            "203.0.113.7"
+        );
+        assert_eq!(client("198.51.100.4", "203.0.113.7"), "198.51.100.4");
+        assert_eq!(
+            client("::ffff:10.0.0.2", "garbage, 203.0.113.7"),
+            "203.0.113.7"
+        );
+        assert_eq!(client("10.0.0.2", "10.0.0.8"), "10.0.0.8");
+    }
+
+    #[test]
+    fn test_invalid_config_is_reported() {
+        let mut config = config();
+        config.trusted_proxies.push("10.0.0.0/33".to_string());
+        config.groups[0].burst = 0;
+        config.groups[0].methods.push("GO T".to_string());
+
+        let problems = RateLimitPolicies::from_config(&config).err().unwrap();
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+    }
+
+    #[tokio::test]
+    async fn test_searches_are_limited_per_client() {
+        let clock = Arc::new(TestClock::new(Utc::now()));
+        let limiter = RateLimiter::new(
+            RateLimitPolicies::from_config(&config()).unwrap(),
+            Arc::new(MemoryBuckets::new()),
+        );
+        let app = app(AppState::in_memory(&MemoryStore::new())
+            .with_clock(clock.clone())
+            .with_rate_limiter(Arc::new(limiter)));
+        let send = |request| app.clone().oneshot(request);
+
+        for remaining in ["1", "0"] {
+            let response = send(request(
+                "/api/books?query=a",
+                "10.0.0.2",
+                Some("203.0.113.7"),
+            ))
+            .await
+            .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[RATELIMIT_LIMIT], "2");
+            assert_eq!(response.headers()[RATELIMIT_REMAINING], remaining);
+            assert_eq!(response.headers()[RATELIMIT_POLICY], "2;w=2");
+        }
+
+        let response = send(request(
+            "/api/books?query=b",
+            "10.0.0.3",
+            Some("203.0.113.7"),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+        assert_eq!(response.headers()[RATELIMIT_RESET], "2");
+
+        // Another client, and the same client outside the group, have budgets of their own.
+        let response = send(request("/api/books", "10.0.0.2", Some("203.0.113.8")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(request(
+            &format!("/api/users/{}", uuid::Uuid::nil()),
+            "10.0.0.2",
+            Some("203.0.113.7"),
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[RATELIMIT_LIMIT], "100");
+
+        clock.advance(chrono::Duration::seconds(1));
+        let response = send(request("/api/books", "10.0.0.2", Some("203.0.113.7")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(request("/health/live", "10.0.0.2", Some("203.0.113.7")))
+            .await
+            .unwrap();
+        assert!(response.headers().get(RATELIMIT_LIMIT).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_made_up_tokens_are_limited_by_ip_before_verification() {
+        let auth = HttpStub::with_body(|_| serde_json::json!({ "valid": false })).await;
+        let limiter = RateLimiter::new(
+            RateLimitPolicies::from_config(&config()).unwrap(),
+            Arc::new(MemoryBuckets::new()),
+        );
+        let mut state = AppState::in_memory(&MemoryStore::new());
+        let mut app_config = (*state.config).clone();
+        app_config.auth.service_url = auth.url().to_string();
+        state = state
+            .with_config(Arc::new(app_config))
+            .with_clock(Arc::new(TestClock::new(Utc::now())))
+            .with_rate_limiter(Arc::new(limiter));
+        let app = app(state);
+
+        let mut statuses = Vec::new();
+        for i in 0..5 {
+            let mut request = request("/api/books", "10.0.0.2", Some("203.0.113.7"));
+            request.headers_mut().insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer made-up-{i}")).unwrap(),
+            );
+            statuses.push(app.clone().oneshot(request).await.unwrap().status());
+        }
+
+        assert_eq!(
+            statuses,
+            [
+                StatusCode::OK,
+                StatusCode::OK,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::TOO_MANY_REQUESTS,
+            ]
+        );
+        assert_eq!(auth.requests().len(), 2);
+    }
+}
@@ -0,0 +1,159 @@
+use std::future::Future;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tokio::task::JoinHandle;
+use tracing::{field::Empty, Instrument, Span};
+use uuid::Uuid;
+
+use crate::telemetry;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+const MAX_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: HeaderValue;
+}
+
+/// Gives every request an `X-Request-Id`: the caller's, when it sent a usable one, or a new
+/// UUID. The ID is set on the request before the trace span is made, echoed on the response,
+/// and available to the handler's task through [`current`].
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .filter(|id| is_usable(id))
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::try_from(Uuid::new_v4().to_string()).unwrap());
+    request.headers_mut().insert(REQUEST_ID, id.clone());
+
+    let mut response = CURRENT.scope(id.clone(), next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID, id);
+    response
+}
+
+fn is_usable(id: &HeaderValue) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id.as_bytes().iter().all(|b| b.is_ascii_graphic())
+}
+
+/// The span for one request, named after its route template and continuing the caller's
+/// trace. `user_id` is filled in once the caller is authenticated.
+pub fn make_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str);
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{} {route}", request.method()),
+        otel.kind = "server",
+        method = %request.method(),
+        route,
+        request_id,
+        user_id = Empty,
+    );
+    telemetry::continue_trace(&span, request.headers());
+    span
+}
+
+/// The ID of the request being handled, if any.
+pub fn current() -> Option<HeaderValue> {
+    CURRENT.try_with(HeaderValue::clone).ok()
+}
+
+/// Adds the current request's ID, if any, and the current trace context to an outbound
+/// request.
+pub fn propagate(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let request = telemetry::inject(request);
+    match current().as_ref().and_then(|id| id.to_str().ok()) {
+        Some(id) => request.header(REQUEST_ID.as_str(), id),
+        None => request,
+    }
+}
+
+/// Spawns `task` in the current request's span and with its ID, so that logs and outbound
+/// calls made from the task can be tied back to the request.
+pub fn spawn<F>(task: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let task = task.instrument(Span::current());
+    match current() {
+        Some(id) => tokio::spawn(CURRENT.scope(id, task)),
+        None => tokio::spawn(task),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    spawn(async { current().unwrap() })
+                        .await
+                        .unwrap()
+                        .to_str()
+                        .unwrap()
+                        .to_owned()
+                }),
+            )
+            .layer(axum::middleware::from_fn(request_id))
+    }
+
+    /// Returns the response's request ID and the one the handler's spawned task saw.
+    async fn send(request: Request) -> (String, String) {
+        let response = app().oneshot(request).await.unwrap();
+        let header = response.headers()[&REQUEST_ID].to_str().unwrap().to_owned();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (header, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_caller_ids_are_propagated_to_spawned_tasks() {
+        let request = Request::get("/")
+            .header(REQUEST_ID, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+
+        let (header, seen_by_task) = send(request).await;
+        assert_eq!(header, "abc-123");
+        assert_eq!(seen_by_task, "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_unusable_ids_are_replaced() {
+        let (header, seen_by_task) = send(Request::get("/").body(Body::empty()).unwrap()).await;
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(seen_by_task, header);
+
+        let request = Request::get("/")
+            .header(REQUEST_ID, "has spaces")
+            .body(Body::empty())
+            .unwrap();
+        let (header, _) = send(request).await;
+        assert!(Uuid::parse_str(&header).is_ok());
+    }
+}
@@ -1,9 +1,10 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Book {
     pub id: Uuid,
     pub isbn: String,
@@ -18,9 +19,10 @@ pub struct Book {
     pub available_copies: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateBookRequest {
     pub isbn: String,
     pub title: String,
@@ -31,10 +33,10 @@ pub struct CreateBookRequest {
     pub description: Option<String>,
     pub cover_url: Option<String>,
     pub total_copies: Option<i32>,
-    pub available_copies: Option<i32>,
+    pub shelf_location: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateBookRequest {
     pub title: Option<String>,
     pub author: Option<String>,
@@ -43,11 +45,10 @@ pub struct UpdateBookRequest {
     pub genre: Option<String>,
     pub description: Option<String>,
     pub cover_url: Option<String>,
-    pub total_copies: Option<i32>,
-    pub available_copies: Option<i32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct BookSearchQuery {
     pub query: Option<String>,
     pub isbn: Option<String>,
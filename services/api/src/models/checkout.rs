@@ -1,9 +1,17 @@
+use std::borrow::Cow;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
+use utoipa::{
+    openapi::{RefOr, Schema},
+    IntoParams, PartialSchema, ToSchema,
+};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default, Type)]
+use super::{Book, User};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default, Type, ToSchema)]
 #[sqlx(type_name = "checkout_status", rename_all = "UPPERCASE")]
 #[serde(rename_all = "UPPERCASE")]
 pub enum CheckoutStatus {
@@ -13,11 +21,12 @@ pub enum CheckoutStatus {
     Overdue,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Checkout {
     pub id: Uuid,
     pub user_id: Uuid,
     pub book_id: Uuid,
+    pub copy_id: Option<Uuid>,
     pub status: CheckoutStatus,
     pub checked_out_at: DateTime<Utc>,
     pub due_date: DateTime<Utc>,
@@ -29,22 +38,112 @@ pub struct Checkout {
     pub updated_at: DateTime<Utc>,
 }
 
+/// A checkout with its patron and book. On the wire it is [`CheckoutDetails`], the same
+/// fields spelled out rather than `#[serde(flatten)]`ed, so that it is one plain object both
+/// to serde and in the OpenAPI document.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "CheckoutDetails", from = "CheckoutDetails")]
 pub struct CheckoutWithDetails {
-    #[serde(flatten)]
     pub checkout: Checkout,
     pub user: CheckoutUser,
     pub book: CheckoutBook,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl PartialSchema for CheckoutWithDetails {
+    fn schema() -> RefOr<Schema> {
+        CheckoutDetails::schema()
+    }
+}
+
+impl ToSchema for CheckoutWithDetails {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("CheckoutWithDetails")
+    }
+
+    fn schemas(schemas: &mut Vec<(String, RefOr<Schema>)>) {
+        CheckoutDetails::schemas(schemas);
+    }
+}
+
+/// A checkout, with the patron who borrowed the book and the book itself.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CheckoutDetails {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub book_id: Uuid,
+    pub copy_id: Option<Uuid>,
+    pub status: CheckoutStatus,
+    pub checked_out_at: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+    pub returned_at: Option<DateTime<Utc>>,
+    pub renewal_count: i32,
+    pub max_renewals: i32,
+    pub overdue_email_sent: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub user: CheckoutUser,
+    pub book: CheckoutBook,
+}
+
+impl From<CheckoutWithDetails> for CheckoutDetails {
+    fn from(details: CheckoutWithDetails) -> Self {
+        let CheckoutWithDetails {
+            checkout,
+            user,
+            book,
+        } = details;
+        Self {
+            id: checkout.id,
+            user_id: checkout.user_id,
+            book_id: checkout.book_id,
+            copy_id: checkout.copy_id,
+            status: checkout.status,
+            checked_out_at: checkout.checked_out_at,
+            due_date: checkout.due_date,
+            returned_at: checkout.returned_at,
+            renewal_count: checkout.renewal_count,
+            max_renewals: checkout.max_renewals,
+            overdue_email_sent: checkout.overdue_email_sent,
+            created_at: checkout.created_at,
+            updated_at: checkout.updated_at,
+            user,
+            book,
+        }
+    }
+}
+
+impl From<CheckoutDetails> for CheckoutWithDetails {
+    fn from(details: CheckoutDetails) -> Self {
+        Self {
+            checkout: Checkout {
+                id: details.id,
+                user_id: details.user_id,
+                book_id: details.book_id,
+                copy_id: details.copy_id,
+                status: details.status,
+                checked_out_at: details.checked_out_at,
+                due_date: details.due_date,
+                returned_at: details.returned_at,
+                renewal_count: details.renewal_count,
+                max_renewals: details.max_renewals,
+                overdue_email_sent: details.overdue_email_sent,
+                created_at: details.created_at,
+                updated_at: details.updated_at,
+            },
+            user: details.user,
+            book: details.book,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CheckoutUser {
     pub id: Uuid,
     pub name: String,
     pub email: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct CheckoutBook {
     pub id: Uuid,
     pub title: String,
@@ -52,30 +151,55 @@ pub struct CheckoutBook {
     pub isbn: String,
 }
 
-#[derive(Debug, Deserialize)]
+impl From<&User> for CheckoutUser {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name.clone(),
+            email: user.email.clone(),
+        }
+    }
+}
+
+impl From<&Book> for CheckoutBook {
+    fn from(book: &Book) -> Self {
+        Self {
+            id: book.id,
+            title: book.title.clone(),
+            author: book.author.clone(),
+            isbn: book.isbn.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateCheckoutRequest {
     pub user_id: Uuid,
     pub book_id: Uuid,
+    pub copy_id: Option<Uuid>,
     pub due_date: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Scanner checkout: either a copy barcode or, for unbarcoded stock, the book's ISBN.
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CheckoutBookRequest {
-    pub isbn: String,
+    pub isbn: Option<String>,
+    pub barcode: Option<String>,
     pub user_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ReturnBookRequest {
     pub checkout_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RenewCheckoutRequest {
     pub checkout_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CheckoutSearchQuery {
     pub user_id: Option<Uuid>,
     pub book_id: Option<Uuid>,
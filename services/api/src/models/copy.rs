@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Type};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Type, ToSchema)]
+#[sqlx(type_name = "copy_status", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CopyStatus {
+    #[default]
+    Available,
+    OnLoan,
+    Lost,
+    InRepair,
+    Withdrawn,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default, Type, ToSchema)]
+#[sqlx(type_name = "copy_condition", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum CopyCondition {
+    New,
+    #[default]
+    Good,
+    Fair,
+    Poor,
+    Damaged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct BookCopy {
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub barcode: String,
+    pub shelf_location: Option<String>,
+    pub condition: CopyCondition,
+    pub status: CopyStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateCopyRequest {
+    pub barcode: Option<String>,
+    pub shelf_location: Option<String>,
+    pub condition: Option<CopyCondition>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateCopyRequest {
+    pub shelf_location: Option<String>,
+    pub condition: Option<CopyCondition>,
+    pub status: Option<CopyStatus>,
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A header row, then one record per row.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BookExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+    /// ISO 2709 binary records, with a 852 field per copy.
+    Marc21,
+    /// A MARCXML `<collection>`.
+    Marcxml,
+}
+
+/// How patrons' names and email addresses appear in an export.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// As stored.
+    None,
+    /// Initials for the name; the first letter and the domain of the email.
+    #[default]
+    Mask,
+    /// Left out.
+    Omit,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BookExportQuery {
+    pub format: Option<BookExportFormat>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    pub format: Option<ExportFormat>,
+    /// Defaults to `mask`.
+    pub redact: Option<Redaction>,
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Type};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type, ToSchema)]
+#[sqlx(type_name = "book_import_format", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BookImportFormat {
+    /// A header row, then one book or copy per row.
+    Csv,
+    /// ISO 2709 binary records.
+    Marc21,
+    /// A MARCXML `<collection>` or single `<record>`.
+    Marcxml,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type, ToSchema)]
+#[sqlx(type_name = "book_import_status", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BookImportStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// What happened to a row; in a dry run, what would have happened.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type, ToSchema)]
+#[sqlx(type_name = "book_import_row_action", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum BookImportRowAction {
+    /// A new book, with its copies.
+    Created,
+    /// An existing book whose details changed or which gained copies.
+    Updated,
+    /// An existing book the row didn't change.
+    Unchanged,
+    /// A later row with the ISBN of an earlier one, applied together with it.
+    Merged,
+    /// Invalid, or refused by the database; see `message`.
+    Failed,
+}
+
+/// A bulk catalog import and its progress.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct BookImport {
+    pub id: Uuid,
+    pub format: BookImportFormat,
+    pub dry_run: bool,
+    pub status: BookImportStatus,
+    pub requested_by: Option<Uuid>,
+    pub total_rows: i32,
+    pub processed_rows: i32,
+    pub books_created: i32,
+    pub books_updated: i32,
+    pub books_unchanged: i32,
+    pub rows_failed: i32,
+    pub copies_added: i32,
+    /// Why the import stopped early, if it did.
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// The outcome of one row of an import.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct BookImportRow {
+    pub row_number: i32,
+    pub isbn: Option<String>,
+    pub action: BookImportRowAction,
+    pub book_id: Option<Uuid>,
+    pub copies_added: i32,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportBooksQuery {
+    /// Defaults from the `Content-Type`: `text/csv`, `application/marc`, or
+    /// `application/marcxml+xml` (or any XML type).
+    pub format: Option<BookImportFormat>,
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BookImportRowQuery {
+    pub action: Option<BookImportRowAction>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
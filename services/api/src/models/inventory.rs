@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A book whose stored counters disagree with its copies and active checkouts.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct InventoryDiscrepancy {
+    pub book_id: Uuid,
+    pub isbn: String,
+    pub title: String,
+    pub total_copies: i32,
+    pub available_copies: i32,
+    pub expected_total_copies: i64,
+    pub expected_available_copies: i64,
+    pub copies_on_loan: i64,
+    pub active_checkouts: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InventoryRepairRequest {
+    pub book_id: Option<Uuid>,
+}
@@ -0,0 +1,79 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Type};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type, ToSchema)]
+#[sqlx(type_name = "job_run_status", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum JobRunStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Type, ToSchema)]
+#[sqlx(type_name = "job_run_source", rename_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum JobRunSource {
+    Schedule,
+    Manual,
+}
+
+/// One run of a scheduled job, on its schedule or by hand, and how it went.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job_name: String,
+    /// The occurrence a scheduled run is for, or the request time of a manual run.
+    pub scheduled_for: DateTime<Utc>,
+    /// The API instance that claimed the run.
+    pub instance_id: String,
+    pub source: JobRunSource,
+    /// A dry run reports what it would process without changing anything.
+    pub dry_run: bool,
+    pub requested_by: Option<Uuid>,
+    pub status: JobRunStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub items_processed: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobRunQuery {
+    pub job_name: Option<String>,
+    pub status: Option<JobRunStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RunJobQuery {
+    pub dry_run: Option<bool>,
+}
+
+/// A registered job as shown by the admin API.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub name: String,
+    pub description: String,
+    /// Six-field cron expression, in UTC.
+    pub schedule: String,
+    pub paused: bool,
+    /// `None` while paused, or when this instance doesn't run scheduled jobs.
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_run: Option<JobRun>,
+    pub last_error: Option<JobError>,
+}
+
+/// The most recent failed run of a job.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobError {
+    pub run_id: Uuid,
+    pub at: DateTime<Utc>,
+    pub message: String,
+}
@@ -1,7 +1,21 @@
+pub mod audit;
 pub mod book;
 pub mod checkout;
+pub mod common;
+pub mod copy;
+pub mod export;
+pub mod import;
+pub mod inventory;
+pub mod job;
 pub mod user;
 
+pub use audit::*;
 pub use book::*;
 pub use checkout::*;
+pub use common::*;
+pub use copy::*;
+pub use export::*;
+pub use import::*;
+pub use inventory::*;
+pub use job::*;
 pub use user::*;
@@ -0,0 +1,183 @@
+//! The OpenAPI 3.1 description of each API version, generated from the handlers'
+//! `#[utoipa::path]` annotations and the models' schemas. A version's document is served at
+//! `/api/<version>/openapi.json`, browsable at `/api/<version>/docs`, and committed as
+//! `openapi/<version>.json` so that clients can be generated from it.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Path,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Json, Router,
+};
+use utoipa::OpenApi;
+
+use crate::{
+    handlers::{admin, books, checkouts, copies, users, ApiVersion},
+    AppState,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Library API",
+        description = "Catalogue, patrons and circulation for the library system. Send \
+            `Accept: application/json; profile=camelCase` to read and write every key in \
+            camelCase, or `profile=snake_case` to read every key in snake_case."
+    ),
+    tags(
+        (name = "books", description = "The catalogue"),
+        (name = "copies", description = "Physical copies of books"),
+        (name = "users", description = "Library patrons and staff"),
+        (name = "checkouts", description = "Loans, returns and renewals"),
+        (name = "admin", description = "Audit log, restores, inventory and scheduled jobs"),
+    )
+)]
+struct ApiDoc;
+
+/// The full document for `version`, with the routes of every handler module it nests.
+/// Mirrors `ApiVersion::router`.
+pub fn spec(version: ApiVersion) -> utoipa::openapi::OpenApi {
+    let mut spec = ApiDoc::openapi();
+    // Taken from Cargo.toml, which names no license.
+    spec.info.license = None;
+    let modules = match version {
+        ApiVersion::V1 => [
+            ("/books", books::ApiDoc::openapi()),
+            ("/copies", copies::ApiDoc::openapi()),
+            ("/users", users::ApiDoc::openapi()),
+            ("/checkouts", checkouts::ApiDoc::openapi()),
+            ("/admin", admin::ApiDoc::openapi()),
+        ],
+    };
+    for (path, routes) in modules {
+        // A module's `/` route is served at the bare prefix, as axum nests it.
+        spec = spec.nest_with_path_composer(
+            format!("{}{path}", version.prefix()),
+            routes,
+            |base, path| match path {
+                "/" => base.to_string(),
+                path => format!("{base}{path}"),
+            },
+        );
+    }
+    spec
+}
+
+/// Swagger UI, served from the copy built into the binary and pointed at the spec next to it.
+/// `file` is the path below `docs/`; the page itself is `index.html`.
+async fn docs(file: Option<Path<String>>) -> Response {
+    let file = file.as_ref().map_or("", |Path(file)| file.as_str());
+    let config = Arc::new(utoipa_swagger_ui::Config::from("../openapi.json"));
+    match utoipa_swagger_ui::serve(file, config) {
+        Ok(Some(file)) => {
+            ([(CONTENT_TYPE, file.content_type)], file.bytes.into_owned()).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+pub fn router(version: ApiVersion) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/openapi.json",
+            get(move || async move { Json(spec(version)) }),
+        )
+        // The page loads its assets relative to itself, so it lives under `docs/`.
+        .route("/docs", get(|| async { Redirect::permanent("docs/") }))
+        .route("/docs/", get(docs))
+        .route("/docs/*file", get(docs))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{app, repositories::memory::MemoryStore};
+
+    /// Fails when the handlers or models change the API without `openapi/<version>.json`
+    /// being regenerated. Run the tests with `UPDATE_OPENAPI=1` to rewrite them.
+    #[test]
+    fn test_committed_specs_are_up_to_date() {
+        for version in ApiVersion::ALL {
+            let committed = format!(
+                "{}/openapi/{}.json",
+                env!("CARGO_MANIFEST_DIR"),
+                version.name()
+            );
+            let generated = format!("{}\n", spec(version).to_pretty_json().unwrap());
+
+            if std::env::var_os("UPDATE_OPENAPI").is_some() {
+                std::fs::write(&committed, &generated).unwrap();
+                continue;
+            }
+            assert!(
+                std::fs::read_to_string(&committed).unwrap_or_default() == generated,
+                "{committed} is out of date; run `UPDATE_OPENAPI=1 cargo test openapi` and commit it"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spec_is_served() {
+        let response = app(AppState::in_memory(&MemoryStore::new()))
+            .oneshot(
+                Request::get("/api/v1/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let served: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(served["openapi"], "3.1.0");
+        assert_eq!(
+            served["paths"]["/api/v1/books/{id}"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Book"
+        );
+        assert!(served["paths"]["/api/v1/books"]["get"].is_object());
+        assert!(served["components"]["schemas"]["CheckoutWithDetails"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_docs_are_served_without_a_cdn() {
+        let app = app(AppState::in_memory(&MemoryStore::new()));
+        let get = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = get("/api/v1/docs").await.unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()["location"], "docs/");
+
+        let response = get("/api/v1/docs/").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/html");
+        let page = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        assert!(page.contains("./swagger-ui-bundle.js"), "{page}");
+        assert!(!page.contains("https://"), "{page}");
+
+        let response = get("/api/v1/docs/swagger-initializer.js").await.unwrap();
+        let script = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&script).contains("\"../openapi.json\""));
+
+        let response = get("/api/v1/docs/swagger-ui-bundle.js").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get("/api/v1/docs/nope.js").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
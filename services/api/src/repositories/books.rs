@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        Book, BookCopy, BookSearchQuery, CreateBookRequest, CreateCopyRequest, UpdateBookRequest,
+    },
+    repositories::RepositoryResult,
+    services::audit::AuditContext,
+};
+
+/// Catalog storage. Reads only see live (not deleted) books; every write is audited in the
+/// same transaction.
+#[async_trait]
+pub trait BookRepository: Send + Sync {
+    async fn count(&self, query: &BookSearchQuery) -> RepositoryResult<i64>;
+
+    /// Newest first.
+    async fn list(
+        &self,
+        query: &BookSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<Book>>;
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<Book>>;
+
+    async fn find_by_isbn(&self, isbn: &str) -> RepositoryResult<Option<Book>>;
+
+    /// Adds the book with `total_copies` new copies. Conflict if the ISBN is taken.
+    async fn create(&self, req: CreateBookRequest, ctx: &AuditContext) -> RepositoryResult<Book>;
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateBookRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book>;
+
+    /// Soft-deletes the book. Conflict if it is on loan, unless `force` is set.
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book>;
+
+    async fn list_copies(&self, book_id: Uuid) -> RepositoryResult<Vec<BookCopy>>;
+
+    /// Conflict if the barcode is taken.
+    async fn add_copy(
+        &self,
+        book_id: Uuid,
+        req: CreateCopyRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<BookCopy>;
+}
@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    models::{Book, BookCopy, Checkout, CheckoutStatus, CheckoutWithDetails, User},
+    repositories::RepositoryResult,
+    services::audit::{AuditContext, AuditEvent},
+};
+
+/// Narrows a checkout listing. All set fields must match.
+#[derive(Debug, Clone, Default)]
+pub struct CheckoutFilter {
+    pub user_id: Option<Uuid>,
+    pub book_id: Option<Uuid>,
+    pub status: Option<CheckoutStatus>,
+    /// Only active loans that were due before this instant.
+    pub overdue_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewCheckout {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub book_id: Uuid,
+    pub copy_id: Uuid,
+    pub checked_out_at: DateTime<Utc>,
+    pub due_date: DateTime<Utc>,
+}
+
+#[async_trait]
+pub trait CheckoutRepository: Send + Sync {
+    async fn begin(&self) -> RepositoryResult<Box<dyn CheckoutTransaction>>;
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<CheckoutWithDetails>>;
+
+    async fn count(&self, filter: &CheckoutFilter) -> RepositoryResult<i64>;
+
+    /// Newest first.
+    async fn list(
+        &self,
+        filter: &CheckoutFilter,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>>;
+
+    /// Active loans due before `as_of`, most overdue first.
+    async fn list_overdue(
+        &self,
+        as_of: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>>;
+}
+
+/// A unit of work over circulation state. Nothing is visible to other transactions until
+/// `commit`; dropping the transaction rolls it back.
+///
+/// The `lock_*` methods hold their row until the transaction ends. Callers take locks in
+/// the order user, book, copy/checkout so concurrent loans and returns can't deadlock.
+#[async_trait]
+pub trait CheckoutTransaction: Send {
+    /// Returns the user even if inactive or deleted; callers decide what that means.
+    async fn lock_user(&mut self, user_id: Uuid) -> RepositoryResult<Option<User>>;
+
+    /// Returns the book even if deleted, so loans of a removed title can still be returned.
+    async fn lock_book(&mut self, book_id: Uuid) -> RepositoryResult<Option<Book>>;
+
+    async fn find_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>>;
+
+    async fn find_copy_by_barcode(&mut self, barcode: &str) -> RepositoryResult<Option<BookCopy>>;
+
+    /// Looks up a live (not deleted) book by ISBN.
+    async fn find_book_id_by_isbn(&mut self, isbn: &str) -> RepositoryResult<Option<Uuid>>;
+
+    async fn count_active_checkouts(&mut self, user_id: Uuid) -> RepositoryResult<i64>;
+
+    /// Moves the copy to ON_LOAN if it is available.
+    async fn claim_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>>;
+
+    /// Moves any available copy of the book to ON_LOAN.
+    async fn claim_available_copy(&mut self, book_id: Uuid) -> RepositoryResult<Option<BookCopy>>;
+
+    /// Puts a copy on loan back on the shelf, leaving any other status alone.
+    async fn release_copy(&mut self, copy_id: Uuid) -> RepositoryResult<()>;
+
+    /// Recomputes the book's copy counters from its copies.
+    async fn refresh_availability(&mut self, book_id: Uuid) -> RepositoryResult<Book>;
+
+    async fn insert_checkout(&mut self, checkout: NewCheckout) -> RepositoryResult<Checkout>;
+
+    /// The book an active checkout is for, read without locking.
+    async fn active_checkout_book_id(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<Uuid>>;
+
+    async fn lock_active_checkout(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<CheckoutWithDetails>>;
+
+    /// Marks the checkout returned at `returned_at`, which is also when it was last updated.
+    async fn mark_returned(
+        &mut self,
+        checkout_id: Uuid,
+        returned_at: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout>;
+
+    /// Moves the due date and counts the renewal, made at `now`.
+    async fn renew(
+        &mut self,
+        checkout_id: Uuid,
+        due_date: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout>;
+
+    async fn record_audit(&mut self, ctx: &AuditContext, event: AuditEvent)
+        -> RepositoryResult<()>;
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()>;
+}
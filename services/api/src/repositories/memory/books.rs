@@ -0,0 +1,280 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{contains_ignore_case, MemoryStore};
+use crate::{
+    models::{
+        Book, BookCopy, BookSearchQuery, CheckoutStatus, CreateBookRequest, CreateCopyRequest,
+        UpdateBookRequest,
+    },
+    repositories::{BookRepository, RepositoryError, RepositoryResult},
+    services::audit::{AuditContext, AuditEvent},
+};
+
+fn matches(query: &BookSearchQuery, book: &Book) -> bool {
+    book.deleted_at.is_none()
+        && query.query.as_ref().is_none_or(|q| {
+            contains_ignore_case(&book.title, q) || contains_ignore_case(&book.author, q)
+        })
+        && query.isbn.as_ref().is_none_or(|isbn| &book.isbn == isbn)
+        && query
+            .author
+            .as_ref()
+            .is_none_or(|author| contains_ignore_case(&book.author, author))
+        && query
+            .genre
+            .as_ref()
+            .is_none_or(|genre| book.genre.as_ref() == Some(genre))
+}
+
+#[derive(Clone)]
+pub struct MemoryBookRepository {
+    store: MemoryStore,
+}
+
+impl MemoryBookRepository {
+    pub fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl BookRepository for MemoryBookRepository {
+    async fn count(&self, query: &BookSearchQuery) -> RepositoryResult<i64> {
+        let data = self.store.data.lock().await;
+        Ok(data.books.values().filter(|b| matches(query, b)).count() as i64)
+    }
+
+    async fn list(
+        &self,
+        query: &BookSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<Book>> {
+        let data = self.store.data.lock().await;
+        let mut books: Vec<_> = data
+            .books
+            .values()
+            .filter(|b| matches(query, b))
+            .cloned()
+            .collect();
+        books.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+
+        Ok(books
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<Book>> {
+        let data = self.store.data.lock().await;
+        Ok(data
+            .books
+            .get(&id)
+            .filter(|b| b.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_by_isbn(&self, isbn: &str) -> RepositoryResult<Option<Book>> {
+        let data = self.store.data.lock().await;
+        Ok(data
+            .books
+            .values()
+            .find(|b| b.isbn == isbn && b.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn create(&self, req: CreateBookRequest, ctx: &AuditContext) -> RepositoryResult<Book> {
+        let mut data = self.store.data.lock().await;
+
+        if data
+            .books
+            .values()
+            .any(|b| b.isbn == req.isbn && b.deleted_at.is_none())
+        {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let now = Utc::now();
+        let book = Book {
+            id: Uuid::new_v4(),
+            isbn: req.isbn,
+            title: req.title,
+            author: req.author,
+            publisher: req.publisher,
+            published_year: req.published_year,
+            genre: req.genre,
+            description: req.description,
+            cover_url: req.cover_url,
+            total_copies: 0,
+            available_copies: 0,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        data.books.insert(book.id, book.clone());
+
+        for _ in 0..req.total_copies.unwrap_or(1) {
+            data.insert_copy(
+                book.id,
+                None,
+                req.shelf_location.clone(),
+                Default::default(),
+            );
+        }
+
+        let book = data
+            .refresh_availability(book.id)
+            .ok_or(RepositoryError::NotFound)?;
+        data.record(
+            ctx,
+            AuditEvent::new("book.create", "book", book.id).after(&book),
+        );
+
+        Ok(book)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateBookRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book> {
+        let mut data = self.store.data.lock().await;
+
+        let book = data
+            .books
+            .get_mut(&id)
+            .filter(|b| b.deleted_at.is_none())
+            .ok_or(RepositoryError::NotFound)?;
+        let existing_book = book.clone();
+
+        if let Some(title) = req.title {
+            book.title = title;
+        }
+        if let Some(author) = req.author {
+            book.author = author;
+        }
+        book.publisher = req.publisher.or(book.publisher.take());
+        book.published_year = req.published_year.or(book.published_year);
+        book.genre = req.genre.or(book.genre.take());
+        book.description = req.description.or(book.description.take());
+        book.cover_url = req.cover_url.or(book.cover_url.take());
+        book.updated_at = Utc::now();
+        let book = book.clone();
+
+        data.record(
+            ctx,
+            AuditEvent::new("book.update", "book", book.id)
+                .before(&existing_book)
+                .after(&book),
+        );
+
+        Ok(book)
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book> {
+        let mut data = self.store.data.lock().await;
+
+        let active_checkouts = data
+            .checkouts
+            .values()
+            .filter(|c| c.book_id == id && c.status == CheckoutStatus::Active)
+            .count();
+
+        let book = data
+            .books
+            .get_mut(&id)
+            .filter(|b| b.deleted_at.is_none())
+            .ok_or(RepositoryError::NotFound)?;
+
+        if active_checkouts > 0 && !force {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let existing_book = book.clone();
+        book.deleted_at = Some(now);
+        book.updated_at = now;
+        let book = book.clone();
+
+        let action = if active_checkouts > 0 {
+            "book.force_delete"
+        } else {
+            "book.delete"
+        };
+        data.record(
+            ctx,
+            AuditEvent::new(action, "book", book.id)
+                .before(&existing_book)
+                .after(&book),
+        );
+
+        Ok(book)
+    }
+
+    async fn list_copies(&self, book_id: Uuid) -> RepositoryResult<Vec<BookCopy>> {
+        let data = self.store.data.lock().await;
+
+        if data
+            .books
+            .get(&book_id)
+            .is_none_or(|b| b.deleted_at.is_some())
+        {
+            return Err(RepositoryError::NotFound);
+        }
+
+        let mut copies: Vec<_> = data
+            .copies
+            .values()
+            .filter(|c| c.book_id == book_id)
+            .cloned()
+            .collect();
+        copies.sort_by(|a, b| a.barcode.cmp(&b.barcode));
+
+        Ok(copies)
+    }
+
+    async fn add_copy(
+        &self,
+        book_id: Uuid,
+        req: CreateCopyRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<BookCopy> {
+        let mut data = self.store.data.lock().await;
+
+        if data
+            .books
+            .get(&book_id)
+            .is_none_or(|b| b.deleted_at.is_some())
+        {
+            return Err(RepositoryError::NotFound);
+        }
+        if let Some(ref barcode) = req.barcode {
+            if data.copies.values().any(|c| &c.barcode == barcode) {
+                return Err(RepositoryError::Conflict);
+            }
+        }
+
+        let copy = data.insert_copy(
+            book_id,
+            req.barcode,
+            req.shelf_location,
+            req.condition.unwrap_or_default(),
+        );
+        data.refresh_availability(book_id);
+        data.record(
+            ctx,
+            AuditEvent::new("copy.create", "copy", copy.id).after(&copy),
+        );
+
+        Ok(copy)
+    }
+}
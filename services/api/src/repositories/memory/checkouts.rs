@@ -0,0 +1,302 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::OwnedMutexGuard;
+use uuid::Uuid;
+
+use super::{MemoryData, MemoryStore};
+use crate::{
+    models::{
+        Book, BookCopy, Checkout, CheckoutBook, CheckoutStatus, CheckoutUser, CheckoutWithDetails,
+        CopyStatus, User,
+    },
+    repositories::{
+        CheckoutFilter, CheckoutRepository, CheckoutTransaction, NewCheckout, RepositoryError,
+        RepositoryResult,
+    },
+    services::audit::{AuditContext, AuditEvent},
+};
+
+fn details(data: &MemoryData, checkout: &Checkout) -> Option<CheckoutWithDetails> {
+    Some(CheckoutWithDetails {
+        checkout: checkout.clone(),
+        user: CheckoutUser::from(data.users.get(&checkout.user_id)?),
+        book: CheckoutBook::from(data.books.get(&checkout.book_id)?),
+    })
+}
+
+fn matches(filter: &CheckoutFilter, checkout: &Checkout) -> bool {
+    filter.user_id.is_none_or(|id| checkout.user_id == id)
+        && filter.book_id.is_none_or(|id| checkout.book_id == id)
+        && filter.status.as_ref().is_none_or(|s| &checkout.status == s)
+        && filter
+            .overdue_at
+            .is_none_or(|at| checkout.status == CheckoutStatus::Active && checkout.due_date < at)
+}
+
+#[derive(Clone)]
+pub struct MemoryCheckoutRepository {
+    store: MemoryStore,
+}
+
+impl MemoryCheckoutRepository {
+    pub fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl CheckoutRepository for MemoryCheckoutRepository {
+    async fn begin(&self) -> RepositoryResult<Box<dyn CheckoutTransaction>> {
+        let guard = self.store.data.clone().lock_owned().await;
+        let data = guard.clone();
+        Ok(Box::new(MemoryCheckoutTransaction { guard, data }))
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<CheckoutWithDetails>> {
+        let data = self.store.data.lock().await;
+        Ok(data.checkouts.get(&id).and_then(|c| details(&data, c)))
+    }
+
+    async fn count(&self, filter: &CheckoutFilter) -> RepositoryResult<i64> {
+        let data = self.store.data.lock().await;
+        Ok(data
+            .checkouts
+            .values()
+            .filter(|c| matches(filter, c))
+            .count() as i64)
+    }
+
+    async fn list(
+        &self,
+        filter: &CheckoutFilter,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>> {
+        let data = self.store.data.lock().await;
+        let mut checkouts: Vec<_> = data
+            .checkouts
+            .values()
+            .filter(|c| matches(filter, c))
+            .collect();
+        checkouts.sort_by_key(|c| std::cmp::Reverse(c.created_at));
+
+        Ok(checkouts
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .filter_map(|c| details(&data, c))
+            .collect())
+    }
+
+    async fn list_overdue(
+        &self,
+        as_of: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>> {
+        let filter = CheckoutFilter {
+            overdue_at: Some(as_of),
+            ..Default::default()
+        };
+
+        let data = self.store.data.lock().await;
+        let mut checkouts: Vec<_> = data
+            .checkouts
+            .values()
+            .filter(|c| matches(&filter, c))
+            .collect();
+        checkouts.sort_by_key(|c| c.due_date);
+
+        Ok(checkouts
+            .into_iter()
+            .filter_map(|c| details(&data, c))
+            .collect())
+    }
+}
+
+pub struct MemoryCheckoutTransaction {
+    guard: OwnedMutexGuard<MemoryData>,
+    data: MemoryData,
+}
+
+impl MemoryCheckoutTransaction {
+    fn set_copy_status(&mut self, copy_id: Uuid, status: CopyStatus) -> Option<BookCopy> {
+        let copy = self.data.copies.get_mut(&copy_id)?;
+        copy.status = status;
+        copy.updated_at = Utc::now();
+        Some(copy.clone())
+    }
+}
+
+#[async_trait]
+impl CheckoutTransaction for MemoryCheckoutTransaction {
+    async fn lock_user(&mut self, user_id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(self.data.users.get(&user_id).cloned())
+    }
+
+    async fn lock_book(&mut self, book_id: Uuid) -> RepositoryResult<Option<Book>> {
+        Ok(self.data.books.get(&book_id).cloned())
+    }
+
+    async fn find_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        Ok(self.data.copies.get(&copy_id).cloned())
+    }
+
+    async fn find_copy_by_barcode(&mut self, barcode: &str) -> RepositoryResult<Option<BookCopy>> {
+        Ok(self
+            .data
+            .copies
+            .values()
+            .find(|c| c.barcode == barcode)
+            .cloned())
+    }
+
+    async fn find_book_id_by_isbn(&mut self, isbn: &str) -> RepositoryResult<Option<Uuid>> {
+        Ok(self
+            .data
+            .books
+            .values()
+            .find(|b| b.isbn == isbn && b.deleted_at.is_none())
+            .map(|b| b.id))
+    }
+
+    async fn count_active_checkouts(&mut self, user_id: Uuid) -> RepositoryResult<i64> {
+        Ok(self
+            .data
+            .checkouts
+            .values()
+            .filter(|c| c.user_id == user_id && c.status == CheckoutStatus::Active)
+            .count() as i64)
+    }
+
+    async fn claim_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        match self.data.copies.get(&copy_id) {
+            Some(copy) if copy.status == CopyStatus::Available => {
+                Ok(self.set_copy_status(copy_id, CopyStatus::OnLoan))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn claim_available_copy(&mut self, book_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        let copy_id = self
+            .data
+            .copies
+            .values()
+            .filter(|c| c.book_id == book_id && c.status == CopyStatus::Available)
+            .min_by(|a, b| a.barcode.cmp(&b.barcode))
+            .map(|c| c.id);
+
+        Ok(copy_id.and_then(|id| self.set_copy_status(id, CopyStatus::OnLoan)))
+    }
+
+    async fn release_copy(&mut self, copy_id: Uuid) -> RepositoryResult<()> {
+        if self
+            .data
+            .copies
+            .get(&copy_id)
+            .is_some_and(|copy| copy.status == CopyStatus::OnLoan)
+        {
+            self.set_copy_status(copy_id, CopyStatus::Available);
+        }
+        Ok(())
+    }
+
+    async fn refresh_availability(&mut self, book_id: Uuid) -> RepositoryResult<Book> {
+        self.data
+            .refresh_availability(book_id)
+            .ok_or(RepositoryError::NotFound)
+    }
+
+    async fn insert_checkout(&mut self, checkout: NewCheckout) -> RepositoryResult<Checkout> {
+        let now = Utc::now();
+        let checkout = Checkout {
+            id: checkout.id,
+            user_id: checkout.user_id,
+            book_id: checkout.book_id,
+            copy_id: Some(checkout.copy_id),
+            status: CheckoutStatus::Active,
+            checked_out_at: checkout.checked_out_at,
+            due_date: checkout.due_date,
+            returned_at: None,
+            renewal_count: 0,
+            max_renewals: 2,
+            overdue_email_sent: false,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.data.checkouts.insert(checkout.id, checkout.clone());
+        Ok(checkout)
+    }
+
+    async fn active_checkout_book_id(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<Uuid>> {
+        Ok(self
+            .data
+            .checkouts
+            .get(&checkout_id)
+            .filter(|c| c.status == CheckoutStatus::Active)
+            .map(|c| c.book_id))
+    }
+
+    async fn lock_active_checkout(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<CheckoutWithDetails>> {
+        Ok(self
+            .data
+            .checkouts
+            .get(&checkout_id)
+            .filter(|c| c.status == CheckoutStatus::Active)
+            .and_then(|c| details(&self.data, c)))
+    }
+
+    async fn mark_returned(
+        &mut self,
+        checkout_id: Uuid,
+        returned_at: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout> {
+        let checkout = self
+            .data
+            .checkouts
+            .get_mut(&checkout_id)
+            .ok_or(RepositoryError::NotFound)?;
+        checkout.status = CheckoutStatus::Returned;
+        checkout.returned_at = Some(returned_at);
+        checkout.updated_at = returned_at;
+        Ok(checkout.clone())
+    }
+
+    async fn renew(
+        &mut self,
+        checkout_id: Uuid,
+        due_date: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout> {
+        let checkout = self
+            .data
+            .checkouts
+            .get_mut(&checkout_id)
+            .ok_or(RepositoryError::NotFound)?;
+        checkout.due_date = due_date;
+        checkout.renewal_count += 1;
+        checkout.updated_at = now;
+        Ok(checkout.clone())
+    }
+
+    async fn record_audit(
+        &mut self,
+        ctx: &AuditContext,
+        event: AuditEvent,
+    ) -> RepositoryResult<()> {
+        self.data.record(ctx, event);
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        let Self { mut guard, data } = *self;
+        *guard = data;
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! In-memory repositories for tests. All repositories built from one `MemoryStore` share
+//! its data, and transactions are serialized: `begin` takes the store's lock and works on a
+//! copy that replaces the stored data on `commit`.
+
+pub mod books;
+pub mod checkouts;
+pub mod users;
+
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::Utc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    models::{Book, BookCopy, Checkout, CopyCondition, CopyStatus, User, UserRole},
+    services::{
+        audit::{AuditContext, AuditEvent},
+        inventory,
+    },
+};
+
+pub use books::MemoryBookRepository;
+pub use checkouts::MemoryCheckoutRepository;
+pub use users::MemoryUserRepository;
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryData {
+    pub users: HashMap<Uuid, User>,
+    pub books: HashMap<Uuid, Book>,
+    pub copies: HashMap<Uuid, BookCopy>,
+    pub checkouts: HashMap<Uuid, Checkout>,
+    pub audit_log: Vec<(AuditContext, AuditEvent)>,
+}
+
+pub(super) fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+impl MemoryData {
+    pub fn record(&mut self, ctx: &AuditContext, event: AuditEvent) {
+        self.audit_log.push((ctx.clone(), event));
+    }
+
+    /// Mirrors `inventory::insert_copy`.
+    pub fn insert_copy(
+        &mut self,
+        book_id: Uuid,
+        barcode: Option<String>,
+        shelf_location: Option<String>,
+        condition: CopyCondition,
+    ) -> BookCopy {
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        let copy = BookCopy {
+            id,
+            book_id,
+            barcode: barcode.unwrap_or_else(|| inventory::generate_barcode(id)),
+            shelf_location,
+            condition,
+            status: CopyStatus::Available,
+            created_at: now,
+            updated_at: now,
+        };
+
+        self.copies.insert(id, copy.clone());
+        copy
+    }
+
+    /// Mirrors `inventory::refresh_availability`.
+    pub fn refresh_availability(&mut self, book_id: Uuid) -> Option<Book> {
+        let copies = self.copies.values().filter(|c| c.book_id == book_id);
+        let (total, available) = copies.fold((0, 0), |(total, available), copy| {
+            (
+                total + i32::from(copy.status != CopyStatus::Withdrawn),
+                available + i32::from(copy.status == CopyStatus::Available),
+            )
+        });
+
+        let book = self.books.get_mut(&book_id)?;
+        book.total_copies = total;
+        book.available_copies = available;
+        book.updated_at = Utc::now();
+        Some(book.clone())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    data: Arc<Mutex<MemoryData>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn snapshot(&self) -> MemoryData {
+        self.data.lock().await.clone()
+    }
+
+    pub async fn insert_user(&self, name: &str, max_checkouts: i32) -> User {
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        let user = User {
+            id,
+            email: format!("{id}@example.com"),
+            name: name.to_string(),
+            role: UserRole::User,
+            is_active: true,
+            max_checkouts,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+
+        self.data.lock().await.users.insert(id, user.clone());
+        user
+    }
+
+    /// Adds a book with `copies` available copies.
+    pub async fn insert_book(&self, title: &str, copies: usize) -> Book {
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        let book = Book {
+            id,
+            isbn: id.simple().to_string(),
+            title: title.to_string(),
+            author: "Author".to_string(),
+            publisher: None,
+            published_year: None,
+            genre: None,
+            description: None,
+            cover_url: None,
+            total_copies: 0,
+            available_copies: 0,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+
+        let mut data = self.data.lock().await;
+        data.books.insert(id, book);
+        for _ in 0..copies {
+            data.insert_copy(id, None, None, CopyCondition::Good);
+        }
+        data.refresh_availability(id)
+            .expect("book was just inserted")
+    }
+}
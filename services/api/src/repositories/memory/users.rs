@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{contains_ignore_case, MemoryStore};
+use crate::{
+    models::{CheckoutStatus, UpdateUserRequest, User, UserSearchQuery},
+    repositories::{NewUser, RepositoryError, RepositoryResult, UserRepository},
+    services::audit::{AuditContext, AuditEvent},
+};
+
+fn matches(query: &UserSearchQuery, user: &User) -> bool {
+    user.deleted_at.is_none()
+        && query.query.as_ref().is_none_or(|q| {
+            contains_ignore_case(&user.name, q) || contains_ignore_case(&user.email, q)
+        })
+        && query.role.as_ref().is_none_or(|role| &user.role == role)
+        && query
+            .is_active
+            .is_none_or(|is_active| user.is_active == is_active)
+}
+
+#[derive(Clone)]
+pub struct MemoryUserRepository {
+    store: MemoryStore,
+}
+
+impl MemoryUserRepository {
+    pub fn new(store: MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn count(&self, query: &UserSearchQuery) -> RepositoryResult<i64> {
+        let data = self.store.data.lock().await;
+        Ok(data.users.values().filter(|u| matches(query, u)).count() as i64)
+    }
+
+    async fn list(
+        &self,
+        query: &UserSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<User>> {
+        let data = self.store.data.lock().await;
+        let mut users: Vec<_> = data
+            .users
+            .values()
+            .filter(|u| matches(query, u))
+            .cloned()
+            .collect();
+        users.sort_by_key(|u| std::cmp::Reverse(u.created_at));
+
+        Ok(users
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .collect())
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        let data = self.store.data.lock().await;
+        Ok(data
+            .users
+            .get(&id)
+            .filter(|u| u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn find_including_deleted(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        let data = self.store.data.lock().await;
+        Ok(data.users.get(&id).cloned())
+    }
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        let data = self.store.data.lock().await;
+        Ok(data
+            .users
+            .values()
+            .find(|u| u.email == email && u.deleted_at.is_none())
+            .cloned())
+    }
+
+    async fn create(
+        &self,
+        user: NewUser,
+        action: &'static str,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut data = self.store.data.lock().await;
+
+        if data.users.contains_key(&user.id)
+            || data
+                .users
+                .values()
+                .any(|u| u.email == user.email && u.deleted_at.is_none())
+        {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let now = Utc::now();
+        let user = User {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            role: user.role,
+            is_active: true,
+            max_checkouts: user.max_checkouts,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        data.users.insert(user.id, user.clone());
+        data.record(ctx, AuditEvent::new(action, "user", user.id).after(&user));
+
+        Ok(user)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateUserRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut data = self.store.data.lock().await;
+
+        let user = data
+            .users
+            .get_mut(&id)
+            .filter(|u| u.deleted_at.is_none())
+            .ok_or(RepositoryError::NotFound)?;
+        let existing_user = user.clone();
+
+        if let Some(name) = req.name {
+            user.name = name;
+        }
+        if let Some(role) = req.role {
+            user.role = role;
+        }
+        if let Some(is_active) = req.is_active {
+            user.is_active = is_active;
+        }
+        if let Some(max_checkouts) = req.max_checkouts {
+            user.max_checkouts = max_checkouts;
+        }
+        user.updated_at = Utc::now();
+        let user = user.clone();
+
+        data.record(
+            ctx,
+            AuditEvent::new("user.update", "user", user.id)
+                .before(&existing_user)
+                .after(&user),
+        );
+
+        Ok(user)
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut data = self.store.data.lock().await;
+
+        let active_checkouts = data
+            .checkouts
+            .values()
+            .filter(|c| c.user_id == id && c.status == CheckoutStatus::Active)
+            .count();
+
+        let user = data
+            .users
+            .get_mut(&id)
+            .filter(|u| u.deleted_at.is_none())
+            .ok_or(RepositoryError::NotFound)?;
+
+        if active_checkouts > 0 && !force {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let existing_user = user.clone();
+        user.deleted_at = Some(now);
+        user.updated_at = now;
+        let user = user.clone();
+
+        let action = if active_checkouts > 0 {
+            "user.force_delete"
+        } else {
+            "user.delete"
+        };
+        data.record(
+            ctx,
+            AuditEvent::new(action, "user", user.id)
+                .before(&existing_user)
+                .after(&user),
+        );
+
+        Ok(user)
+    }
+}
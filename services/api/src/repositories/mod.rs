@@ -0,0 +1,47 @@
+pub mod books;
+pub mod checkouts;
+#[cfg(test)]
+pub mod memory;
+pub mod postgres;
+pub mod users;
+
+use axum::http::StatusCode;
+
+pub use books::*;
+pub use checkouts::*;
+pub use users::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepositoryError {
+    #[error("record not found")]
+    NotFound,
+    /// The write would violate a uniqueness rule or a business constraint.
+    #[error("conflicts with existing data")]
+    Conflict,
+    #[error(transparent)]
+    Database(sqlx::Error),
+}
+
+pub type RepositoryResult<T> = Result<T, RepositoryError>;
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound,
+            sqlx::Error::Database(db_err) if db_err.constraint().is_some() => {
+                RepositoryError::Conflict
+            }
+            err => RepositoryError::Database(err),
+        }
+    }
+}
+
+impl From<RepositoryError> for StatusCode {
+    fn from(err: RepositoryError) -> Self {
+        match err {
+            RepositoryError::NotFound => StatusCode::NOT_FOUND,
+            RepositoryError::Conflict => StatusCode::CONFLICT,
+            RepositoryError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
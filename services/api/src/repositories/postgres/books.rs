@@ -0,0 +1,318 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        Book, BookCopy, BookSearchQuery, CopyCondition, CreateBookRequest, CreateCopyRequest,
+        UpdateBookRequest,
+    },
+    repositories::{BookRepository, RepositoryError, RepositoryResult},
+    services::{
+        audit::{self, AuditContext, AuditEvent},
+        inventory,
+    },
+};
+
+pub(crate) fn push_book_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a BookSearchQuery,
+) {
+    builder.push(" WHERE deleted_at IS NULL");
+
+    if let Some(ref q) = query.query {
+        let pattern = format!("%{q}%");
+        builder
+            .push(" AND (title ILIKE ")
+            .push_bind(pattern.clone())
+            .push(" OR author ILIKE ")
+            .push_bind(pattern)
+            .push(")");
+    }
+    if let Some(ref isbn) = query.isbn {
+        builder.push(" AND isbn = ").push_bind(isbn);
+    }
+    if let Some(ref author) = query.author {
+        builder
+            .push(" AND author ILIKE ")
+            .push_bind(format!("%{author}%"));
+    }
+    if let Some(ref genre) = query.genre {
+        builder.push(" AND genre = ").push_bind(genre);
+    }
+}
+
+#[derive(Clone)]
+pub struct PgBookRepository {
+    db: PgPool,
+}
+
+impl PgBookRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl BookRepository for PgBookRepository {
+    async fn count(&self, query: &BookSearchQuery) -> RepositoryResult<i64> {
+        let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM books");
+        push_book_filters(&mut count_query, query);
+
+        Ok(count_query.build_query_scalar().fetch_one(&self.db).await?)
+    }
+
+    async fn list(
+        &self,
+        query: &BookSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<Book>> {
+        let mut books_query = QueryBuilder::<Postgres>::new("SELECT * FROM books");
+        push_book_filters(&mut books_query, query);
+        books_query
+            .push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        Ok(books_query
+            .build_query_as::<Book>()
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<Book>> {
+        Ok(
+            sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = $1 AND deleted_at IS NULL")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn find_by_isbn(&self, isbn: &str) -> RepositoryResult<Option<Book>> {
+        Ok(
+            sqlx::query_as::<_, Book>("SELECT * FROM books WHERE isbn = $1 AND deleted_at IS NULL")
+                .bind(isbn)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn create(&self, req: CreateBookRequest, ctx: &AuditContext) -> RepositoryResult<Book> {
+        let total_copies = req.total_copies.unwrap_or(1);
+
+        let mut tx = self.db.begin().await?;
+
+        let book = sqlx::query_as::<_, Book>(
+            r#"
+            INSERT INTO books (id, isbn, title, author, publisher, published_year, genre, description, cover_url, total_copies, available_copies)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 0, 0)
+            RETURNING *
+            "#
+        )
+        .bind(Uuid::new_v4())
+        .bind(req.isbn)
+        .bind(req.title)
+        .bind(req.author)
+        .bind(req.publisher)
+        .bind(req.published_year)
+        .bind(req.genre)
+        .bind(req.description)
+        .bind(req.cover_url)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for _ in 0..total_copies {
+            inventory::insert_copy(
+                &mut *tx,
+                book.id,
+                None,
+                req.shelf_location.clone(),
+                CopyCondition::default(),
+            )
+            .await?;
+        }
+
+        let book = inventory::refresh_availability(&mut *tx, book.id).await?;
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("book.create", "book", book.id).after(&book),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(book)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateBookRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book> {
+        let mut tx = self.db.begin().await?;
+
+        let existing_book = sqlx::query_as::<_, Book>(
+            "SELECT * FROM books WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let book = sqlx::query_as::<_, Book>(
+            r#"
+            UPDATE books
+            SET title = COALESCE($2, title),
+                author = COALESCE($3, author),
+                publisher = COALESCE($4, publisher),
+                published_year = COALESCE($5, published_year),
+                genre = COALESCE($6, genre),
+                description = COALESCE($7, description),
+                cover_url = COALESCE($8, cover_url),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(req.title)
+        .bind(req.author)
+        .bind(req.publisher)
+        .bind(req.published_year)
+        .bind(req.genre)
+        .bind(req.description)
+        .bind(req.cover_url)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("book.update", "book", book.id)
+                .before(&existing_book)
+                .after(&book),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(book)
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<Book> {
+        let mut tx = self.db.begin().await?;
+
+        let existing_book = sqlx::query_as::<_, Book>(
+            "SELECT * FROM books WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let active_checkouts: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM checkouts WHERE book_id = $1 AND status = 'ACTIVE'",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if active_checkouts > 0 && !force {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let book = sqlx::query_as::<_, Book>(
+            "UPDATE books SET deleted_at = $2, updated_at = $2 WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let action = if active_checkouts > 0 {
+            "book.force_delete"
+        } else {
+            "book.delete"
+        };
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new(action, "book", book.id)
+                .before(&existing_book)
+                .after(&book),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(book)
+    }
+
+    async fn list_copies(&self, book_id: Uuid) -> RepositoryResult<Vec<BookCopy>> {
+        let book_exists: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM books WHERE id = $1 AND deleted_at IS NULL)",
+        )
+        .bind(book_id)
+        .fetch_one(&self.db)
+        .await?;
+
+        if !book_exists {
+            return Err(RepositoryError::NotFound);
+        }
+
+        Ok(sqlx::query_as::<_, BookCopy>(
+            "SELECT * FROM book_copies WHERE book_id = $1 ORDER BY barcode",
+        )
+        .bind(book_id)
+        .fetch_all(&self.db)
+        .await?)
+    }
+
+    async fn add_copy(
+        &self,
+        book_id: Uuid,
+        req: CreateCopyRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<BookCopy> {
+        let mut tx = self.db.begin().await?;
+
+        sqlx::query("SELECT id FROM books WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+            .bind(book_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let copy = inventory::insert_copy(
+            &mut *tx,
+            book_id,
+            req.barcode,
+            req.shelf_location,
+            req.condition.unwrap_or_default(),
+        )
+        .await?;
+
+        inventory::refresh_availability(&mut *tx, book_id).await?;
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("copy.create", "copy", copy.id).after(&copy),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(copy)
+    }
+}
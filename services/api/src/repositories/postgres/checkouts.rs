@@ -0,0 +1,327 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgRow, PgPool, Postgres, QueryBuilder, Row, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    models::{Book, BookCopy, Checkout, CheckoutBook, CheckoutUser, CheckoutWithDetails, User},
+    repositories::{
+        CheckoutFilter, CheckoutRepository, CheckoutTransaction, NewCheckout, RepositoryResult,
+    },
+    services::{
+        audit::{self, AuditContext, AuditEvent},
+        inventory,
+    },
+};
+
+/// Checkouts joined with the borrower and the book, as read by `details_from_row`.
+pub(crate) const DETAILS_SELECT: &str = r#"
+    SELECT
+        c.*,
+        u.name as user_name, u.email as user_email,
+        b.title as book_title, b.author as book_author, b.isbn as book_isbn
+    FROM checkouts c
+    JOIN users u ON c.user_id = u.id
+    JOIN books b ON c.book_id = b.id
+"#;
+
+pub(crate) fn details_from_row(row: &PgRow) -> sqlx::Result<CheckoutWithDetails> {
+    Ok(CheckoutWithDetails {
+        checkout: Checkout {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            book_id: row.try_get("book_id")?,
+            copy_id: row.try_get("copy_id")?,
+            status: row.try_get("status")?,
+            checked_out_at: row.try_get("checked_out_at")?,
+            due_date: row.try_get("due_date")?,
+            returned_at: row.try_get("returned_at")?,
+            renewal_count: row.try_get("renewal_count")?,
+            max_renewals: row.try_get("max_renewals")?,
+            overdue_email_sent: row.try_get("overdue_email_sent")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        },
+        user: CheckoutUser {
+            id: row.try_get("user_id")?,
+            name: row.try_get("user_name")?,
+            email: row.try_get("user_email")?,
+        },
+        book: CheckoutBook {
+            id: row.try_get("book_id")?,
+            title: row.try_get("book_title")?,
+            author: row.try_get("book_author")?,
+            isbn: row.try_get("book_isbn")?,
+        },
+    })
+}
+
+pub(crate) fn push_checkout_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    filter: &'a CheckoutFilter,
+) {
+    builder.push(" WHERE TRUE");
+
+    if let Some(user_id) = filter.user_id {
+        builder.push(" AND c.user_id = ").push_bind(user_id);
+    }
+    if let Some(book_id) = filter.book_id {
+        builder.push(" AND c.book_id = ").push_bind(book_id);
+    }
+    if let Some(ref status) = filter.status {
+        builder.push(" AND c.status = ").push_bind(status);
+    }
+    if let Some(overdue_at) = filter.overdue_at {
+        builder
+            .push(" AND c.status = 'ACTIVE' AND c.due_date < ")
+            .push_bind(overdue_at);
+    }
+}
+
+#[derive(Clone)]
+pub struct PgCheckoutRepository {
+    db: PgPool,
+}
+
+impl PgCheckoutRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl CheckoutRepository for PgCheckoutRepository {
+    async fn begin(&self) -> RepositoryResult<Box<dyn CheckoutTransaction>> {
+        let tx = self.db.begin().await?;
+        Ok(Box::new(PgCheckoutTransaction { tx }))
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<CheckoutWithDetails>> {
+        sqlx::query(&format!("{DETAILS_SELECT} WHERE c.id = $1"))
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?
+            .map(|row| details_from_row(&row))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn count(&self, filter: &CheckoutFilter) -> RepositoryResult<i64> {
+        let mut query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM checkouts c");
+        push_checkout_filters(&mut query, filter);
+
+        Ok(query.build_query_scalar().fetch_one(&self.db).await?)
+    }
+
+    async fn list(
+        &self,
+        filter: &CheckoutFilter,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>> {
+        let mut query = QueryBuilder::<Postgres>::new(DETAILS_SELECT);
+        push_checkout_filters(&mut query, filter);
+        query
+            .push(" ORDER BY c.created_at DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        query
+            .build()
+            .fetch_all(&self.db)
+            .await?
+            .iter()
+            .map(details_from_row)
+            .collect::<sqlx::Result<_>>()
+            .map_err(Into::into)
+    }
+
+    async fn list_overdue(
+        &self,
+        as_of: DateTime<Utc>,
+    ) -> RepositoryResult<Vec<CheckoutWithDetails>> {
+        sqlx::query(&format!(
+            "{DETAILS_SELECT} WHERE c.due_date < $1 AND c.status = 'ACTIVE' ORDER BY c.due_date ASC"
+        ))
+        .bind(as_of)
+        .fetch_all(&self.db)
+        .await?
+        .iter()
+        .map(details_from_row)
+        .collect::<sqlx::Result<_>>()
+        .map_err(Into::into)
+    }
+}
+
+pub struct PgCheckoutTransaction {
+    tx: Transaction<'static, Postgres>,
+}
+
+#[async_trait]
+impl CheckoutTransaction for PgCheckoutTransaction {
+    async fn lock_user(&mut self, user_id: Uuid) -> RepositoryResult<Option<User>> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn lock_book(&mut self, book_id: Uuid) -> RepositoryResult<Option<Book>> {
+        sqlx::query_as::<_, Book>("SELECT * FROM books WHERE id = $1 FOR UPDATE")
+            .bind(book_id)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn find_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        sqlx::query_as::<_, BookCopy>("SELECT * FROM book_copies WHERE id = $1")
+            .bind(copy_id)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn find_copy_by_barcode(&mut self, barcode: &str) -> RepositoryResult<Option<BookCopy>> {
+        sqlx::query_as::<_, BookCopy>("SELECT * FROM book_copies WHERE barcode = $1")
+            .bind(barcode)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn find_book_id_by_isbn(&mut self, isbn: &str) -> RepositoryResult<Option<Uuid>> {
+        sqlx::query_scalar("SELECT id FROM books WHERE isbn = $1 AND deleted_at IS NULL")
+            .bind(isbn)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn count_active_checkouts(&mut self, user_id: Uuid) -> RepositoryResult<i64> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM checkouts WHERE user_id = $1 AND status = 'ACTIVE'",
+        )
+        .bind(user_id)
+        .fetch_one(&mut *self.tx)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn claim_copy(&mut self, copy_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        Ok(inventory::claim_copy(&mut *self.tx, copy_id).await?)
+    }
+
+    async fn claim_available_copy(&mut self, book_id: Uuid) -> RepositoryResult<Option<BookCopy>> {
+        Ok(inventory::claim_available_copy(&mut *self.tx, book_id).await?)
+    }
+
+    async fn release_copy(&mut self, copy_id: Uuid) -> RepositoryResult<()> {
+        Ok(inventory::release_copy(&mut *self.tx, copy_id).await?)
+    }
+
+    async fn refresh_availability(&mut self, book_id: Uuid) -> RepositoryResult<Book> {
+        Ok(inventory::refresh_availability(&mut *self.tx, book_id).await?)
+    }
+
+    async fn insert_checkout(&mut self, checkout: NewCheckout) -> RepositoryResult<Checkout> {
+        sqlx::query_as::<_, Checkout>(
+            r#"
+            INSERT INTO checkouts (id, user_id, book_id, copy_id, checked_out_at, due_date)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(checkout.id)
+        .bind(checkout.user_id)
+        .bind(checkout.book_id)
+        .bind(checkout.copy_id)
+        .bind(checkout.checked_out_at)
+        .bind(checkout.due_date)
+        .fetch_one(&mut *self.tx)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn active_checkout_book_id(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<Uuid>> {
+        sqlx::query_scalar("SELECT book_id FROM checkouts WHERE id = $1 AND status = 'ACTIVE'")
+            .bind(checkout_id)
+            .fetch_optional(&mut *self.tx)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn lock_active_checkout(
+        &mut self,
+        checkout_id: Uuid,
+    ) -> RepositoryResult<Option<CheckoutWithDetails>> {
+        sqlx::query(&format!(
+            "{DETAILS_SELECT} WHERE c.id = $1 AND c.status = 'ACTIVE' FOR UPDATE OF c"
+        ))
+        .bind(checkout_id)
+        .fetch_optional(&mut *self.tx)
+        .await?
+        .map(|row| details_from_row(&row))
+        .transpose()
+        .map_err(Into::into)
+    }
+
+    async fn mark_returned(
+        &mut self,
+        checkout_id: Uuid,
+        returned_at: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout> {
+        sqlx::query_as::<_, Checkout>(
+            r#"
+            UPDATE checkouts
+            SET status = 'RETURNED', returned_at = $2, updated_at = $2
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(checkout_id)
+        .bind(returned_at)
+        .fetch_one(&mut *self.tx)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn renew(
+        &mut self,
+        checkout_id: Uuid,
+        due_date: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> RepositoryResult<Checkout> {
+        sqlx::query_as::<_, Checkout>(
+            r#"
+            UPDATE checkouts
+            SET due_date = $2, renewal_count = renewal_count + 1, updated_at = $3
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(checkout_id)
+        .bind(due_date)
+        .bind(now)
+        .fetch_one(&mut *self.tx)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn record_audit(
+        &mut self,
+        ctx: &AuditContext,
+        event: AuditEvent,
+    ) -> RepositoryResult<()> {
+        Ok(audit::record(&mut *self.tx, ctx, event).await?)
+    }
+
+    async fn commit(self: Box<Self>) -> RepositoryResult<()> {
+        Ok(self.tx.commit().await?)
+    }
+}
@@ -0,0 +1,7 @@
+pub mod books;
+pub mod checkouts;
+pub mod users;
+
+pub use books::PgBookRepository;
+pub use checkouts::PgCheckoutRepository;
+pub use users::PgUserRepository;
@@ -0,0 +1,242 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::{
+    models::{UpdateUserRequest, User, UserSearchQuery},
+    repositories::{NewUser, RepositoryError, RepositoryResult, UserRepository},
+    services::audit::{self, AuditContext, AuditEvent},
+};
+
+pub(crate) fn push_user_filters<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    query: &'a UserSearchQuery,
+) {
+    builder.push(" WHERE deleted_at IS NULL");
+
+    if let Some(ref q) = query.query {
+        let pattern = format!("%{q}%");
+        builder
+            .push(" AND (name ILIKE ")
+            .push_bind(pattern.clone())
+            .push(" OR email ILIKE ")
+            .push_bind(pattern)
+            .push(")");
+    }
+    if let Some(ref role) = query.role {
+        builder.push(" AND role = ").push_bind(role);
+    }
+    if let Some(is_active) = query.is_active {
+        builder.push(" AND is_active = ").push_bind(is_active);
+    }
+}
+
+#[derive(Clone)]
+pub struct PgUserRepository {
+    db: PgPool,
+}
+
+impl PgUserRepository {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgUserRepository {
+    async fn count(&self, query: &UserSearchQuery) -> RepositoryResult<i64> {
+        let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users");
+        push_user_filters(&mut count_query, query);
+
+        Ok(count_query.build_query_scalar().fetch_one(&self.db).await?)
+    }
+
+    async fn list(
+        &self,
+        query: &UserSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<User>> {
+        let mut users_query = QueryBuilder::<Postgres>::new("SELECT * FROM users");
+        push_user_filters(&mut users_query, query);
+        users_query
+            .push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        Ok(users_query
+            .build_query_as::<User>()
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn find_including_deleted(&self, id: Uuid) -> RepositoryResult<Option<User>> {
+        Ok(
+            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.db)
+                .await?,
+        )
+    }
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>> {
+        Ok(
+            sqlx::query_as::<_, User>(
+                "SELECT * FROM users WHERE email = $1 AND deleted_at IS NULL",
+            )
+            .bind(email)
+            .fetch_optional(&self.db)
+            .await?,
+        )
+    }
+
+    async fn create(
+        &self,
+        user: NewUser,
+        action: &'static str,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut tx = self.db.begin().await?;
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            INSERT INTO users (id, email, name, role, max_checkouts)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(user.id)
+        .bind(user.email)
+        .bind(user.name)
+        .bind(user.role)
+        .bind(user.max_checkouts)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new(action, "user", user.id).after(&user),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateUserRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut tx = self.db.begin().await?;
+
+        let existing_user = sqlx::query_as::<_, User>(
+            "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users
+            SET name = COALESCE($2, name),
+                role = COALESCE($3, role),
+                is_active = COALESCE($4, is_active),
+                max_checkouts = COALESCE($5, max_checkouts),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(req.name)
+        .bind(req.role)
+        .bind(req.is_active)
+        .bind(req.max_checkouts)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("user.update", "user", user.id)
+                .before(&existing_user)
+                .after(&user),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User> {
+        let mut tx = self.db.begin().await?;
+
+        let existing_user = sqlx::query_as::<_, User>(
+            "SELECT * FROM users WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let active_checkouts: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM checkouts WHERE user_id = $1 AND status = 'ACTIVE'",
+        )
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if active_checkouts > 0 && !force {
+            return Err(RepositoryError::Conflict);
+        }
+
+        let user = sqlx::query_as::<_, User>(
+            "UPDATE users SET deleted_at = $2, updated_at = $2 WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let action = if active_checkouts > 0 {
+            "user.force_delete"
+        } else {
+            "user.delete"
+        };
+
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new(action, "user", user.id)
+                .before(&existing_user)
+                .after(&user),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+}
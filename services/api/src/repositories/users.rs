@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    models::{UpdateUserRequest, User, UserRole, UserSearchQuery},
+    repositories::RepositoryResult,
+    services::audit::AuditContext,
+};
+
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub role: UserRole,
+    pub max_checkouts: i32,
+}
+
+/// Patron storage. Reads only see live (not deleted) users unless noted; every write is
+/// audited in the same transaction.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn count(&self, query: &UserSearchQuery) -> RepositoryResult<i64>;
+
+    /// Newest first.
+    async fn list(
+        &self,
+        query: &UserSearchQuery,
+        limit: i64,
+        offset: i64,
+    ) -> RepositoryResult<Vec<User>>;
+
+    async fn find(&self, id: Uuid) -> RepositoryResult<Option<User>>;
+
+    /// Like `find`, but also returns soft-deleted users.
+    async fn find_including_deleted(&self, id: Uuid) -> RepositoryResult<Option<User>>;
+
+    async fn find_by_email(&self, email: &str) -> RepositoryResult<Option<User>>;
+
+    /// Conflict if the email is taken. Audited as `action`, e.g. `user.create`.
+    async fn create(
+        &self,
+        user: NewUser,
+        action: &'static str,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User>;
+
+    async fn update(
+        &self,
+        id: Uuid,
+        req: UpdateUserRequest,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User>;
+
+    /// Soft-deletes the user. Conflict if they have books out, unless `force` is set.
+    async fn delete(
+        &self,
+        id: Uuid,
+        force: bool,
+        now: DateTime<Utc>,
+        ctx: &AuditContext,
+    ) -> RepositoryResult<User>;
+}
@@ -0,0 +1,264 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderMap},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{
+    middleware::{auth::caller_id, rate_limit::TrustedProxies},
+    AppState,
+};
+
+/// Who performed a request and where it came from, captured for the audit log.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub actor_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let actor_id = caller_id(
+            &state.config.auth.service_url,
+            &parts.extensions,
+            &parts.headers,
+        )
+        .await;
+
+        let peer_addr = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+
+        Ok(Self::new(
+            actor_id,
+            &parts.headers,
+            peer_addr,
+            &state.trusted_proxies,
+        ))
+    }
+}
+
+impl AuditContext {
+    /// The client address is the peer's, or what a trusted proxy says in `X-Forwarded-For`.
+    pub fn new(
+        actor_id: Option<Uuid>,
+        headers: &HeaderMap,
+        peer_addr: Option<SocketAddr>,
+        proxies: &TrustedProxies,
+    ) -> Self {
+        let ip_address = proxies
+            .client_ip(peer_addr.map(|addr| addr.ip()), headers)
+            .map(|ip| ip.to_string());
+
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+
+        Self {
+            actor_id,
+            ip_address,
+            user_agent,
+        }
+    }
+}
+
+/// A single mutation to be appended to `audit_log`.
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub action: &'static str,
+    pub entity_type: &'static str,
+    pub entity_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+impl AuditEvent {
+    pub fn new(action: &'static str, entity_type: &'static str, entity_id: Uuid) -> Self {
+        Self {
+            action,
+            entity_type,
+            entity_id,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn before<T: Serialize>(mut self, snapshot: &T) -> Self {
+        self.before = serde_json::to_value(snapshot).ok();
+        self
+    }
+
+    pub fn after<T: Serialize>(mut self, snapshot: &T) -> Self {
+        self.after = serde_json::to_value(snapshot).ok();
+        self
+    }
+}
+
+pub async fn record<'e, E>(executor: E, ctx: &AuditContext, event: AuditEvent) -> sqlx::Result<()>
+where
+    E: PgExecutor<'e>,
+{
+    let (before, after) = match (event.before, event.after) {
+        (Some(before), Some(after)) => {
+            let (before, after) = diff(before, after);
+            (Some(before), Some(after))
+        }
+        other => other,
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log (id, actor_id, action, entity_type, entity_id, before, after, ip_address, user_agent)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(ctx.actor_id)
+    .bind(event.action)
+    .bind(event.entity_type)
+    .bind(event.entity_id)
+    .bind(before)
+    .bind(after)
+    .bind(&ctx.ip_address)
+    .bind(&ctx.user_agent)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Reduces two object snapshots to the fields that actually changed.
+fn diff(before: Value, after: Value) -> (Value, Value) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            let mut changed_before = Map::new();
+            let mut changed_after = Map::new();
+
+            for (key, old) in &before {
+                match after.get(key) {
+                    Some(new) if new == old => {}
+                    Some(new) => {
+                        changed_before.insert(key.clone(), old.clone());
+                        changed_after.insert(key.clone(), new.clone());
+                    }
+                    None => {
+                        changed_before.insert(key.clone(), old.clone());
+                    }
+                }
+            }
+
+            for (key, new) in after {
+                if !before.contains_key(&key) {
+                    changed_after.insert(key, new);
+                }
+            }
+
+            (Value::Object(changed_before), Value::Object(changed_after))
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_forwarded_for_is_believed_only_from_trusted_proxies() {
+        let proxies = TrustedProxies::from_config(&["10.0.0.0/8".to_string()]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.1, 203.0.113.7".parse().unwrap(),
+        );
+        let ip = |peer: &str| {
+            let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+            AuditContext::new(None, &headers, Some(peer), &proxies).ip_address
+        };
+
+        assert_eq!(ip("10.0.0.2").as_deref(), Some("203.0.113.7"));
+        assert_eq!(ip("192.0.2.9").as_deref(), Some("192.0.2.9"));
+        assert_eq!(
+            AuditContext::new(None, &headers, None, &proxies).ip_address,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_recorded_and_cannot_be_rewritten() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.sign_in(&fixtures.admin);
+
+        let (status, _) = app
+            .request(
+                Method::PUT,
+                &format!("/api/books/{}", fixtures.dune.id),
+                Some(json!({ "title": "Dune Messiah" })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (actor_id, before, after): (Option<Uuid>, Value, Value) = sqlx::query_as(
+            "SELECT actor_id, before, after FROM audit_log \
+             WHERE action = 'book.update' AND entity_id = $1",
+        )
+        .bind(fixtures.dune.id)
+        .fetch_one(app.pool())
+        .await
+        .unwrap();
+        assert_eq!(actor_id, Some(fixtures.admin.id));
+        assert_eq!(before["title"], "Dune");
+        assert_eq!(after["title"], "Dune Messiah");
+        assert!(after.get("author").is_none());
+
+        let update = sqlx::query("UPDATE audit_log SET actor_id = NULL")
+            .execute(app.pool())
+            .await;
+        assert!(update.is_err());
+        let delete = sqlx::query("DELETE FROM audit_log WHERE entity_id = $1")
+            .bind(fixtures.dune.id)
+            .execute(app.pool())
+            .await;
+        assert!(delete.is_err());
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM audit_log WHERE entity_id = $1")
+                .bind(fixtures.dune.id)
+                .fetch_one(app.pool())
+                .await
+                .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_diff_keeps_only_changed_fields() {
+        let before = json!({ "title": "Dune", "available_copies": 2, "genre": null });
+        let after = json!({ "title": "Dune", "available_copies": 1, "genre": "SF" });
+
+        let (before, after) = diff(before, after);
+
+        assert_eq!(before, json!({ "available_copies": 2, "genre": null }));
+        assert_eq!(after, json!({ "available_copies": 1, "genre": "SF" }));
+    }
+}
@@ -0,0 +1,131 @@
+//! CSV uploads: a header row naming the columns, then one record per row. A row is either a
+//! book with `copies` copies, or one copy of it identified by `barcode`; rows repeating an
+//! ISBN are merged, so a file can list a book once per copy.
+
+use ::csv::{ReaderBuilder, Trim};
+
+use super::{ParseError, RawHolding, RawRecord};
+
+const COLUMNS: [&str; 12] = [
+    "isbn",
+    "title",
+    "author",
+    "publisher",
+    "published_year",
+    "genre",
+    "description",
+    "cover_url",
+    "copies",
+    "barcode",
+    "shelf_location",
+    "condition",
+];
+
+pub fn parse(data: &[u8]) -> Result<Vec<Result<RawRecord, String>>, ParseError> {
+    let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(data);
+
+    let headers = reader
+        .headers()
+        .map_err(|e| ParseError(format!("unreadable header row: {e}")))?
+        .iter()
+        .map(|name| name.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    let unknown = headers
+        .iter()
+        .filter(|name| !COLUMNS.contains(&name.as_str()))
+        .map(|name| format!("`{name}`"))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(ParseError(format!(
+            "unknown columns {} (expected some of {})",
+            unknown.join(", "),
+            COLUMNS.join(", ")
+        )));
+    }
+    if !headers.iter().any(|name| name == "isbn") {
+        return Err(ParseError(
+            "the header row has no `isbn` column".to_string(),
+        ));
+    }
+
+    let records = reader
+        .records()
+        .map(|row| {
+            let row = row.map_err(|e| format!("unreadable row: {e}"))?;
+            let field = |column: &str| {
+                headers
+                    .iter()
+                    .position(|name| name == column)
+                    .and_then(|i| row.get(i))
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_owned)
+            };
+
+            let holding = RawHolding {
+                barcode: field("barcode"),
+                shelf_location: field("shelf_location"),
+                condition: field("condition"),
+            };
+            // Without a barcode, the shelf and condition describe the copies `copies` adds.
+            let (holdings, defaults) = if holding.barcode.is_some() {
+                (vec![holding], RawHolding::default())
+            } else {
+                (Vec::new(), holding)
+            };
+
+            Ok(RawRecord {
+                isbn: field("isbn"),
+                title: field("title"),
+                author: field("author"),
+                publisher: field("publisher"),
+                published_year: field("published_year"),
+                genre: field("genre"),
+                description: field("description"),
+                cover_url: field("cover_url"),
+                copies: field("copies"),
+                holdings,
+                defaults,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if records.is_empty() {
+        return Err(ParseError("the file has no rows".to_string()));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let data = "ISBN,title,author,copies,barcode,shelf_location\n\
+            978-0-441-01359-3,Dune,Frank Herbert,2,,SF HER\n\
+            9780441013593,,,,B-0001,SF HER\n\
+            9780141439587,\"Emma, a novel\",Jane Austen\n";
+        let records = parse(data.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 3);
+        let dune = records[0].as_ref().unwrap();
+        assert_eq!(dune.isbn.as_deref(), Some("978-0-441-01359-3"));
+        assert_eq!(dune.copies.as_deref(), Some("2"));
+        assert!(dune.holdings.is_empty());
+        assert_eq!(dune.defaults.shelf_location.as_deref(), Some("SF HER"));
+
+        let copy = records[1].as_ref().unwrap();
+        assert_eq!(copy.title, None);
+        assert_eq!(copy.holdings[0].barcode.as_deref(), Some("B-0001"));
+
+        // Short rows are an error for that row only.
+        assert!(records[2].is_err());
+    }
+
+    #[test]
+    fn test_csv_header_is_checked() {
+        assert!(parse(b"isbn,titel\n9780441013593,Dune\n").is_err());
+        assert!(parse(b"title,author\nDune,Frank Herbert\n").is_err());
+        assert!(parse(b"isbn,title\n").is_err());
+    }
+}
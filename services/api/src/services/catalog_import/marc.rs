@@ -0,0 +1,407 @@
+//! MARC 21 bibliographic records, as ISO 2709 binary or MARCXML, and how their fields map
+//! onto books. Holdings in 852 (or Koha's 952) fields become copies.
+
+use quick_xml::{events::Event, Reader};
+
+use super::{ParseError, RawHolding, RawRecord};
+
+const SUBFIELD_DELIMITER: u8 = 0x1F;
+const FIELD_TERMINATOR: u8 = 0x1E;
+const RECORD_TERMINATOR: u8 = 0x1D;
+const LEADER_LENGTH: usize = 24;
+
+#[derive(Debug, Default)]
+struct MarcRecord {
+    control_fields: Vec<(String, String)>,
+    data_fields: Vec<DataField>,
+}
+
+#[derive(Debug, Default)]
+struct DataField {
+    tag: String,
+    subfields: Vec<(char, String)>,
+}
+
+impl DataField {
+    fn subfield(&self, code: char) -> Option<&str> {
+        self.subfields
+            .iter()
+            .find(|(c, value)| *c == code && !value.trim().is_empty())
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl MarcRecord {
+    fn fields<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a DataField> + 'a {
+        self.data_fields
+            .iter()
+            .filter(move |field| field.tag == tag)
+    }
+
+    /// The first of `tags`, in order of preference, with subfield `code`.
+    fn first<'a>(&'a self, tags: &[&'a str], code: char) -> Option<&'a str> {
+        tags.iter()
+            .flat_map(|tag| self.fields(tag))
+            .find_map(|field| field.subfield(code))
+    }
+
+    fn into_raw(self) -> RawRecord {
+        // 020 $a may carry a qualifier, as in `9780441013593 (pbk.)`.
+        let isbn = self
+            .first(&["020"], 'a')
+            .and_then(|isbn| isbn.split_whitespace().next())
+            .map(str::to_owned);
+
+        let title = self.fields("245").next().and_then(|field| {
+            let title = field.subfield('a')?;
+            Some(match field.subfield('b') {
+                Some(subtitle) => format!("{}: {}", clean(title), subtitle),
+                None => title.to_string(),
+            })
+        });
+
+        let published_year = self
+            .first(&["264", "260"], 'c')
+            .and_then(year)
+            .or_else(|| {
+                // 008/07-10 is the first date of publication.
+                self.control_fields
+                    .iter()
+                    .find(|(tag, _)| tag == "008")
+                    .and_then(|(_, value)| value.get(7..11))
+                    .and_then(year)
+            })
+            .map(str::to_owned);
+
+        let holdings = self
+            .fields("852")
+            .map(|field| RawHolding {
+                barcode: field.subfield('p').map(str::to_owned),
+                shelf_location: call_number(field, &['h', 'i']),
+                condition: None,
+            })
+            .chain(self.fields("952").map(|field| RawHolding {
+                barcode: field.subfield('p').map(str::to_owned),
+                shelf_location: call_number(field, &['o']),
+                condition: None,
+            }))
+            .collect();
+
+        RawRecord {
+            isbn,
+            title: title.as_deref().map(clean),
+            author: self.first(&["100", "110", "111", "700"], 'a').map(clean),
+            publisher: self.first(&["264", "260"], 'b').map(clean),
+            published_year,
+            genre: self.first(&["655", "650"], 'a').map(clean),
+            description: self.first(&["520"], 'a').map(str::to_owned),
+            cover_url: None,
+            copies: None,
+            holdings,
+            defaults: RawHolding::default(),
+        }
+    }
+}
+
+/// Drops the ISBD punctuation that separates MARC subfields, as in `Dune /` or
+/// `Herbert, Frank,`. A closing full stop goes too, unless it ends an initial.
+fn clean(value: &str) -> String {
+    let value = value.trim_end_matches([' ', '/', ':', ';', ',', '=']);
+    match value.strip_suffix('.') {
+        Some(rest)
+            if rest
+                .rsplit(' ')
+                .next()
+                .is_none_or(|word| word.chars().count() > 1) =>
+        {
+            rest.to_string()
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// The first four-digit run, as in `c1965.` or `[1990?]`.
+fn year(value: &str) -> Option<&str> {
+    let start = value.find(|c: char| c.is_ascii_digit())?;
+    value
+        .get(start..start + 4)
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn call_number(field: &DataField, codes: &[char]) -> Option<String> {
+    let parts = codes
+        .iter()
+        .filter_map(|code| field.subfield(*code))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Parses ISO 2709 records. A damaged record is an error for that record only, since the
+/// record terminator lets the next one be found regardless.
+pub fn parse_iso2709(data: &[u8]) -> Result<Vec<Result<RawRecord, String>>, ParseError> {
+    let records = data
+        .split(|byte| *byte == RECORD_TERMINATOR)
+        .filter(|record| !record.iter().all(u8::is_ascii_whitespace))
+        .map(|record| parse_record(record).map(MarcRecord::into_raw))
+        .collect::<Vec<_>>();
+
+    if records.is_empty() {
+        return Err(ParseError("the file has no MARC records".to_string()));
+    }
+    if records.iter().all(Result::is_err) {
+        return Err(ParseError(
+            "the file is not ISO 2709 MARC; none of its records could be read".to_string(),
+        ));
+    }
+    Ok(records)
+}
+
+fn parse_record(record: &[u8]) -> Result<MarcRecord, String> {
+    // Files often separate records with line breaks.
+    let start = record
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(0);
+    let record = &record[start..];
+
+    let leader = record
+        .get(..LEADER_LENGTH)
+        .ok_or("the record is shorter than its leader")?;
+    let base_address = std::str::from_utf8(&leader[12..17])
+        .ok()
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .filter(|base| *base > LEADER_LENGTH && *base <= record.len())
+        .ok_or("the leader has no valid base address")?;
+
+    // The directory ends with a field terminator just before the base address.
+    let directory = &record[LEADER_LENGTH..base_address - 1];
+    if !directory.len().is_multiple_of(12) {
+        return Err("the directory is malformed".to_string());
+    }
+
+    let mut marc = MarcRecord::default();
+    for entry in directory.chunks(12) {
+        let entry = std::str::from_utf8(entry).map_err(|_| "the directory is malformed")?;
+        let (tag, length, offset) = (&entry[..3], &entry[3..7], &entry[7..]);
+        let (Ok(length), Ok(offset)) = (length.parse::<usize>(), offset.parse::<usize>()) else {
+            return Err(format!("the directory entry for {tag} is malformed"));
+        };
+        let data = record
+            .get(base_address + offset..base_address + offset + length)
+            .ok_or_else(|| format!("field {tag} runs past the end of the record"))?;
+        let data = data.strip_suffix(&[FIELD_TERMINATOR]).unwrap_or(data);
+
+        if tag < "010" {
+            let value = String::from_utf8_lossy(data).into_owned();
+            marc.control_fields.push((tag.to_string(), value));
+            continue;
+        }
+
+        // Two indicators, then subfields that each start with the delimiter and a code.
+        let subfields = data
+            .get(2..)
+            .unwrap_or_default()
+            .split(|byte| *byte == SUBFIELD_DELIMITER)
+            .skip(1)
+            .filter_map(|subfield| {
+                let (code, value) = subfield.split_first()?;
+                Some((*code as char, String::from_utf8_lossy(value).into_owned()))
+            })
+            .collect();
+        marc.data_fields.push(DataField {
+            tag: tag.to_string(),
+            subfields,
+        });
+    }
+    Ok(marc)
+}
+
+/// Parses a MARCXML `<collection>`, or a single `<record>`, with or without a namespace
+/// prefix. Malformed XML rejects the whole file.
+pub fn parse_marcxml(data: &[u8]) -> Result<Vec<Result<RawRecord, String>>, ParseError> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().trim_text(true);
+
+    let mut records = Vec::new();
+    let mut record: Option<MarcRecord> = None;
+    let mut control_tag: Option<String> = None;
+    let mut subfield_code: Option<char> = None;
+    let mut text = String::new();
+    let mut buf = Vec::new();
+
+    let malformed = |reader: &Reader<&[u8]>, e: &dyn std::fmt::Display| {
+        ParseError(format!(
+            "not valid MARCXML at byte {}: {e}",
+            reader.buffer_position()
+        ))
+    };
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| malformed(&reader, &e))?;
+        match event {
+            Event::Start(element) => {
+                let attribute = |name: &[u8]| {
+                    element
+                        .attributes()
+                        .flatten()
+                        .find(|a| a.key.local_name().as_ref() == name)
+                        .and_then(|a| a.unescape_value().ok().map(|v| v.into_owned()))
+                };
+                text.clear();
+                match element.local_name().as_ref() {
+                    b"record" => record = Some(MarcRecord::default()),
+                    b"controlfield" => control_tag = attribute(b"tag"),
+                    b"datafield" => {
+                        if let (Some(record), Some(tag)) = (record.as_mut(), attribute(b"tag")) {
+                            record.data_fields.push(DataField {
+                                tag,
+                                subfields: Vec::new(),
+                            });
+                        }
+                    }
+                    b"subfield" => {
+                        subfield_code = attribute(b"code").and_then(|code| code.chars().next())
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(value) => {
+                text.push_str(&value.unescape().map_err(|e| malformed(&reader, &e))?)
+            }
+            Event::CData(value) => text.push_str(&String::from_utf8_lossy(&value)),
+            Event::End(element) => match element.local_name().as_ref() {
+                b"record" => records.extend(record.take().map(|r| Ok(r.into_raw()))),
+                b"controlfield" => {
+                    if let (Some(record), Some(tag)) = (record.as_mut(), control_tag.take()) {
+                        record.control_fields.push((tag, std::mem::take(&mut text)));
+                    }
+                }
+                b"subfield" => {
+                    let field = record.as_mut().and_then(|r| r.data_fields.last_mut());
+                    if let (Some(field), Some(code)) = (field, subfield_code.take()) {
+                        field.subfields.push((code, std::mem::take(&mut text)));
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    if records.is_empty() {
+        return Err(ParseError("the file has no MARCXML records".to_string()));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an ISO 2709 record from `(tag, field)` pairs, where a data field is its
+    /// indicators then `$`-delimited subfields.
+    fn iso2709(fields: &[(&str, &str)]) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut data = Vec::new();
+        for (tag, field) in fields {
+            let mut bytes = field.replace('$', "\u{1f}").into_bytes();
+            bytes.push(FIELD_TERMINATOR);
+            directory.extend(format!("{tag}{:04}{:05}", bytes.len(), data.len()).into_bytes());
+            data.extend(bytes);
+        }
+        directory.push(FIELD_TERMINATOR);
+
+        let base_address = LEADER_LENGTH + directory.len();
+        let length = base_address + data.len() + 1;
+        let mut record = format!("{length:05}nam a22{base_address:05} a 4500").into_bytes();
+        record.extend(directory);
+        record.extend(data);
+        record.push(RECORD_TERMINATOR);
+        record
+    }
+
+    #[test]
+    fn test_parse_iso2709() {
+        let mut data = iso2709(&[
+            ("001", "ocm123"),
+            ("008", "750101s1965    nyu           000 1 eng d"),
+            ("020", "  $a9780441013593 (pbk.)"),
+            ("100", "1 $aHerbert, Frank."),
+            ("245", "10$aDune /$cFrank Herbert."),
+            ("264", " 1$aNew York :$bAce,$c[2005]"),
+            ("650", " 0$aScience fiction."),
+            ("852", "  $hPS3558$iH4727 D8$pB-0001"),
+            ("852", "  $hPS3558$iH4727 D8$pB-0002"),
+        ]);
+        data.extend(b"\n00010nam  2200000   4500");
+        data.push(RECORD_TERMINATOR);
+
+        let records = parse_iso2709(&data).unwrap();
+        assert_eq!(records.len(), 2);
+        let dune = records[0].as_ref().unwrap();
+        assert_eq!(dune.isbn.as_deref(), Some("9780441013593"));
+        assert_eq!(dune.title.as_deref(), Some("Dune"));
+        assert_eq!(dune.author.as_deref(), Some("Herbert, Frank"));
+        assert_eq!(dune.publisher.as_deref(), Some("Ace"));
+        assert_eq!(dune.published_year.as_deref(), Some("2005"));
+        assert_eq!(dune.genre.as_deref(), Some("Science fiction"));
+        assert_eq!(dune.holdings.len(), 2);
+        assert_eq!(dune.holdings[1].barcode.as_deref(), Some("B-0002"));
+        assert_eq!(
+            dune.holdings[1].shelf_location.as_deref(),
+            Some("PS3558 H4727 D8")
+        );
+        assert!(records[1].is_err());
+
+        assert!(parse_iso2709(b"isbn,title\n9780441013593,Dune\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_marcxml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <marc:collection xmlns:marc="http://www.loc.gov/MARC21/slim">
+              <marc:record>
+                <marc:leader>00000nam a2200000 a 4500</marc:leader>
+                <marc:controlfield tag="008">750101s1815    enk           000 1 eng d</marc:controlfield>
+                <marc:datafield tag="020" ind1=" " ind2=" ">
+                  <marc:subfield code="a">0141439580</marc:subfield>
+                </marc:datafield>
+                <marc:datafield tag="100" ind1="1" ind2=" ">
+                  <marc:subfield code="a">Austen, Jane,</marc:subfield>
+                </marc:datafield>
+                <marc:datafield tag="245" ind1="1" ind2="0">
+                  <marc:subfield code="a">Emma :</marc:subfield>
+                  <marc:subfield code="b">a novel &amp; more /</marc:subfield>
+                </marc:datafield>
+                <marc:datafield tag="952" ind1=" " ind2=" ">
+                  <marc:subfield code="o">823 AUS</marc:subfield>
+                </marc:datafield>
+              </marc:record>
+            </marc:collection>"#;
+
+        let records = parse_marcxml(xml.as_bytes()).unwrap();
+        assert_eq!(records.len(), 1);
+        let emma = records[0].as_ref().unwrap();
+        assert_eq!(emma.isbn.as_deref(), Some("0141439580"));
+        assert_eq!(emma.title.as_deref(), Some("Emma: a novel & more"));
+        assert_eq!(emma.author.as_deref(), Some("Austen, Jane"));
+        assert_eq!(emma.published_year.as_deref(), Some("1815"));
+        assert_eq!(emma.holdings[0].barcode, None);
+        assert_eq!(emma.holdings[0].shelf_location.as_deref(), Some("823 AUS"));
+
+        assert!(parse_marcxml(b"<collection><record>").is_err());
+        assert!(parse_marcxml(b"<collection/>").is_err());
+    }
+
+    #[test]
+    fn test_clean_keeps_initials() {
+        assert_eq!(clean("Tolkien, J. R. R."), "Tolkien, J. R. R.");
+        assert_eq!(clean("Herbert, Frank."), "Herbert, Frank");
+        assert_eq!(clean("Dune /"), "Dune");
+    }
+}
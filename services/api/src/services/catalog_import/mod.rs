@@ -0,0 +1,930 @@
+//! Bulk catalog imports. An upload is parsed as soon as it arrives, so an unreadable file is
+//! refused outright; its records are then validated, grouped by ISBN and applied in the
+//! background, one transaction per book. Every row's outcome is stored as the import's
+//! report, and a dry run rolls each transaction back so the report shows what would change.
+
+mod csv;
+mod marc;
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Datelike, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        Book, BookCopy, BookImport, BookImportFormat, BookImportRowAction, BookImportStatus,
+        CopyCondition,
+    },
+    services::{
+        audit::{self, AuditContext, AuditEvent},
+        clock::Clock,
+        inventory,
+    },
+};
+
+/// Largest upload accepted, in bytes.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Most copies one row may ask for.
+const MAX_COPIES: i32 = 1000;
+
+/// Rows processed between progress updates.
+const FLUSH_EVERY: usize = 100;
+
+/// The whole upload is unreadable.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ParseError(pub String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("unsupported format; send text/csv, application/marc or application/marcxml+xml")]
+    UnsupportedFormat,
+    #[error(transparent)]
+    Malformed(#[from] ParseError),
+    #[error("the server is shutting down")]
+    ShuttingDown,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<ImportError> for StatusCode {
+    fn from(err: ImportError) -> Self {
+        match err {
+            ImportError::UnsupportedFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ImportError::Malformed(_) => StatusCode::BAD_REQUEST,
+            ImportError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            ImportError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// A record as read from the upload, still all text.
+#[derive(Debug, Clone, Default)]
+pub struct RawRecord {
+    pub isbn: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub publisher: Option<String>,
+    pub published_year: Option<String>,
+    pub genre: Option<String>,
+    pub description: Option<String>,
+    pub cover_url: Option<String>,
+    /// How many copies the book should have at least.
+    pub copies: Option<String>,
+    /// Particular copies, matched to existing ones by barcode.
+    pub holdings: Vec<RawHolding>,
+    /// Shelf and condition for copies added to make up `copies`.
+    pub defaults: RawHolding,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RawHolding {
+    pub barcode: Option<String>,
+    pub shelf_location: Option<String>,
+    pub condition: Option<String>,
+}
+
+/// The format named by a `Content-Type`, ignoring its parameters.
+pub fn format_for(content_type: &str) -> Option<BookImportFormat> {
+    let media_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    match media_type.as_str() {
+        "text/csv" => Some(BookImportFormat::Csv),
+        "application/marc" => Some(BookImportFormat::Marc21),
+        "application/marcxml+xml" | "application/xml" | "text/xml" => {
+            Some(BookImportFormat::Marcxml)
+        }
+        _ => None,
+    }
+}
+
+/// Reads every record of an upload. A record that can't be read is an error for that row;
+/// a file that can't be read at all is a [`ParseError`].
+pub fn parse(
+    format: BookImportFormat,
+    data: &[u8],
+) -> Result<Vec<Result<RawRecord, String>>, ParseError> {
+    match format {
+        BookImportFormat::Csv => csv::parse(data),
+        BookImportFormat::Marc21 => marc::parse_iso2709(data),
+        BookImportFormat::Marcxml => marc::parse_marcxml(data),
+    }
+}
+
+/// Normalises an ISBN-10 or ISBN-13, with or without hyphens and spaces, to ISBN-13.
+/// `None` if it isn't one, or its check digit is wrong.
+pub fn normalize_isbn(isbn: &str) -> Option<String> {
+    let isbn = isbn
+        .chars()
+        .filter(|c| *c != '-' && *c != ' ')
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+    let digits = isbn
+        .chars()
+        .map(|c| match c {
+            'X' => Some(10),
+            c => c.to_digit(10),
+        })
+        .collect::<Option<Vec<u32>>>()?;
+
+    match digits.len() {
+        10 => {
+            // Only the check digit may be X.
+            if digits[..9].contains(&10) {
+                return None;
+            }
+            let sum: u32 = digits.iter().zip((1..=10).rev()).map(|(d, w)| d * w).sum();
+            sum.is_multiple_of(11).then(|| isbn13_of(&isbn[..9]))
+        }
+        13 => {
+            let sum: u32 = digits
+                .iter()
+                .enumerate()
+                .map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 })
+                .sum();
+            (!digits.contains(&10)
+                && (isbn.starts_with("978") || isbn.starts_with("979"))
+                && sum.is_multiple_of(10))
+            .then_some(isbn)
+        }
+        _ => None,
+    }
+}
+
+/// The ISBN-13 for the first nine digits of an ISBN-10.
+fn isbn13_of(isbn10_stem: &str) -> String {
+    let stem = format!("978{isbn10_stem}");
+    let sum: u32 = stem
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d } else { d * 3 })
+        .sum();
+    format!("{stem}{}", (10 - sum % 10) % 10)
+}
+
+/// The ISBN-10 form of a 978 ISBN-13, under which older books may have been catalogued.
+fn isbn10_of(isbn13: &str) -> Option<String> {
+    let stem = isbn13.strip_prefix("978")?.get(..9)?;
+    let sum: u32 = stem
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .zip((2..=10).rev())
+        .map(|(d, w)| d * w)
+        .sum();
+    let check = match (11 - sum % 11) % 11 {
+        10 => 'X',
+        d => char::from_digit(d, 10)?,
+    };
+    Some(format!("{stem}{check}"))
+}
+
+#[derive(Debug, Clone)]
+struct Holding {
+    barcode: Option<String>,
+    shelf_location: Option<String>,
+    condition: Option<CopyCondition>,
+}
+
+/// A validated record, or several merged by ISBN.
+#[derive(Debug, Clone)]
+struct ImportBook {
+    isbn: String,
+    title: Option<String>,
+    author: Option<String>,
+    publisher: Option<String>,
+    published_year: Option<i32>,
+    genre: Option<String>,
+    description: Option<String>,
+    cover_url: Option<String>,
+    copies: Option<i32>,
+    holdings: Vec<Holding>,
+    defaults: Holding,
+}
+
+impl ImportBook {
+    /// Adds a later record with the same ISBN: its copies, and any details still missing.
+    fn merge(&mut self, other: ImportBook) {
+        self.title = self.title.take().or(other.title);
+        self.author = self.author.take().or(other.author);
+        self.publisher = self.publisher.take().or(other.publisher);
+        self.published_year = self.published_year.or(other.published_year);
+        self.genre = self.genre.take().or(other.genre);
+        self.description = self.description.take().or(other.description);
+        self.cover_url = self.cover_url.take().or(other.cover_url);
+        self.copies = self.copies.max(other.copies);
+        self.holdings.extend(other.holdings);
+    }
+
+    /// Whether applying the record would change `book`'s details.
+    fn changes(&self, book: &Book) -> bool {
+        fn differs<T: PartialEq>(new: &Option<T>, old: Option<&T>) -> bool {
+            new.as_ref().is_some_and(|new| Some(new) != old)
+        }
+        differs(&self.title, Some(&book.title))
+            || differs(&self.author, Some(&book.author))
+            || differs(&self.publisher, book.publisher.as_ref())
+            || differs(&self.published_year, book.published_year.as_ref())
+            || differs(&self.genre, book.genre.as_ref())
+            || differs(&self.description, book.description.as_ref())
+            || differs(&self.cover_url, book.cover_url.as_ref())
+    }
+}
+
+fn text(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn condition(value: Option<String>, problems: &mut Vec<String>) -> Option<CopyCondition> {
+    let value = text(value)?;
+    match serde_json::from_value(serde_json::Value::String(value.to_ascii_uppercase())) {
+        Ok(condition) => Some(condition),
+        Err(_) => {
+            problems.push(format!(
+                "condition `{value}` is not one of NEW, GOOD, FAIR, POOR or DAMAGED"
+            ));
+            None
+        }
+    }
+}
+
+/// Checks a record, reporting every problem with it at once.
+fn validate(raw: RawRecord, now: DateTime<Utc>) -> Result<ImportBook, String> {
+    let mut problems = Vec::new();
+
+    let isbn = match text(raw.isbn) {
+        Some(isbn) => normalize_isbn(&isbn).or_else(|| {
+            problems.push(format!("`{isbn}` is not a valid ISBN-10 or ISBN-13"));
+            None
+        }),
+        None => {
+            problems.push("the ISBN is missing".to_string());
+            None
+        }
+    };
+
+    let published_year = text(raw.published_year).and_then(|year| match year.parse::<i32>() {
+        Ok(y) if (1..=now.year() + 1).contains(&y) => Some(y),
+        _ => {
+            problems.push(format!("published_year `{year}` is not a year"));
+            None
+        }
+    });
+
+    let copies = text(raw.copies).and_then(|copies| match copies.parse::<i32>() {
+        Ok(n) if (0..=MAX_COPIES).contains(&n) => Some(n),
+        _ => {
+            problems.push(format!(
+                "copies `{copies}` is not a number from 0 to {MAX_COPIES}"
+            ));
+            None
+        }
+    });
+
+    let cover_url = text(raw.cover_url).filter(|url| {
+        let valid = url.starts_with("http://") || url.starts_with("https://");
+        if !valid {
+            problems.push(format!("cover_url `{url}` is not an http(s) URL"));
+        }
+        valid
+    });
+
+    let holdings = raw
+        .holdings
+        .into_iter()
+        .map(|holding| Holding {
+            barcode: text(holding.barcode),
+            shelf_location: text(holding.shelf_location),
+            condition: condition(holding.condition, &mut problems),
+        })
+        .collect();
+    let defaults = Holding {
+        barcode: None,
+        shelf_location: text(raw.defaults.shelf_location),
+        condition: condition(raw.defaults.condition, &mut problems),
+    };
+
+    match isbn {
+        Some(isbn) if problems.is_empty() => Ok(ImportBook {
+            isbn,
+            title: text(raw.title),
+            author: text(raw.author),
+            publisher: text(raw.publisher),
+            published_year,
+            genre: text(raw.genre),
+            description: text(raw.description),
+            cover_url,
+            copies,
+            holdings,
+            defaults,
+        }),
+        _ => Err(problems.join("; ")),
+    }
+}
+
+/// The outcome of one row, as stored in `book_import_rows`.
+#[derive(Debug, Clone)]
+struct RowOutcome {
+    row_number: i32,
+    isbn: Option<String>,
+    action: BookImportRowAction,
+    book_id: Option<Uuid>,
+    copies_added: i32,
+    message: Option<String>,
+}
+
+impl RowOutcome {
+    fn failed(row_number: i32, isbn: Option<String>, message: String) -> Self {
+        Self {
+            row_number,
+            isbn,
+            action: BookImportRowAction::Failed,
+            book_id: None,
+            copies_added: 0,
+            message: Some(message),
+        }
+    }
+}
+
+/// Books sharing an ISBN, applied together. The first row is the one that counts.
+struct Group {
+    rows: Vec<i32>,
+    book: ImportBook,
+}
+
+/// Validates the records, returning the rows that failed and the rest grouped by ISBN, in
+/// the order each ISBN first appears.
+fn plan(
+    records: Vec<Result<RawRecord, String>>,
+    now: DateTime<Utc>,
+) -> (Vec<RowOutcome>, Vec<Group>) {
+    let mut failed = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut by_isbn = HashMap::new();
+
+    for (row_number, record) in (1..).zip(records) {
+        let isbn = record.as_ref().ok().and_then(|raw| text(raw.isbn.clone()));
+        match record.and_then(|raw| validate(raw, now)) {
+            Ok(book) => match by_isbn.get(&book.isbn) {
+                Some(&i) => {
+                    let group: &mut Group = &mut groups[i];
+                    group.rows.push(row_number);
+                    group.book.merge(book);
+                }
+                None => {
+                    by_isbn.insert(book.isbn.clone(), groups.len());
+                    groups.push(Group {
+                        rows: vec![row_number],
+                        book,
+                    });
+                }
+            },
+            Err(message) => failed.push(RowOutcome::failed(row_number, isbn, message)),
+        }
+    }
+    (failed, groups)
+}
+
+/// What applying a group did to its book.
+struct Applied {
+    action: BookImportRowAction,
+    book_id: Uuid,
+    copies_added: i32,
+}
+
+/// Creates or updates the book and its copies in one transaction, which a dry run rolls
+/// back. A refusal specific to this book, such as a barcode belonging to another, is an
+/// `Ok(Err)`; `Err` is the database failing.
+async fn apply(
+    db: &PgPool,
+    ctx: &AuditContext,
+    book: &ImportBook,
+    dry_run: bool,
+) -> sqlx::Result<Result<Applied, String>> {
+    let mut tx = db.begin().await?;
+
+    let candidates = [Some(book.isbn.clone()), isbn10_of(&book.isbn)]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    let existing = sqlx::query_as::<_, Book>(
+        r#"
+        SELECT * FROM books
+        WHERE isbn = ANY($1) AND deleted_at IS NULL
+        ORDER BY isbn = $2 DESC
+        LIMIT 1
+        FOR UPDATE
+        "#,
+    )
+    .bind(&candidates)
+    .bind(&book.isbn)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let (record, mut action) = match existing {
+        None => {
+            let (Some(title), Some(author)) = (&book.title, &book.author) else {
+                return Ok(Err("a new book needs a title and an author".to_string()));
+            };
+            let created = sqlx::query_as::<_, Book>(
+                r#"
+                INSERT INTO books (id, isbn, title, author, publisher, published_year, genre, description, cover_url, total_copies, available_copies)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 0, 0)
+                RETURNING *
+                "#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(&book.isbn)
+            .bind(title)
+            .bind(author)
+            .bind(&book.publisher)
+            .bind(book.published_year)
+            .bind(&book.genre)
+            .bind(&book.description)
+            .bind(&book.cover_url)
+            .fetch_one(&mut *tx)
+            .await?;
+            (created, BookImportRowAction::Created)
+        }
+        Some(existing) if book.changes(&existing) => {
+            let updated = sqlx::query_as::<_, Book>(
+                r#"
+                UPDATE books
+                SET title = COALESCE($2, title),
+                    author = COALESCE($3, author),
+                    publisher = COALESCE($4, publisher),
+                    published_year = COALESCE($5, published_year),
+                    genre = COALESCE($6, genre),
+                    description = COALESCE($7, description),
+                    cover_url = COALESCE($8, cover_url),
+                    updated_at = NOW()
+                WHERE id = $1
+                RETURNING *
+                "#,
+            )
+            .bind(existing.id)
+            .bind(&book.title)
+            .bind(&book.author)
+            .bind(&book.publisher)
+            .bind(book.published_year)
+            .bind(&book.genre)
+            .bind(&book.description)
+            .bind(&book.cover_url)
+            .fetch_one(&mut *tx)
+            .await?;
+            audit::record(
+                &mut *tx,
+                ctx,
+                AuditEvent::new("book.update", "book", updated.id)
+                    .before(&existing)
+                    .after(&updated),
+            )
+            .await?;
+            (updated, BookImportRowAction::Updated)
+        }
+        Some(existing) => (existing, BookImportRowAction::Unchanged),
+    };
+
+    let mut copies_added = 0;
+    let mut copies_changed = false;
+    for holding in &book.holdings {
+        let Some(ref barcode) = holding.barcode else {
+            continue;
+        };
+        let copy = sqlx::query_as::<_, BookCopy>(
+            "SELECT * FROM book_copies WHERE barcode = $1 FOR UPDATE",
+        )
+        .bind(barcode)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        match copy {
+            Some(copy) if copy.book_id != record.id => {
+                return Ok(Err(format!("barcode `{barcode}` belongs to another book")));
+            }
+            Some(copy)
+                if holding
+                    .shelf_location
+                    .as_ref()
+                    .is_some_and(|shelf| copy.shelf_location.as_ref() != Some(shelf))
+                    || holding.condition.is_some_and(|c| c != copy.condition) =>
+            {
+                let updated = sqlx::query_as::<_, BookCopy>(
+                    r#"
+                    UPDATE book_copies
+                    SET shelf_location = COALESCE($2, shelf_location),
+                        condition = COALESCE($3, condition),
+                        updated_at = NOW()
+                    WHERE id = $1
+                    RETURNING *
+                    "#,
+                )
+                .bind(copy.id)
+                .bind(&holding.shelf_location)
+                .bind(holding.condition)
+                .fetch_one(&mut *tx)
+                .await?;
+                audit::record(
+                    &mut *tx,
+                    ctx,
+                    AuditEvent::new("copy.update", "copy", copy.id)
+                        .before(&copy)
+                        .after(&updated),
+                )
+                .await?;
+                copies_changed = true;
+            }
+            Some(_) => {}
+            None => {
+                let copy = inventory::insert_copy(
+                    &mut *tx,
+                    record.id,
+                    Some(barcode.clone()),
+                    holding.shelf_location.clone(),
+                    holding.condition.unwrap_or_default(),
+                )
+                .await?;
+                audit::record(
+                    &mut *tx,
+                    ctx,
+                    AuditEvent::new("copy.create", "copy", copy.id).after(&copy),
+                )
+                .await?;
+                copies_added += 1;
+            }
+        }
+    }
+
+    // Make up the copies the record asks for. A new book gets one by default, as through
+    // the API.
+    let wanted = match (book.copies, action) {
+        (Some(copies), _) => copies.max(book.holdings.len() as i32),
+        (None, BookImportRowAction::Created) if book.holdings.is_empty() => 1,
+        (None, _) => book.holdings.len() as i32,
+    };
+    let current: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM book_copies WHERE book_id = $1 AND status <> 'WITHDRAWN'",
+    )
+    .bind(record.id)
+    .fetch_one(&mut *tx)
+    .await?;
+    let padding = book
+        .holdings
+        .iter()
+        .find(|holding| holding.barcode.is_none())
+        .unwrap_or(&book.defaults);
+    for _ in current..i64::from(wanted) {
+        let copy = inventory::insert_copy(
+            &mut *tx,
+            record.id,
+            None,
+            padding.shelf_location.clone(),
+            padding.condition.unwrap_or_default(),
+        )
+        .await?;
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("copy.create", "copy", copy.id).after(&copy),
+        )
+        .await?;
+        copies_added += 1;
+    }
+
+    if copies_added > 0 || copies_changed {
+        let refreshed = inventory::refresh_availability(&mut *tx, record.id).await?;
+        if action == BookImportRowAction::Unchanged {
+            action = BookImportRowAction::Updated;
+        }
+        if action == BookImportRowAction::Created {
+            audit::record(
+                &mut *tx,
+                ctx,
+                AuditEvent::new("book.create", "book", refreshed.id).after(&refreshed),
+            )
+            .await?;
+        }
+    } else if action == BookImportRowAction::Created {
+        audit::record(
+            &mut *tx,
+            ctx,
+            AuditEvent::new("book.create", "book", record.id).after(&record),
+        )
+        .await?;
+    }
+
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+
+    Ok(Ok(Applied {
+        action,
+        book_id: record.id,
+        copies_added,
+    }))
+}
+
+/// Running totals, written to `book_imports` as progress.
+#[derive(Debug, Default)]
+struct Totals {
+    processed_rows: i32,
+    books_created: i32,
+    books_updated: i32,
+    books_unchanged: i32,
+    rows_failed: i32,
+    copies_added: i32,
+}
+
+impl Totals {
+    fn add(&mut self, outcome: &RowOutcome) {
+        self.processed_rows += 1;
+        self.copies_added += outcome.copies_added;
+        match outcome.action {
+            BookImportRowAction::Created => self.books_created += 1,
+            BookImportRowAction::Updated => self.books_updated += 1,
+            BookImportRowAction::Unchanged => self.books_unchanged += 1,
+            BookImportRowAction::Failed => self.rows_failed += 1,
+            BookImportRowAction::Merged => {}
+        }
+    }
+}
+
+/// Stores the pending row outcomes and the totals so far.
+async fn flush(
+    db: &PgPool,
+    import_id: Uuid,
+    pending: &mut Vec<RowOutcome>,
+    totals: &Totals,
+) -> sqlx::Result<()> {
+    let mut tx = db.begin().await?;
+
+    if !pending.is_empty() {
+        let mut insert = QueryBuilder::<Postgres>::new(
+            "INSERT INTO book_import_rows (import_id, row_number, isbn, action, book_id, copies_added, message) ",
+        );
+        insert.push_values(pending.drain(..), |mut row, outcome| {
+            row.push_bind(import_id)
+                .push_bind(outcome.row_number)
+                .push_bind(outcome.isbn)
+                .push_bind(outcome.action)
+                .push_bind(outcome.book_id)
+                .push_bind(outcome.copies_added)
+                .push_bind(outcome.message);
+        });
+        insert.build().execute(&mut *tx).await?;
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE book_imports
+        SET processed_rows = $2, books_created = $3, books_updated = $4, books_unchanged = $5,
+            rows_failed = $6, copies_added = $7
+        WHERE id = $1
+        "#,
+    )
+    .bind(import_id)
+    .bind(totals.processed_rows)
+    .bind(totals.books_created)
+    .bind(totals.books_updated)
+    .bind(totals.books_unchanged)
+    .bind(totals.rows_failed)
+    .bind(totals.copies_added)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Records a new import of `total_rows` records, before any is applied.
+pub async fn create(
+    db: &PgPool,
+    format: BookImportFormat,
+    dry_run: bool,
+    requested_by: Option<Uuid>,
+    total_rows: usize,
+    now: DateTime<Utc>,
+) -> sqlx::Result<BookImport> {
+    sqlx::query_as(
+        r#"
+        INSERT INTO book_imports (id, format, dry_run, requested_by, total_rows, started_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(format)
+    .bind(dry_run)
+    .bind(requested_by)
+    .bind(total_rows as i32)
+    .bind(now)
+    .fetch_one(db)
+    .await
+}
+
+/// Marks the import finished: succeeded, or failed with `error` when it stopped early.
+pub async fn finish(
+    db: &PgPool,
+    import_id: Uuid,
+    now: DateTime<Utc>,
+    error: Option<String>,
+) -> sqlx::Result<()> {
+    let status = match error {
+        None => BookImportStatus::Succeeded,
+        Some(_) => BookImportStatus::Failed,
+    };
+    sqlx::query("UPDATE book_imports SET status = $2, finished_at = $3, error = $4 WHERE id = $1")
+        .bind(import_id)
+        .bind(status)
+        .bind(now)
+        .bind(error)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Applies the records of `import`, recording each row's outcome and the progress as it
+/// goes. Stops between books once `stop` is cancelled.
+pub async fn run(
+    db: PgPool,
+    clock: Arc<dyn Clock>,
+    ctx: AuditContext,
+    import: BookImport,
+    records: Vec<Result<RawRecord, String>>,
+    stop: CancellationToken,
+) {
+    let (failed, groups) = plan(records, import.started_at);
+
+    let mut totals = Totals::default();
+    failed.iter().for_each(|outcome| totals.add(outcome));
+    let mut pending = failed;
+
+    let mut error = None;
+    for group in groups {
+        if stop.is_cancelled() {
+            error = Some(format!(
+                "Stopped by shutdown after {} of {} rows",
+                totals.processed_rows, import.total_rows
+            ));
+            break;
+        }
+
+        let book = &group.book;
+        let outcomes = match apply(&db, &ctx, book, import.dry_run).await {
+            Ok(Ok(applied)) => {
+                let first = group.rows[0];
+                group
+                    .rows
+                    .iter()
+                    .map(|&row_number| RowOutcome {
+                        row_number,
+                        isbn: Some(book.isbn.clone()),
+                        action: if row_number == first {
+                            applied.action
+                        } else {
+                            BookImportRowAction::Merged
+                        },
+                        book_id: Some(applied.book_id),
+                        copies_added: if row_number == first {
+                            applied.copies_added
+                        } else {
+                            0
+                        },
+                        message: (row_number != first)
+                            .then(|| format!("Merged into row {first}, which has the same ISBN")),
+                    })
+                    .collect::<Vec<_>>()
+            }
+            Ok(Err(message)) => group
+                .rows
+                .iter()
+                .map(|&row| RowOutcome::failed(row, Some(book.isbn.clone()), message.clone()))
+                .collect(),
+            Err(sqlx::Error::Database(e)) if e.constraint().is_some() => {
+                let message = format!("conflicts with existing data ({e})");
+                group
+                    .rows
+                    .iter()
+                    .map(|&row| RowOutcome::failed(row, Some(book.isbn.clone()), message.clone()))
+                    .collect()
+            }
+            Err(e) => {
+                error = Some(format!("Database error: {e}"));
+                break;
+            }
+        };
+
+        outcomes.iter().for_each(|outcome| totals.add(outcome));
+        pending.extend(outcomes);
+        if pending.len() >= FLUSH_EVERY {
+            if let Err(e) = flush(&db, import.id, &mut pending, &totals).await {
+                error = Some(format!("Database error: {e}"));
+                break;
+            }
+        }
+    }
+
+    let result = async {
+        flush(&db, import.id, &mut pending, &totals).await?;
+        finish(&db, import.id, clock.now(), error.clone()).await
+    }
+    .await;
+    match (result, error) {
+        (Err(e), _) => warn!(
+            "Failed to record the outcome of book import {}: {e}",
+            import.id
+        ),
+        (Ok(()), Some(error)) => warn!("Book import {} stopped: {error}", import.id),
+        (Ok(()), None) => info!(
+            "Book import {} finished: {} created, {} updated, {} unchanged, {} failed{}",
+            import.id,
+            totals.books_created,
+            totals.books_updated,
+            totals.books_unchanged,
+            totals.rows_failed,
+            if import.dry_run { " (dry run)" } else { "" }
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isbns_are_normalized_to_isbn13() {
+        assert_eq!(
+            normalize_isbn("0-441-01359-7").as_deref(),
+            Some("9780441013593")
+        );
+        assert_eq!(
+            normalize_isbn("978 0 441 01359 3").as_deref(),
+            Some("9780441013593")
+        );
+        assert_eq!(
+            normalize_isbn("080442957x").as_deref(),
+            Some("9780804429573")
+        );
+        assert_eq!(normalize_isbn("0-441-01359-8"), None);
+        assert_eq!(normalize_isbn("9780441013594"), None);
+        assert_eq!(normalize_isbn("12345"), None);
+
+        assert_eq!(isbn10_of("9780441013593").as_deref(), Some("0441013597"));
+        assert_eq!(isbn10_of("9780804429573").as_deref(), Some("080442957X"));
+        assert_eq!(isbn10_of("9791032305690"), None);
+    }
+
+    #[test]
+    fn test_rows_are_validated_and_merged_by_isbn() {
+        let record = |isbn: &str, title: Option<&str>, copies: Option<&str>| RawRecord {
+            isbn: Some(isbn.to_string()),
+            title: title.map(str::to_owned),
+            copies: copies.map(str::to_owned),
+            ..RawRecord::default()
+        };
+        let records = vec![
+            Ok(record("0-441-01359-7", None, Some("2"))),
+            Err("unreadable row".to_string()),
+            Ok(record("9780441013593", Some("Dune"), Some("3"))),
+            Ok(RawRecord {
+                published_year: Some("soon".to_string()),
+                cover_url: Some("ftp://example.com/dune.jpg".to_string()),
+                ..record("9780441013594", None, None)
+            }),
+            Ok(RawRecord {
+                defaults: RawHolding {
+                    condition: Some("good".to_string()),
+                    ..RawHolding::default()
+                },
+                ..record("9780141439587", Some("Emma"), Some("1"))
+            }),
+        ];
+
+        let (failed, groups) = plan(records, Utc::now());
+
+        assert_eq!(
+            failed.iter().map(|row| row.row_number).collect::<Vec<_>>(),
+            [2, 4]
+        );
+        assert_eq!(failed[0].message.as_deref(), Some("unreadable row"));
+        let problems = failed[1].message.as_deref().unwrap();
+        assert!(problems.contains("not a valid ISBN"));
+        assert!(problems.contains("published_year"));
+        assert!(problems.contains("cover_url"));
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].rows, [1, 3]);
+        assert_eq!(groups[0].book.isbn, "9780441013593");
+        assert_eq!(groups[0].book.title.as_deref(), Some("Dune"));
+        assert_eq!(groups[0].book.copies, Some(3));
+        assert_eq!(groups[1].book.defaults.condition, Some(CopyCondition::Good));
+    }
+}
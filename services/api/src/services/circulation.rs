@@ -0,0 +1,482 @@
+//! Lending rules: who may borrow what, for how long, and how loans end. Storage and
+//! locking are left to a `CheckoutRepository`; HTTP concerns stay in the handlers.
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::{
+    models::{Book, CheckoutSearchQuery, CheckoutStatus, CheckoutWithDetails},
+    repositories::{CheckoutFilter, CheckoutRepository, NewCheckout, RepositoryError},
+    services::audit::{AuditContext, AuditEvent},
+};
+
+/// Default length of a loan, and how far each renewal pushes the due date.
+pub const LOAN_PERIOD_DAYS: i64 = 14;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CirculationError {
+    #[error("user not found or inactive")]
+    UserNotFound,
+    #[error("book not found")]
+    BookNotFound,
+    #[error("copy not found")]
+    CopyNotFound,
+    #[error("checkout not found or not active")]
+    CheckoutNotFound,
+    #[error("no copy is available")]
+    NoCopyAvailable,
+    #[error("user has reached their checkout limit")]
+    CheckoutLimitReached,
+    #[error("checkout has no renewals left")]
+    RenewalLimitReached,
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+impl From<CirculationError> for StatusCode {
+    fn from(err: CirculationError) -> Self {
+        match err {
+            CirculationError::UserNotFound
+            | CirculationError::BookNotFound
+            | CirculationError::CopyNotFound
+            | CirculationError::CheckoutNotFound => StatusCode::NOT_FOUND,
+            CirculationError::NoCopyAvailable
+            | CirculationError::CheckoutLimitReached
+            | CirculationError::RenewalLimitReached => StatusCode::CONFLICT,
+            CirculationError::Repository(err) => err.into(),
+        }
+    }
+}
+
+/// Which copy to lend.
+#[derive(Debug, Clone)]
+pub enum CopySelector {
+    /// A specific copy of the book, or any available one.
+    Book {
+        book_id: Uuid,
+        copy_id: Option<Uuid>,
+    },
+    /// The copy with this barcode.
+    Barcode(String),
+    /// Any available copy of the live book with this ISBN.
+    Isbn(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct LoanRequest {
+    pub user_id: Uuid,
+    pub copy: CopySelector,
+    pub due_date: Option<DateTime<Utc>>,
+}
+
+/// A checkout after a circulation change, with the book's refreshed counters.
+#[derive(Debug, Clone)]
+pub struct LoanOutcome {
+    pub checkout: CheckoutWithDetails,
+    pub book: Book,
+}
+
+pub async fn checkout(
+    repo: &dyn CheckoutRepository,
+    ctx: &AuditContext,
+    req: LoanRequest,
+    now: DateTime<Utc>,
+) -> Result<LoanOutcome, CirculationError> {
+    let mut tx = repo.begin().await?;
+
+    let (book_id, copy_id) = match req.copy {
+        CopySelector::Book { book_id, copy_id } => (book_id, copy_id),
+        CopySelector::Barcode(barcode) => {
+            let copy = tx
+                .find_copy_by_barcode(&barcode)
+                .await?
+                .ok_or(CirculationError::CopyNotFound)?;
+            (copy.book_id, Some(copy.id))
+        }
+        CopySelector::Isbn(isbn) => {
+            let book_id = tx
+                .find_book_id_by_isbn(&isbn)
+                .await?
+                .ok_or(CirculationError::BookNotFound)?;
+            (book_id, None)
+        }
+    };
+
+    // Holding the user makes the active-checkout count authoritative; holding the book
+    // serializes the availability refresh.
+    let user = tx
+        .lock_user(req.user_id)
+        .await?
+        .filter(|user| user.is_active && user.deleted_at.is_none())
+        .ok_or(CirculationError::UserNotFound)?;
+
+    let book = tx
+        .lock_book(book_id)
+        .await?
+        .filter(|book| book.deleted_at.is_none())
+        .ok_or(CirculationError::BookNotFound)?;
+
+    if tx.count_active_checkouts(user.id).await? >= user.max_checkouts as i64 {
+        return Err(CirculationError::CheckoutLimitReached);
+    }
+
+    let copy = match copy_id {
+        Some(copy_id) => {
+            tx.find_copy(copy_id)
+                .await?
+                .filter(|copy| copy.book_id == book.id)
+                .ok_or(CirculationError::CopyNotFound)?;
+            tx.claim_copy(copy_id).await?
+        }
+        None => tx.claim_available_copy(book.id).await?,
+    }
+    .ok_or(CirculationError::NoCopyAvailable)?;
+
+    let due_date = req
+        .due_date
+        .unwrap_or_else(|| now + Duration::days(LOAN_PERIOD_DAYS));
+
+    let checkout = tx
+        .insert_checkout(NewCheckout {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            book_id: book.id,
+            copy_id: copy.id,
+            checked_out_at: now,
+            due_date,
+        })
+        .await?;
+
+    let book = tx.refresh_availability(book.id).await?;
+
+    tx.record_audit(
+        ctx,
+        AuditEvent::new("checkout.create", "checkout", checkout.id).after(&checkout),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(LoanOutcome {
+        checkout: CheckoutWithDetails {
+            checkout,
+            user: (&user).into(),
+            book: (&book).into(),
+        },
+        book,
+    })
+}
+
+pub async fn return_book(
+    repo: &dyn CheckoutRepository,
+    ctx: &AuditContext,
+    checkout_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<LoanOutcome, CirculationError> {
+    let mut tx = repo.begin().await?;
+
+    // Lock the book before the checkout, matching the order used when lending, so a
+    // concurrent return and checkout of the same title can't deadlock.
+    let book_id = tx
+        .active_checkout_book_id(checkout_id)
+        .await?
+        .ok_or(CirculationError::CheckoutNotFound)?;
+
+    tx.lock_book(book_id).await?;
+
+    // Re-check the status under the row lock; a racing return will have already flipped it.
+    let previous = tx
+        .lock_active_checkout(checkout_id)
+        .await?
+        .ok_or(CirculationError::CheckoutNotFound)?;
+
+    let checkout = tx.mark_returned(checkout_id, now).await?;
+
+    if let Some(copy_id) = checkout.copy_id {
+        tx.release_copy(copy_id).await?;
+    }
+
+    let book = tx.refresh_availability(book_id).await?;
+
+    tx.record_audit(
+        ctx,
+        AuditEvent::new("checkout.return", "checkout", checkout.id)
+            .before(&previous.checkout)
+            .after(&checkout),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(LoanOutcome {
+        checkout: CheckoutWithDetails {
+            checkout,
+            user: previous.user,
+            book: previous.book,
+        },
+        book,
+    })
+}
+
+pub async fn renew(
+    repo: &dyn CheckoutRepository,
+    ctx: &AuditContext,
+    checkout_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<CheckoutWithDetails, CirculationError> {
+    let mut tx = repo.begin().await?;
+
+    let previous = tx
+        .lock_active_checkout(checkout_id)
+        .await?
+        .ok_or(CirculationError::CheckoutNotFound)?;
+
+    if previous.checkout.renewal_count >= previous.checkout.max_renewals {
+        return Err(CirculationError::RenewalLimitReached);
+    }
+
+    let due_date = previous.checkout.due_date + Duration::days(LOAN_PERIOD_DAYS);
+    let checkout = tx.renew(checkout_id, due_date, now).await?;
+
+    tx.record_audit(
+        ctx,
+        AuditEvent::new("checkout.renew", "checkout", checkout.id)
+            .before(&previous.checkout)
+            .after(&checkout),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(CheckoutWithDetails {
+        checkout,
+        user: previous.user,
+        book: previous.book,
+    })
+}
+
+pub async fn get(
+    repo: &dyn CheckoutRepository,
+    checkout_id: Uuid,
+) -> Result<CheckoutWithDetails, CirculationError> {
+    repo.find(checkout_id)
+        .await?
+        .ok_or(CirculationError::CheckoutNotFound)
+}
+
+/// The repository filter for `query`; `overdue=true` means past due as of `now`.
+pub fn filter_for(query: &CheckoutSearchQuery, now: DateTime<Utc>) -> CheckoutFilter {
+    CheckoutFilter {
+        user_id: query.user_id,
+        book_id: query.book_id,
+        status: query.status.clone(),
+        overdue_at: (query.overdue == Some(true)).then_some(now),
+    }
+}
+
+/// One page of checkouts matching `filter`, with the total number of matches.
+pub async fn list(
+    repo: &dyn CheckoutRepository,
+    filter: &CheckoutFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<CheckoutWithDetails>, i64), CirculationError> {
+    let total = repo.count(filter).await?;
+    let checkouts = repo.list(filter, limit, offset).await?;
+    Ok((checkouts, total))
+}
+
+/// Active loans past their due date as of `now`, reported with the OVERDUE status.
+pub async fn overdue(
+    repo: &dyn CheckoutRepository,
+    now: DateTime<Utc>,
+) -> Result<Vec<CheckoutWithDetails>, CirculationError> {
+    let mut checkouts = repo.list_overdue(now).await?;
+    for details in &mut checkouts {
+        details.checkout.status = CheckoutStatus::Overdue;
+    }
+    Ok(checkouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::CopyStatus,
+        repositories::memory::{MemoryCheckoutRepository, MemoryStore},
+        services::clock::{Clock, TestClock},
+    };
+
+    fn lend(user_id: Uuid, book_id: Uuid) -> LoanRequest {
+        LoanRequest {
+            user_id,
+            copy: CopySelector::Book {
+                book_id,
+                copy_id: None,
+            },
+            due_date: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkout_claims_a_copy_until_returned() {
+        let store = MemoryStore::new();
+        let repo = MemoryCheckoutRepository::new(store.clone());
+        let ctx = AuditContext::default();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 1).await;
+
+        let loan = checkout(&repo, &ctx, lend(user.id, book.id), Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(loan.book.available_copies, 0);
+
+        let copy_id = loan.checkout.checkout.copy_id.unwrap();
+        assert_eq!(
+            store.snapshot().await.copies[&copy_id].status,
+            CopyStatus::OnLoan
+        );
+
+        let second_reader = store.insert_user("Other", 5).await;
+        let err = checkout(&repo, &ctx, lend(second_reader.id, book.id), Utc::now())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CirculationError::NoCopyAvailable));
+
+        let returned = return_book(&repo, &ctx, loan.checkout.checkout.id, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(returned.checkout.checkout.status, CheckoutStatus::Returned);
+        assert_eq!(returned.book.available_copies, 1);
+
+        let err = return_book(&repo, &ctx, loan.checkout.checkout.id, Utc::now())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CirculationError::CheckoutNotFound));
+
+        let actions: Vec<_> = store
+            .snapshot()
+            .await
+            .audit_log
+            .iter()
+            .map(|(_, event)| event.action)
+            .collect();
+        assert_eq!(actions, ["checkout.create", "checkout.return"]);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_limit_is_enforced() {
+        let store = MemoryStore::new();
+        let repo = MemoryCheckoutRepository::new(store.clone());
+        let ctx = AuditContext::default();
+        let user = store.insert_user("Reader", 1).await;
+        let first = store.insert_book("Dune", 1).await;
+        let second = store.insert_book("Emma", 1).await;
+
+        checkout(&repo, &ctx, lend(user.id, first.id), Utc::now())
+            .await
+            .unwrap();
+        let err = checkout(&repo, &ctx, lend(user.id, second.id), Utc::now())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, CirculationError::CheckoutLimitReached));
+        assert_eq!(store.snapshot().await.books[&second.id].available_copies, 1);
+    }
+
+    #[tokio::test]
+    async fn test_renewals_extend_due_date_up_to_limit() {
+        let store = MemoryStore::new();
+        let repo = MemoryCheckoutRepository::new(store.clone());
+        let ctx = AuditContext::default();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 1).await;
+
+        let clock = TestClock::new(Utc::now());
+
+        let loan = checkout(&repo, &ctx, lend(user.id, book.id), clock.now())
+            .await
+            .unwrap();
+        let checkout_id = loan.checkout.checkout.id;
+        let due_date = loan.checkout.checkout.due_date;
+
+        clock.advance(Duration::days(3));
+        let renewed = renew(&repo, &ctx, checkout_id, clock.now()).await.unwrap();
+        assert_eq!(
+            renewed.checkout.due_date,
+            due_date + Duration::days(LOAN_PERIOD_DAYS)
+        );
+        assert_eq!(renewed.checkout.updated_at, clock.now());
+
+        renew(&repo, &ctx, checkout_id, clock.now()).await.unwrap();
+        let err = renew(&repo, &ctx, checkout_id, clock.now())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, CirculationError::RenewalLimitReached));
+    }
+
+    #[tokio::test]
+    async fn test_barcode_checkout_lends_that_copy() {
+        let store = MemoryStore::new();
+        let repo = MemoryCheckoutRepository::new(store.clone());
+        let ctx = AuditContext::default();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 3).await;
+
+        let copy = store
+            .snapshot()
+            .await
+            .copies
+            .into_values()
+            .max_by(|a, b| a.barcode.cmp(&b.barcode))
+            .unwrap();
+
+        let loan = checkout(
+            &repo,
+            &ctx,
+            LoanRequest {
+                user_id: user.id,
+                copy: CopySelector::Barcode(copy.barcode.clone()),
+                due_date: None,
+            },
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(loan.checkout.checkout.copy_id, Some(copy.id));
+        assert_eq!(loan.checkout.book.id, book.id);
+        assert_eq!(loan.book.available_copies, 2);
+    }
+
+    #[tokio::test]
+    async fn test_loans_fall_overdue_after_the_loan_period() {
+        let store = MemoryStore::new();
+        let repo = MemoryCheckoutRepository::new(store.clone());
+        let ctx = AuditContext::default();
+        let user = store.insert_user("Reader", 5).await;
+        let book = store.insert_book("Dune", 1).await;
+        let clock = TestClock::new(Utc::now());
+
+        let loan = checkout(&repo, &ctx, lend(user.id, book.id), clock.now())
+            .await
+            .unwrap();
+        assert_eq!(loan.checkout.checkout.checked_out_at, clock.now());
+
+        clock.advance(Duration::days(LOAN_PERIOD_DAYS));
+        assert!(overdue(&repo, clock.now()).await.unwrap().is_empty());
+
+        clock.advance(Duration::days(1));
+        let late = overdue(&repo, clock.now()).await.unwrap();
+        assert_eq!(late.len(), 1);
+        assert_eq!(late[0].checkout.status, CheckoutStatus::Overdue);
+
+        let returned = return_book(&repo, &ctx, loan.checkout.checkout.id, clock.now())
+            .await
+            .unwrap();
+        assert_eq!(returned.checkout.checkout.returned_at, Some(clock.now()));
+        assert_eq!(returned.checkout.checkout.updated_at, clock.now());
+        assert!(overdue(&repo, clock.now()).await.unwrap().is_empty());
+    }
+}
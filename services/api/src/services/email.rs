@@ -1,12 +1,71 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde_json::json;
 use sqlx::PgPool;
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, Instrument};
 use uuid::Uuid;
 
-pub async fn send_overdue_notifications(db: &PgPool) -> Result<()> {
+use crate::{config::EmailConfig, metrics::METRICS, middleware::request_id, telemetry};
+
+/// Client for the Resend email API.
+#[derive(Clone)]
+pub struct Resend {
+    client: Client,
+    api_url: String,
+    api_key: String,
+    from: String,
+}
+
+impl Resend {
+    pub fn new(
+        api_url: impl Into<String>,
+        api_key: impl Into<String>,
+        from: impl Into<String>,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            api_url: api_url.into(),
+            api_key: api_key.into(),
+            from: from.into(),
+        }
+    }
+
+    /// `None` when no API key is configured.
+    pub fn from_config(config: &EmailConfig) -> Option<Self> {
+        let api_key = config.resend_api_key.as_ref()?;
+        Some(Self::new(&config.resend_api_url, api_key, &config.from))
+    }
+
+    /// Checks that the Resend API answers, returning its status code.
+    pub async fn ping(&self, timeout: Duration) -> reqwest::Result<StatusCode> {
+        let response = self
+            .client
+            .get(&self.api_url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        Ok(response.status())
+    }
+}
+
+/// Emails every borrower whose active loan was due before `now` and has not been notified yet,
+/// returning how many emails were sent. A dry run sends nothing and returns how many would be.
+///
+/// Each loan is flagged as soon as its email is sent, so when `stop` is cancelled the batch
+/// ends before the next email and the following run picks up the rest.
+pub async fn send_overdue_notifications(
+    db: &PgPool,
+    resend: &Resend,
+    now: DateTime<Utc>,
+    dry_run: bool,
+    stop: &CancellationToken,
+) -> Result<u64> {
     let overdue_checkouts =
         sqlx::query_as::<_, (Uuid, DateTime<Utc>, bool, String, String, String, String)>(
             r#"
@@ -22,29 +81,44 @@ pub async fn send_overdue_notifications(db: &PgPool) -> Result<()> {
         JOIN users u ON c.user_id = u.id
         JOIN books b ON c.book_id = b.id
         WHERE c.status = 'ACTIVE' 
-        AND c.due_date < NOW()
+        AND c.due_date < $1
         AND c.overdue_email_sent = false
         "#,
         )
+        .bind(now)
         .fetch_all(db)
         .await?;
 
-    let client = Client::new();
-    let resend_api_key =
-        std::env::var("RESEND_API_KEY").unwrap_or_else(|_| "your-resend-api-key".to_string());
+    let total = overdue_checkouts.len();
+    if dry_run {
+        info!("Would send {} overdue notifications", total);
+        return Ok(total as u64);
+    }
 
+    let mut sent = 0;
     for (
-        checkout_id,
-        due_date,
-        _overdue_email_sent,
-        user_name,
-        user_email,
-        book_title,
-        book_author,
-    ) in overdue_checkouts
+        done,
+        (
+            checkout_id,
+            due_date,
+            _overdue_email_sent,
+            user_name,
+            user_email,
+            book_title,
+            book_author,
+        ),
+    ) in overdue_checkouts.into_iter().enumerate()
     {
+        if stop.is_cancelled() {
+            info!(
+                "Stopping overdue notifications for shutdown; {} left for the next run",
+                total - done
+            );
+            break;
+        }
+
         let email_body = json!({
-            "from": "Library System <noreply@library.com>",
+            "from": resend.from,
             "to": [user_email.clone()],
             "subject": format!("Overdue Book: {}", book_title),
             "html": format!(
@@ -67,13 +141,17 @@ pub async fn send_overdue_notifications(db: &PgPool) -> Result<()> {
             )
         });
 
-        let response = client
-            .post("https://api.resend.com/emails")
-            .header("Authorization", format!("Bearer {resend_api_key}"))
-            .header("Content-Type", "application/json")
-            .json(&email_body)
-            .send()
-            .await;
+        let url = format!("{}/emails", resend.api_url);
+        let response = async {
+            request_id::propagate(resend.client.post(&url))
+                .header("Authorization", format!("Bearer {}", resend.api_key))
+                .header("Content-Type", "application/json")
+                .json(&email_body)
+                .send()
+                .await
+        }
+        .instrument(telemetry::client_span("resend", "POST", &url))
+        .await;
 
         match response {
             Ok(resp) if resp.status().is_success() => {
@@ -82,38 +160,44 @@ pub async fn send_overdue_notifications(db: &PgPool) -> Result<()> {
                     .execute(db)
                     .await?;
 
+                sent += 1;
+                METRICS.overdue_emails.with_label_values(&["sent"]).inc();
                 info!("Sent overdue notification to {}", user_email);
             }
             Ok(resp) => {
                 error!("Failed to send email to {}: {}", user_email, resp.status());
+                METRICS.overdue_emails.with_label_values(&["failed"]).inc();
 
                 sqlx::query(
                     r#"
                     INSERT INTO overdue_email_failures (checkout_id, error_message, created_at)
-                    VALUES ($1, $2, NOW())
+                    VALUES ($1, $2, $3)
                     "#,
                 )
                 .bind(checkout_id)
                 .bind(format!("HTTP {}", resp.status()))
+                .bind(now)
                 .execute(db)
                 .await?;
             }
             Err(e) => {
                 error!("Network error sending email to {}: {}", user_email, e);
+                METRICS.overdue_emails.with_label_values(&["failed"]).inc();
 
                 sqlx::query(
                     r#"
                     INSERT INTO overdue_email_failures (checkout_id, error_message, created_at)
-                    VALUES ($1, $2, NOW())
+                    VALUES ($1, $2, $3)
                     "#,
                 )
                 .bind(checkout_id)
                 .bind(e.to_string())
+                .bind(now)
                 .execute(db)
                 .await?;
             }
         }
     }
 
-    Ok(())
+    Ok(sent)
 }
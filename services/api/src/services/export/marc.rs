@@ -0,0 +1,205 @@
+//! Books as MARC 21 bibliographic records, laid out so that the catalog import reads them
+//! back: ISBN in 020, author in 100, title in 245, publisher and year in 264, genre in 655,
+//! description in 520, cover in 856 and a 852 field per copy.
+
+use chrono::{DateTime, Utc};
+use quick_xml::escape::escape;
+use serde::Deserialize;
+
+use crate::models::Book;
+
+const SUBFIELD_DELIMITER: u8 = 0x1F;
+const FIELD_TERMINATOR: u8 = 0x1E;
+const RECORD_TERMINATOR: u8 = 0x1D;
+const LEADER_LENGTH: usize = 24;
+
+/// The most a field's length can be in the four digits the directory gives it.
+const MAX_FIELD_BYTES: usize = 9999;
+/// The most a record's length can be in the five digits the leader gives it.
+const MAX_RECORD_BYTES: usize = 99_999;
+
+pub const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+    <collection xmlns=\"http://www.loc.gov/MARC21/slim\">\n";
+pub const XML_FOOTER: &str = "</collection>\n";
+
+/// A copy, as aggregated alongside its book.
+#[derive(Debug, Deserialize)]
+pub struct Holding {
+    pub barcode: String,
+    pub shelf_location: Option<String>,
+}
+
+struct DataField {
+    tag: &'static str,
+    indicators: [char; 2],
+    subfields: Vec<(char, String)>,
+}
+
+pub struct MarcRecord {
+    control_fields: Vec<(&'static str, String)>,
+    data_fields: Vec<DataField>,
+}
+
+impl MarcRecord {
+    pub fn new(book: &Book, holdings: &[Holding]) -> Self {
+        let mut record = Self {
+            control_fields: vec![
+                ("001", book.id.to_string()),
+                ("008", fixed_data(book.created_at, book.published_year)),
+            ],
+            data_fields: Vec::new(),
+        };
+
+        record.push("020", [' ', ' '], [('a', Some(book.isbn.clone()))]);
+        record.push("100", ['1', ' '], [('a', Some(book.author.clone()))]);
+        // The import joins $a and $b with `: `, so split there to read the title back whole.
+        let (title, subtitle) = match book.title.split_once(": ") {
+            Some((title, subtitle)) => (title.to_string(), Some(subtitle.to_string())),
+            None => (book.title.clone(), None),
+        };
+        record.push("245", ['1', '0'], [('a', Some(title)), ('b', subtitle)]);
+        record.push(
+            "264",
+            [' ', '1'],
+            [
+                ('b', book.publisher.clone()),
+                ('c', book.published_year.map(|year| year.to_string())),
+            ],
+        );
+        record.push("520", [' ', ' '], [('a', book.description.clone())]);
+        record.push("655", [' ', '4'], [('a', book.genre.clone())]);
+        record.push("856", ['4', '0'], [('u', book.cover_url.clone())]);
+        for holding in holdings {
+            record.push(
+                "852",
+                [' ', ' '],
+                [
+                    ('h', holding.shelf_location.clone()),
+                    ('p', Some(holding.barcode.clone())),
+                ],
+            );
+        }
+        record
+    }
+
+    /// Adds a field with the subfields that have a value, if any do.
+    fn push<const N: usize>(
+        &mut self,
+        tag: &'static str,
+        indicators: [char; 2],
+        subfields: [(char, Option<String>); N],
+    ) {
+        let subfields = subfields
+            .into_iter()
+            .filter_map(|(code, value)| Some((code, value.filter(|v| !v.is_empty())?)))
+            .collect::<Vec<_>>();
+        if !subfields.is_empty() {
+            self.data_fields.push(DataField {
+                tag,
+                indicators,
+                subfields,
+            });
+        }
+    }
+
+    /// The record in ISO 2709, or `None` when it is too long for the format. A field too long
+    /// for its directory entry, in practice a long description, is cut short.
+    pub fn to_iso2709(&self) -> Option<Vec<u8>> {
+        let mut fields: Vec<(&str, Vec<u8>)> = Vec::new();
+        for (tag, value) in &self.control_fields {
+            let mut data = value.as_bytes().to_vec();
+            data.push(FIELD_TERMINATOR);
+            fields.push((tag, data));
+        }
+        for field in &self.data_fields {
+            let mut data = Vec::new();
+            for indicator in field.indicators {
+                data.push(indicator as u8);
+            }
+            for (code, value) in &field.subfields {
+                data.push(SUBFIELD_DELIMITER);
+                data.push(*code as u8);
+                data.extend_from_slice(value.as_bytes());
+            }
+            if data.len() >= MAX_FIELD_BYTES {
+                data.truncate(floor_char_boundary(&data, MAX_FIELD_BYTES - 1));
+            }
+            data.push(FIELD_TERMINATOR);
+            fields.push((field.tag, data));
+        }
+
+        let base_address = LEADER_LENGTH + fields.len() * 12 + 1;
+        let length = base_address + fields.iter().map(|(_, data)| data.len()).sum::<usize>() + 1;
+        if length > MAX_RECORD_BYTES {
+            return None;
+        }
+
+        let mut record = Vec::with_capacity(length);
+        record.extend_from_slice(leader(Some(length), Some(base_address)).as_bytes());
+        let mut start = 0;
+        for (tag, data) in &fields {
+            record.extend_from_slice(format!("{tag}{:04}{start:05}", data.len()).as_bytes());
+            start += data.len();
+        }
+        record.push(FIELD_TERMINATOR);
+        for (_, data) in fields {
+            record.extend_from_slice(&data);
+        }
+        record.push(RECORD_TERMINATOR);
+        Some(record)
+    }
+
+    /// The record as a MARCXML `<record>` element, on one line.
+    pub fn to_xml(&self) -> String {
+        let mut xml = format!("<record><leader>{}</leader>", leader(None, None));
+        for (tag, value) in &self.control_fields {
+            xml.push_str(&format!(
+                "<controlfield tag=\"{tag}\">{}</controlfield>",
+                escape(value.as_str())
+            ));
+        }
+        for field in &self.data_fields {
+            let [ind1, ind2] = field.indicators;
+            xml.push_str(&format!(
+                "<datafield tag=\"{}\" ind1=\"{ind1}\" ind2=\"{ind2}\">",
+                field.tag
+            ));
+            for (code, value) in &field.subfields {
+                xml.push_str(&format!(
+                    "<subfield code=\"{code}\">{}</subfield>",
+                    escape(value.as_str())
+                ));
+            }
+            xml.push_str("</datafield>");
+        }
+        xml.push_str("</record>\n");
+        xml
+    }
+}
+
+/// The 40 characters of field 008: when the record was entered, and the year published.
+fn fixed_data(entered: DateTime<Utc>, published_year: Option<i32>) -> String {
+    let (date_type, year) = match published_year {
+        Some(year) if (0..=9999).contains(&year) => ('s', format!("{year:04}")),
+        _ => ('n', "uuuu".to_string()),
+    };
+    format!(
+        "{}{date_type}{year}{:24}und d",
+        entered.format("%y%m%d"),
+        ""
+    )
+}
+
+/// Leader positions 0 to 23: a new Unicode book record, without ISBD punctuation. MARCXML
+/// leaves the lengths blank.
+fn leader(length: Option<usize>, base_address: Option<usize>) -> String {
+    let number = |n: Option<usize>| n.map_or("     ".to_string(), |n| format!("{n:05}"));
+    format!("{}nam a22{}   4500", number(length), number(base_address))
+}
+
+fn floor_char_boundary(data: &[u8], index: usize) -> usize {
+    (0..=index)
+        .rev()
+        .find(|&i| std::str::from_utf8(&data[..i]).is_ok())
+        .unwrap_or(0)
+}
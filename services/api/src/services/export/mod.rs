@@ -0,0 +1,585 @@
+//! Streaming exports of the catalog, the patrons and circulation. Each export runs one query
+//! with the filters of the matching list endpoint and reads it through a cursor, writing rows
+//! to the response as they arrive; the buffer between the two is bounded, so a slow client
+//! slows the query down rather than letting rows pile up in memory.
+
+mod marc;
+
+use std::io;
+
+use axum::{
+    body::{Body, Bytes},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, TryStreamExt};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    middleware::request_id,
+    models::{
+        Book, BookExportFormat, BookSearchQuery, CheckoutSearchQuery, CheckoutStatus,
+        CheckoutWithDetails, ExportFormat, Redaction, User, UserRole, UserSearchQuery,
+    },
+    repositories::postgres::{
+        books::push_book_filters,
+        checkouts::{details_from_row, push_checkout_filters, DETAILS_SELECT},
+        users::push_user_filters,
+    },
+    services::circulation,
+};
+
+use self::marc::{Holding, MarcRecord};
+
+/// Output is sent to the client in chunks of about this many bytes.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Chunks written ahead of the client.
+const CHUNKS_BUFFERED: usize = 4;
+
+#[derive(Debug, thiserror::Error)]
+enum ExportError {
+    #[error("the client went away")]
+    Disconnected,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Csv(#[from] ::csv::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A file download whose body is written by a background task.
+pub struct Export {
+    content_type: &'static str,
+    filename: String,
+    body: Body,
+}
+
+impl IntoResponse for Export {
+    fn into_response(self) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, self.content_type.to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", self.filename),
+                ),
+            ],
+            self.body,
+        )
+            .into_response()
+    }
+}
+
+/// The writing end of an export's body.
+struct Output {
+    chunks: mpsc::Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+    records: u64,
+}
+
+impl Output {
+    /// Writes `record` as a CSV row, with a header row first, or as a line of JSON.
+    async fn record<T: Serialize>(
+        &mut self,
+        format: ExportFormat,
+        record: &T,
+    ) -> Result<(), ExportError> {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = ::csv::WriterBuilder::new()
+                    .has_headers(self.records == 0)
+                    .from_writer(&mut self.buffer);
+                writer.serialize(record)?;
+                writer.flush().map_err(::csv::Error::from)?;
+            }
+            ExportFormat::Jsonl => {
+                serde_json::to_writer(&mut self.buffer, record)?;
+                self.buffer.push(b'\n');
+            }
+        }
+        self.records += 1;
+        self.flush_if_full().await
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), ExportError> {
+        self.buffer.extend_from_slice(data);
+        self.flush_if_full().await
+    }
+
+    async fn flush_if_full(&mut self) -> Result<(), ExportError> {
+        if self.buffer.len() < CHUNK_BYTES {
+            return Ok(());
+        }
+        self.flush().await
+    }
+
+    async fn flush(&mut self) -> Result<(), ExportError> {
+        let chunk = Bytes::from(std::mem::take(&mut self.buffer));
+        self.chunks
+            .send(Ok(chunk))
+            .await
+            .map_err(|_| ExportError::Disconnected)
+    }
+
+    /// Sends what's left, or on failure ends the body with an error so that the client sees
+    /// a broken download rather than a short file.
+    async fn finish(mut self, name: &str, result: Result<(), ExportError>) {
+        let result = match result {
+            Ok(()) => self.flush().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => debug!("Exported {} {name}", self.records),
+            Err(ExportError::Disconnected) => {
+                debug!(
+                    "The client left the {name} export after {} records",
+                    self.records
+                )
+            }
+            Err(e) => {
+                warn!(
+                    "The {name} export failed after {} records: {e}",
+                    self.records
+                );
+                let _ = self.chunks.send(Err(io::Error::other(e))).await;
+            }
+        }
+    }
+}
+
+/// Runs `write` in the background, in the request's span, streaming what it writes.
+fn spawn<F, Fut>(name: &'static str, write: F) -> Body
+where
+    F: FnOnce(Output) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = (Output, Result<(), ExportError>)> + Send,
+{
+    let (chunks, receiver) = mpsc::channel(CHUNKS_BUFFERED);
+    let output = Output {
+        chunks,
+        buffer: Vec::with_capacity(CHUNK_BYTES),
+        records: 0,
+    };
+    request_id::spawn(async move {
+        let (output, result) = write(output).await;
+        output.finish(name, result).await;
+    });
+
+    Body::from_stream(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    }))
+}
+
+/// Orders like the list endpoints, which are newest first; paging applies only when asked
+/// for, so by default an export has every match.
+fn push_paging(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    created_at: &str,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) {
+    builder.push(format!(" ORDER BY {created_at} DESC"));
+    if let Some(limit) = limit {
+        builder.push(" LIMIT ").push_bind(limit);
+    }
+    if let Some(offset) = offset {
+        builder.push(" OFFSET ").push_bind(offset);
+    }
+}
+
+fn filename(name: &str, now: DateTime<Utc>, extension: &str) -> String {
+    format!("{name}-{}.{extension}", now.format("%Y%m%d"))
+}
+
+/// A book with its copies, for MARC holdings.
+#[derive(FromRow)]
+struct BookWithHoldings {
+    #[sqlx(flatten)]
+    book: Book,
+    /// A JSON array of [`Holding`]s.
+    holdings: String,
+}
+
+/// Books matching `query`, as the list endpoint would return them.
+pub fn books(
+    db: PgPool,
+    query: BookSearchQuery,
+    format: BookExportFormat,
+    now: DateTime<Utc>,
+) -> Export {
+    let (content_type, extension) = match format {
+        BookExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        BookExportFormat::Jsonl => ("application/x-ndjson", "jsonl"),
+        BookExportFormat::Marc21 => ("application/marc", "mrc"),
+        BookExportFormat::Marcxml => ("application/marcxml+xml", "xml"),
+    };
+    let body = spawn("books", move |mut out| async move {
+        let result = write_books(&db, &query, format, &mut out).await;
+        (out, result)
+    });
+
+    Export {
+        content_type,
+        filename: filename("books", now, extension),
+        body,
+    }
+}
+
+async fn write_books(
+    db: &PgPool,
+    query: &BookSearchQuery,
+    format: BookExportFormat,
+    out: &mut Output,
+) -> Result<(), ExportError> {
+    let format = match format {
+        BookExportFormat::Csv => ExportFormat::Csv,
+        BookExportFormat::Jsonl => ExportFormat::Jsonl,
+        BookExportFormat::Marc21 | BookExportFormat::Marcxml => {
+            return write_marc(db, query, format == BookExportFormat::Marcxml, out).await;
+        }
+    };
+
+    let mut select = QueryBuilder::<Postgres>::new("SELECT * FROM books");
+    push_book_filters(&mut select, query);
+    push_paging(&mut select, "created_at", query.limit, query.offset);
+
+    let mut books = select.build_query_as::<Book>().fetch(db);
+    while let Some(book) = books.try_next().await? {
+        out.record(format, &book).await?;
+    }
+    Ok(())
+}
+
+async fn write_marc(
+    db: &PgPool,
+    query: &BookSearchQuery,
+    xml: bool,
+    out: &mut Output,
+) -> Result<(), ExportError> {
+    let mut select = QueryBuilder::<Postgres>::new(
+        r#"
+        SELECT books.*, (
+            SELECT COALESCE(
+                json_agg(
+                    json_build_object('barcode', c.barcode, 'shelf_location', c.shelf_location)
+                    ORDER BY c.created_at
+                ),
+                '[]'
+            )
+            FROM book_copies c
+            WHERE c.book_id = books.id AND c.status <> 'WITHDRAWN'
+        )::text AS holdings
+        FROM books
+        "#,
+    );
+    push_book_filters(&mut select, query);
+    push_paging(&mut select, "created_at", query.limit, query.offset);
+
+    if xml {
+        out.write(marc::XML_HEADER.as_bytes()).await?;
+    }
+    let mut books = select.build_query_as::<BookWithHoldings>().fetch(db);
+    while let Some(BookWithHoldings { book, holdings }) = books.try_next().await? {
+        let holdings: Vec<Holding> = serde_json::from_str(&holdings)?;
+        let record = MarcRecord::new(&book, &holdings);
+        if xml {
+            out.write(record.to_xml().as_bytes()).await?;
+        } else if let Some(record) = record.to_iso2709() {
+            out.write(&record).await?;
+        } else {
+            warn!(
+                "Book {} is too long for a MARC record; left out of the export",
+                book.id
+            );
+        }
+        out.records += 1;
+    }
+    if xml {
+        out.write(marc::XML_FOOTER.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn redact_name(name: String, redaction: Redaction) -> Option<String> {
+    match redaction {
+        Redaction::None => Some(name),
+        Redaction::Mask => Some(
+            name.split_whitespace()
+                .filter_map(|word| word.chars().next())
+                .map(|initial| format!("{initial}."))
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        Redaction::Omit => None,
+    }
+}
+
+fn redact_email(email: String, redaction: Redaction) -> Option<String> {
+    match redaction {
+        Redaction::None => Some(email),
+        Redaction::Mask => Some(match email.split_once('@') {
+            Some((local, domain)) => match local.chars().next() {
+                Some(first) => format!("{first}***@{domain}"),
+                None => format!("***@{domain}"),
+            },
+            None => "***".to_string(),
+        }),
+        Redaction::Omit => None,
+    }
+}
+
+/// A user as exported. Redacted fields are left out rather than left empty.
+#[derive(Serialize)]
+struct UserRecord {
+    id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    role: UserRole,
+    is_active: bool,
+    max_checkouts: i32,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl UserRecord {
+    fn new(user: User, redaction: Redaction) -> Self {
+        Self {
+            id: user.id,
+            name: redact_name(user.name, redaction),
+            email: redact_email(user.email, redaction),
+            role: user.role,
+            is_active: user.is_active,
+            max_checkouts: user.max_checkouts,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+/// Users matching `query`, as the list endpoint would return them, with names and email
+/// addresses redacted as asked.
+pub fn users(
+    db: PgPool,
+    query: UserSearchQuery,
+    format: ExportFormat,
+    redaction: Redaction,
+    now: DateTime<Utc>,
+) -> Export {
+    let body = spawn("users", move |mut out| async move {
+        let result = async {
+            let mut select = QueryBuilder::<Postgres>::new("SELECT * FROM users");
+            push_user_filters(&mut select, &query);
+            push_paging(&mut select, "created_at", query.limit, query.offset);
+
+            let mut users = select.build_query_as::<User>().fetch(&db);
+            while let Some(user) = users.try_next().await? {
+                out.record(format, &UserRecord::new(user, redaction))
+                    .await?;
+            }
+            Ok(())
+        }
+        .await;
+        (out, result)
+    });
+
+    Export {
+        content_type: content_type(format),
+        filename: filename("users", now, extension(format)),
+        body,
+    }
+}
+
+/// A checkout as exported: flat, so that it fits a CSV row.
+#[derive(Serialize)]
+struct CheckoutRecord {
+    id: Uuid,
+    user_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_email: Option<String>,
+    book_id: Uuid,
+    book_isbn: String,
+    book_title: String,
+    book_author: String,
+    copy_id: Option<Uuid>,
+    status: CheckoutStatus,
+    checked_out_at: DateTime<Utc>,
+    due_date: DateTime<Utc>,
+    returned_at: Option<DateTime<Utc>>,
+    renewal_count: i32,
+    max_renewals: i32,
+    overdue_email_sent: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl CheckoutRecord {
+    fn new(details: CheckoutWithDetails, redaction: Redaction) -> Self {
+        let CheckoutWithDetails {
+            checkout,
+            user,
+            book,
+        } = details;
+        Self {
+            id: checkout.id,
+            user_id: checkout.user_id,
+            user_name: redact_name(user.name, redaction),
+            user_email: redact_email(user.email, redaction),
+            book_id: checkout.book_id,
+            book_isbn: book.isbn,
+            book_title: book.title,
+            book_author: book.author,
+            copy_id: checkout.copy_id,
+            status: checkout.status,
+            checked_out_at: checkout.checked_out_at,
+            due_date: checkout.due_date,
+            returned_at: checkout.returned_at,
+            renewal_count: checkout.renewal_count,
+            max_renewals: checkout.max_renewals,
+            overdue_email_sent: checkout.overdue_email_sent,
+            created_at: checkout.created_at,
+            updated_at: checkout.updated_at,
+        }
+    }
+}
+
+/// Checkouts matching `query`, as the list endpoint would return them, with borrowers'
+/// names and email addresses redacted as asked.
+pub fn checkouts(
+    db: PgPool,
+    query: CheckoutSearchQuery,
+    format: ExportFormat,
+    redaction: Redaction,
+    now: DateTime<Utc>,
+) -> Export {
+    let filter = circulation::filter_for(&query, now);
+    let body = spawn("checkouts", move |mut out| async move {
+        let result = async {
+            let mut select = QueryBuilder::<Postgres>::new(DETAILS_SELECT);
+            push_checkout_filters(&mut select, &filter);
+            push_paging(&mut select, "c.created_at", query.limit, query.offset);
+
+            let mut rows = select.build().fetch(&db);
+            while let Some(row) = rows.try_next().await? {
+                let checkout = details_from_row(&row)?;
+                out.record(format, &CheckoutRecord::new(checkout, redaction))
+                    .await?;
+            }
+            Ok(())
+        }
+        .await;
+        (out, result)
+    });
+
+    Export {
+        content_type: content_type(format),
+        filename: filename("checkouts", now, extension(format)),
+        body,
+    }
+}
+
+fn content_type(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "text/csv; charset=utf-8",
+        ExportFormat::Jsonl => "application/x-ndjson",
+    }
+}
+
+fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Csv => "csv",
+        ExportFormat::Jsonl => "jsonl",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::BookImportFormat, services::catalog_import};
+
+    use super::*;
+
+    #[test]
+    fn test_names_and_emails_are_redacted() {
+        let name = || "Reed van Richards".to_string();
+        let email = || "reed@example.com".to_string();
+
+        assert_eq!(redact_name(name(), Redaction::None), Some(name()));
+        assert_eq!(
+            redact_name(name(), Redaction::Mask).as_deref(),
+            Some("R. v. R.")
+        );
+        assert_eq!(redact_name(name(), Redaction::Omit), None);
+
+        assert_eq!(
+            redact_email(email(), Redaction::Mask).as_deref(),
+            Some("r***@example.com")
+        );
+        assert_eq!(
+            redact_email("nobody".to_string(), Redaction::Mask).as_deref(),
+            Some("***")
+        );
+        assert_eq!(redact_email(email(), Redaction::Omit), None);
+    }
+
+    #[test]
+    fn test_marc_records_read_back_through_the_import() {
+        let now = Utc::now();
+        let book = Book {
+            id: Uuid::new_v4(),
+            isbn: "9780141439587".to_string(),
+            title: "Emma: a novel & more".to_string(),
+            author: "Jane Austen".to_string(),
+            publisher: Some("Penguin".to_string()),
+            published_year: Some(2003),
+            genre: Some("Fiction".to_string()),
+            description: Some("é".repeat(6000)),
+            cover_url: None,
+            total_copies: 1,
+            available_copies: 1,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        let holdings = [Holding {
+            barcode: "B-0001".to_string(),
+            shelf_location: Some("FIC AUS".to_string()),
+        }];
+        let record = MarcRecord::new(&book, &holdings);
+
+        let iso2709 = record.to_iso2709().unwrap();
+        let xml = format!(
+            "{}{}{}",
+            marc::XML_HEADER,
+            record.to_xml(),
+            marc::XML_FOOTER
+        );
+        for (format, data) in [
+            (BookImportFormat::Marc21, iso2709.as_slice()),
+            (BookImportFormat::Marcxml, xml.as_bytes()),
+        ] {
+            let records = catalog_import::parse(format, data).unwrap();
+            let raw = records[0].as_ref().unwrap();
+            assert_eq!(raw.isbn.as_deref(), Some("9780141439587"));
+            assert_eq!(raw.title.as_deref(), Some("Emma: a novel & more"));
+            assert_eq!(raw.author.as_deref(), Some("Jane Austen"));
+            assert_eq!(raw.publisher.as_deref(), Some("Penguin"));
+            assert_eq!(raw.published_year.as_deref(), Some("2003"));
+            assert_eq!(raw.genre.as_deref(), Some("Fiction"));
+            assert_eq!(raw.holdings[0].barcode.as_deref(), Some("B-0001"));
+            assert_eq!(raw.holdings[0].shelf_location.as_deref(), Some("FIC AUS"));
+        }
+        // The description is longer than a field may be, so ISO 2709 cuts it short.
+        let records = catalog_import::parse(BookImportFormat::Marc21, &iso2709).unwrap();
+        let description = records[0].as_ref().unwrap().description.clone().unwrap();
+        assert!(description.len() < 9999 && description.chars().all(|c| c == 'é'));
+    }
+}
@@ -0,0 +1,212 @@
+//! Readiness checks. Each component reports its own status; the instance is ready when the
+//! database, migrations and scheduler are up. Supabase and Resend only degrade it, since
+//! requests are still served without them.
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::{services::email::Resend, AppState, MIGRATOR};
+
+/// How long a single check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the scheduler may go without a heartbeat before it counts as stalled.
+const SCHEDULER_STALL_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Up,
+    /// An optional backend is failing; the instance still serves requests.
+    Degraded,
+    Down,
+    /// Not configured, or not run on this instance.
+    Disabled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+impl Check {
+    fn up(details: Value) -> Self {
+        Self {
+            status: CheckStatus::Up,
+            error: None,
+            details,
+        }
+    }
+
+    fn failed(status: CheckStatus, error: impl Into<String>, details: Value) -> Self {
+        Self {
+            status,
+            error: Some(error.into()),
+            details,
+        }
+    }
+
+    fn disabled() -> Self {
+        Self {
+            status: CheckStatus::Disabled,
+            error: None,
+            details: Value::Null,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessStatus {
+    Ready,
+    Degraded,
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Readiness {
+    fn from_checks(checks: BTreeMap<&'static str, Check>) -> Self {
+        let status = if checks.values().any(|c| c.status == CheckStatus::Down) {
+            ReadinessStatus::Unavailable
+        } else if checks.values().any(|c| c.status == CheckStatus::Degraded) {
+            ReadinessStatus::Degraded
+        } else {
+            ReadinessStatus::Ready
+        };
+
+        Self { status, checks }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status != ReadinessStatus::Unavailable
+    }
+}
+
+/// Runs every check concurrently.
+pub async fn readiness(state: &AppState) -> Readiness {
+    let resend = Resend::from_config(&state.config.email);
+    let (database, migrations, supabase, email) = tokio::join!(
+        database(&state.db),
+        migrations(&state.db),
+        backend(state.supabase_sync.as_ref().map(|s| s.ping(CHECK_TIMEOUT))),
+        backend(resend.as_ref().map(|r| r.ping(CHECK_TIMEOUT))),
+    );
+
+    Readiness::from_checks(BTreeMap::from([
+        ("database", database),
+        ("migrations", migrations),
+        ("scheduler", scheduler(state)),
+        ("supabase", supabase),
+        ("email", email),
+    ]))
+}
+
+/// Connectivity, plus how much of the pool is in use. A saturated pool shows up as a
+/// timeout waiting for a connection.
+async fn database(db: &PgPool) -> Check {
+    let (size, idle) = (db.size(), db.num_idle() as u32);
+    let max = db.options().get_max_connections();
+    let details =
+        json!({ "pool": { "size": size, "idle": idle, "in_use": size - idle, "max": max } });
+    let saturated = size >= max && idle == 0;
+
+    match tokio::time::timeout(CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(db)).await {
+        Ok(Ok(_)) => Check::up(details),
+        Ok(Err(e)) => Check::failed(CheckStatus::Down, e.to_string(), details),
+        Err(_) if saturated => {
+            Check::failed(CheckStatus::Down, "connection pool saturated", details)
+        }
+        Err(_) => Check::failed(CheckStatus::Down, "timed out", details),
+    }
+}
+
+/// The database must have every migration this build knows about, and none failed. A
+/// newer schema is fine; it happens while a rollout is in progress.
+async fn migrations(db: &PgPool) -> Check {
+    let expected = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    let query = sqlx::query_as::<_, (i64, bool)>(
+        "SELECT version, success FROM _sqlx_migrations ORDER BY version",
+    )
+    .fetch_all(db);
+
+    let applied = match tokio::time::timeout(CHECK_TIMEOUT, query).await {
+        Ok(Ok(applied)) => applied,
+        Ok(Err(e)) => return Check::failed(CheckStatus::Down, e.to_string(), Value::Null),
+        Err(_) => return Check::failed(CheckStatus::Down, "timed out", Value::Null),
+    };
+
+    let version = applied
+        .iter()
+        .filter(|(_, success)| *success)
+        .map(|(version, _)| *version)
+        .max();
+    let details = json!({ "version": version, "expected": expected });
+
+    if let Some((failed, _)) = applied.iter().find(|(_, success)| !success) {
+        Check::failed(
+            CheckStatus::Down,
+            format!("migration {failed} failed"),
+            details,
+        )
+    } else if version.unwrap_or(0) < expected {
+        Check::failed(CheckStatus::Down, "migrations pending", details)
+    } else {
+        Check::up(details)
+    }
+}
+
+fn scheduler(state: &AppState) -> Check {
+    let Some(heartbeat) = state.jobs.heartbeat() else {
+        return Check::disabled();
+    };
+
+    let silent_for = (state.clock.now() - heartbeat).num_seconds();
+    let details = json!({ "last_heartbeat": heartbeat });
+    if silent_for > SCHEDULER_STALL_SECS {
+        Check::failed(
+            CheckStatus::Down,
+            format!("no heartbeat for {silent_for}s"),
+            details,
+        )
+    } else {
+        Check::up(details)
+    }
+}
+
+/// An optional HTTP backend: reachable unless it errors or answers with a server error.
+async fn backend<F>(ping: Option<F>) -> Check
+where
+    F: Future<Output = reqwest::Result<reqwest::StatusCode>>,
+{
+    let Some(ping) = ping else {
+        return Check::disabled();
+    };
+
+    let started = Instant::now();
+    let result = ping.await;
+    let details = json!({ "latency_ms": started.elapsed().as_millis() as u64 });
+
+    match result {
+        Ok(status) if status.is_server_error() => {
+            Check::failed(CheckStatus::Degraded, format!("HTTP {status}"), details)
+        }
+        Ok(_) => Check::up(details),
+        Err(e) => Check::failed(CheckStatus::Degraded, e.to_string(), details),
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgPool};
+use tracing::info;
+use uuid::Uuid;
+
+pub const DEFAULT_TTL_HOURS: i32 = 24;
+
+/// A response captured the first time a key was used.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: i32,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Claim {
+    /// First use of the key; the caller should run the request and store the result.
+    New,
+    /// The key already completed with an identical request.
+    Replay(StoredResponse),
+    /// Another request with this key is still being processed.
+    InProgress,
+    /// The key was used before for a different request.
+    Mismatch,
+}
+
+#[derive(FromRow)]
+struct IdempotencyRecord {
+    request_hash: String,
+    response_status: Option<i32>,
+    response_content_type: Option<String>,
+    response_body: Option<Vec<u8>>,
+}
+
+/// Hashes what makes two requests "the same" for the purpose of replaying a response.
+pub fn fingerprint(method: &str, path: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_bytes());
+    hasher.update(b" ");
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// Reserves `key` for this user, or reports what happened to the earlier request that used it.
+///
+/// Keys older than `ttl_hours` are treated as unused even if the cleanup job hasn't removed
+/// them yet.
+pub async fn claim(
+    db: &PgPool,
+    user_id: Uuid,
+    key: &str,
+    request_hash: &str,
+    ttl_hours: i32,
+) -> sqlx::Result<Claim> {
+    let inserted: Option<String> = sqlx::query_scalar(
+        r#"
+        INSERT INTO idempotency_keys (user_id, key, request_hash)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, key) DO UPDATE
+        SET request_hash = EXCLUDED.request_hash,
+            response_status = NULL,
+            response_content_type = NULL,
+            response_body = NULL,
+            created_at = NOW(),
+            completed_at = NULL
+        WHERE idempotency_keys.created_at < NOW() - make_interval(hours => $4)
+        RETURNING key
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(request_hash)
+    .bind(ttl_hours)
+    .fetch_optional(db)
+    .await?;
+
+    if inserted.is_some() {
+        return Ok(Claim::New);
+    }
+
+    let existing = sqlx::query_as::<_, IdempotencyRecord>(
+        r#"
+        SELECT request_hash, response_status, response_content_type, response_body
+        FROM idempotency_keys
+        WHERE user_id = $1 AND key = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .fetch_one(db)
+    .await?;
+
+    if existing.request_hash != request_hash {
+        return Ok(Claim::Mismatch);
+    }
+
+    Ok(match existing.response_status {
+        Some(status) => Claim::Replay(StoredResponse {
+            status,
+            content_type: existing.response_content_type,
+            body: existing.response_body.unwrap_or_default(),
+        }),
+        None => Claim::InProgress,
+    })
+}
+
+pub async fn complete(
+    db: &PgPool,
+    user_id: Uuid,
+    key: &str,
+    response: &StoredResponse,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE idempotency_keys
+        SET response_status = $3,
+            response_content_type = $4,
+            response_body = $5,
+            completed_at = NOW()
+        WHERE user_id = $1 AND key = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(response.status)
+    .bind(&response.content_type)
+    .bind(&response.body)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Gives up a claimed key so the client can retry, used when the request failed server-side.
+pub async fn release(db: &PgPool, user_id: Uuid, key: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM idempotency_keys WHERE user_id = $1 AND key = $2")
+        .bind(user_id)
+        .bind(key)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes keys older than `ttl_hours`, or with `dry_run` just counts them.
+pub async fn purge_expired(db: &PgPool, ttl_hours: i32, dry_run: bool) -> Result<u64> {
+    if dry_run {
+        let expired: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM idempotency_keys WHERE created_at < NOW() - make_interval(hours => $1)",
+        )
+        .bind(ttl_hours)
+        .fetch_one(db)
+        .await?;
+        return Ok(expired as u64);
+    }
+
+    let deleted = sqlx::query(
+        "DELETE FROM idempotency_keys WHERE created_at < NOW() - make_interval(hours => $1)",
+    )
+    .bind(ttl_hours)
+    .execute(db)
+    .await?
+    .rows_affected();
+
+    if deleted > 0 {
+        info!("Purged {} expired idempotency keys", deleted);
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_distinguishes_body_and_route() {
+        let renew = fingerprint("POST", "/renew", br#"{"checkout_id":"a"}"#);
+
+        assert_eq!(
+            renew,
+            fingerprint("POST", "/renew", br#"{"checkout_id":"a"}"#)
+        );
+        assert_ne!(
+            renew,
+            fingerprint("POST", "/renew", br#"{"checkout_id":"b"}"#)
+        );
+        assert_ne!(
+            renew,
+            fingerprint("POST", "/return", br#"{"checkout_id":"a"}"#)
+        );
+    }
+}
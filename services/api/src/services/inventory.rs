@@ -0,0 +1,348 @@
+use anyhow::Result;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::models::{Book, BookCopy, CopyCondition, InventoryDiscrepancy};
+
+/// Barcode assigned to copies that arrive without one, e.g. `LIB-3F2A9C01B7E4`.
+pub fn generate_barcode(copy_id: Uuid) -> String {
+    format!("LIB-{}", &copy_id.simple().to_string()[..12].to_uppercase())
+}
+
+pub async fn insert_copy<'e, E>(
+    executor: E,
+    book_id: Uuid,
+    barcode: Option<String>,
+    shelf_location: Option<String>,
+    condition: CopyCondition,
+) -> sqlx::Result<BookCopy>
+where
+    E: PgExecutor<'e>,
+{
+    let copy_id = Uuid::new_v4();
+    let barcode = barcode.unwrap_or_else(|| generate_barcode(copy_id));
+
+    sqlx::query_as::<_, BookCopy>(
+        r#"
+        INSERT INTO book_copies (id, book_id, barcode, shelf_location, condition)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+    )
+    .bind(copy_id)
+    .bind(book_id)
+    .bind(barcode)
+    .bind(shelf_location)
+    .bind(condition)
+    .fetch_one(executor)
+    .await
+}
+
+/// Atomically moves the first available copy of a book to ON_LOAN.
+///
+/// Copies locked by a concurrent checkout are skipped rather than waited on, so two
+/// requests can never claim the same copy.
+pub async fn claim_available_copy<'e, E>(
+    executor: E,
+    book_id: Uuid,
+) -> sqlx::Result<Option<BookCopy>>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query_as::<_, BookCopy>(
+        r#"
+        UPDATE book_copies
+        SET status = 'ON_LOAN', updated_at = NOW()
+        WHERE id = (
+            SELECT id FROM book_copies
+            WHERE book_id = $1 AND status = 'AVAILABLE'
+            ORDER BY barcode
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(book_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Moves a specific copy to ON_LOAN, returning `None` if it isn't available.
+pub async fn claim_copy<'e, E>(executor: E, copy_id: Uuid) -> sqlx::Result<Option<BookCopy>>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query_as::<_, BookCopy>(
+        r#"
+        UPDATE book_copies
+        SET status = 'ON_LOAN', updated_at = NOW()
+        WHERE id = $1 AND status = 'AVAILABLE'
+        RETURNING *
+        "#,
+    )
+    .bind(copy_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Moves a copy on loan back to AVAILABLE. Copies staff marked lost or in repair while
+/// they were out keep that status.
+pub async fn release_copy<'e, E>(executor: E, copy_id: Uuid) -> sqlx::Result<()>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query(
+        r#"
+        UPDATE book_copies
+        SET status = 'AVAILABLE', updated_at = NOW()
+        WHERE id = $1 AND status = 'ON_LOAN'
+        "#,
+    )
+    .bind(copy_id)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Recomputes a book's `total_copies` and `available_copies` from the status of its copies.
+///
+/// Must be called in the same transaction as any change to `book_copies`, after taking a
+/// `FOR UPDATE` lock on the book row so the counts see every committed claim.
+pub async fn refresh_availability<'e, E>(executor: E, book_id: Uuid) -> sqlx::Result<Book>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query_as::<_, Book>(
+        r#"
+        UPDATE books
+        SET total_copies = (
+                SELECT COUNT(*) FROM book_copies
+                WHERE book_id = $1 AND status <> 'WITHDRAWN'
+            ),
+            available_copies = (
+                SELECT COUNT(*) FROM book_copies
+                WHERE book_id = $1 AND status = 'AVAILABLE'
+            ),
+            updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(book_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Lists books whose counters disagree with their copies, or whose copies on loan don't
+/// match their active checkouts. Expected availability is lendable copies (available or
+/// on loan) minus active checkouts.
+pub async fn find_discrepancies<'e, E>(executor: E) -> sqlx::Result<Vec<InventoryDiscrepancy>>
+where
+    E: PgExecutor<'e>,
+{
+    sqlx::query_as::<_, InventoryDiscrepancy>(
+        r#"
+        WITH copy_counts AS (
+            SELECT
+                book_id,
+                COUNT(*) FILTER (WHERE status <> 'WITHDRAWN') AS total,
+                COUNT(*) FILTER (WHERE status IN ('AVAILABLE', 'ON_LOAN')) AS lendable,
+                COUNT(*) FILTER (WHERE status = 'ON_LOAN') AS on_loan
+            FROM book_copies
+            GROUP BY book_id
+        ), loan_counts AS (
+            SELECT book_id, COUNT(*) AS active
+            FROM checkouts
+            WHERE status = 'ACTIVE'
+            GROUP BY book_id
+        )
+        SELECT
+            b.id AS book_id,
+            b.isbn,
+            b.title,
+            b.total_copies,
+            b.available_copies,
+            COALESCE(cc.total, 0) AS expected_total_copies,
+            COALESCE(cc.lendable, 0) - COALESCE(lc.active, 0) AS expected_available_copies,
+            COALESCE(cc.on_loan, 0) AS copies_on_loan,
+            COALESCE(lc.active, 0) AS active_checkouts
+        FROM books b
+        LEFT JOIN copy_counts cc ON cc.book_id = b.id
+        LEFT JOIN loan_counts lc ON lc.book_id = b.id
+        WHERE b.total_copies <> COALESCE(cc.total, 0)
+        OR b.available_copies <> COALESCE(cc.lendable, 0) - COALESCE(lc.active, 0)
+        OR COALESCE(cc.on_loan, 0) <> COALESCE(lc.active, 0)
+        ORDER BY b.title
+        "#,
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Scheduled invariant check; logs every discrepancy and returns how many were found.
+pub async fn check_consistency(db: &PgPool) -> Result<usize> {
+    let discrepancies = find_discrepancies(db).await?;
+
+    for d in &discrepancies {
+        warn!(
+            "Inventory mismatch for book {} ({}): total {} (expected {}), available {} (expected {}), {} copies on loan vs {} active checkouts",
+            d.book_id,
+            d.isbn,
+            d.total_copies,
+            d.expected_total_copies,
+            d.available_copies,
+            d.expected_available_copies,
+            d.copies_on_loan,
+            d.active_checkouts
+        );
+    }
+
+    info!(
+        "Inventory consistency check found {} discrepancies",
+        discrepancies.len()
+    );
+
+    Ok(discrepancies.len())
+}
+
+/// Reconciles a book's copies with its active checkouts and recomputes its counters.
+///
+/// Active loans without a copy are given a free one, copies marked on loan with no active
+/// checkout go back on the shelf, and copies lent out but still marked available are
+/// flagged as on loan.
+pub async fn repair_book(conn: &mut PgConnection, book_id: Uuid) -> sqlx::Result<Book> {
+    let unassigned_loans: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT id FROM checkouts
+        WHERE book_id = $1 AND status = 'ACTIVE' AND copy_id IS NULL
+        ORDER BY checked_out_at
+        "#,
+    )
+    .bind(book_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for checkout_id in unassigned_loans {
+        let Some(copy) = claim_available_copy(&mut *conn, book_id).await? else {
+            break;
+        };
+
+        sqlx::query("UPDATE checkouts SET copy_id = $2, updated_at = NOW() WHERE id = $1")
+            .bind(checkout_id)
+            .bind(copy.id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE book_copies bc
+        SET status = 'AVAILABLE', updated_at = NOW()
+        WHERE bc.book_id = $1
+        AND bc.status = 'ON_LOAN'
+        AND NOT EXISTS (
+            SELECT 1 FROM checkouts c WHERE c.copy_id = bc.id AND c.status = 'ACTIVE'
+        )
+        "#,
+    )
+    .bind(book_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE book_copies bc
+        SET status = 'ON_LOAN', updated_at = NOW()
+        WHERE bc.book_id = $1
+        AND bc.status <> 'ON_LOAN'
+        AND EXISTS (
+            SELECT 1 FROM checkouts c WHERE c.copy_id = bc.id AND c.status = 'ACTIVE'
+        )
+        "#,
+    )
+    .bind(book_id)
+    .execute(&mut *conn)
+    .await?;
+
+    refresh_availability(&mut *conn, book_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestApp;
+
+    #[test]
+    fn test_generated_barcode_format() {
+        let barcode = generate_barcode(Uuid::new_v4());
+
+        assert_eq!(barcode.len(), 16);
+        assert!(barcode.starts_with("LIB-"));
+        assert!(barcode[4..]
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_lowercase()));
+    }
+
+    #[tokio::test]
+    async fn test_discrepancies_are_found_and_repaired() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        assert!(find_discrepancies(app.pool()).await.unwrap().is_empty());
+
+        // Dune's counter drifts; Emma's only copy is marked on loan with no checkout.
+        sqlx::query("UPDATE books SET available_copies = 0 WHERE id = $1")
+            .bind(fixtures.dune.id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+        sqlx::query("UPDATE book_copies SET status = 'ON_LOAN' WHERE book_id = $1")
+            .bind(fixtures.emma.id)
+            .execute(app.pool())
+            .await
+            .unwrap();
+
+        let discrepancies = find_discrepancies(app.pool()).await.unwrap();
+        assert_eq!(discrepancies.len(), 2);
+        let dune = &discrepancies[0];
+        assert_eq!(dune.book_id, fixtures.dune.id);
+        assert_eq!(
+            (dune.available_copies, dune.expected_available_copies),
+            (0, 2)
+        );
+        let emma = &discrepancies[1];
+        assert_eq!(emma.book_id, fixtures.emma.id);
+        assert_eq!((emma.copies_on_loan, emma.active_checkouts), (1, 0));
+
+        let mut conn = app.pool().acquire().await.unwrap();
+        let dune = repair_book(&mut conn, fixtures.dune.id).await.unwrap();
+        assert_eq!((dune.total_copies, dune.available_copies), (2, 2));
+        let emma = repair_book(&mut conn, fixtures.emma.id).await.unwrap();
+        assert_eq!((emma.total_copies, emma.available_copies), (1, 1));
+        assert!(find_discrepancies(app.pool()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_copy_counters_are_constrained() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+
+        for (total, available) in [(-1, 0), (2, -1), (2, 3)] {
+            let update = sqlx::query(
+                "UPDATE books SET total_copies = $2, available_copies = $3 WHERE id = $1",
+            )
+            .bind(fixtures.dune.id)
+            .bind(total)
+            .bind(available)
+            .execute(app.pool())
+            .await;
+            assert!(update.is_err(), "total {total}, available {available}");
+        }
+    }
+}
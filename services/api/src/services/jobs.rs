@@ -0,0 +1,789 @@
+//! Scheduled jobs: a registry of the periodic tasks, run on their schedules or by hand from the
+//! admin API. Runs are claimed in Postgres so replicas neither duplicate nor overlap them, and
+//! every run is recorded in `job_runs`.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use cron::Schedule;
+use sqlx::{Connection, PgConnection, PgPool};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, RateLimitStore},
+    middleware::{rate_limit::RateLimitPolicies, request_id},
+    models::{JobError, JobRun, JobRunSource, JobRunStatus, JobStatus},
+    services::{
+        self,
+        clock::{Clock, SystemClock},
+        email::Resend,
+    },
+    shutdown::JobRuns,
+};
+
+/// How far apart the replicas' clocks may be while still agreeing on which occurrence is due.
+const CLOCK_SKEW_SECS: i64 = 30;
+
+/// How often a running scheduler records its heartbeat.
+const HEARTBEAT_CRON: &str = "*/15 * * * * *";
+
+/// What a job gets for one run.
+#[derive(Clone)]
+pub struct JobContext {
+    /// The time the run is for; jobs use it instead of reading the clock.
+    pub now: DateTime<Utc>,
+    /// Report what would be processed without changing anything.
+    pub dry_run: bool,
+    /// Cancelled at shutdown; long jobs check it between units of work.
+    pub stop: CancellationToken,
+}
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<u64>> + Send>>;
+type JobFn = Arc<dyn Fn(JobContext) -> JobFuture + Send + Sync>;
+
+/// A registered job. The function returns how many items it processed.
+pub struct JobDefinition {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub cron: String,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RunError {
+    #[error("no such job")]
+    NotFound,
+    #[error("the job is already running")]
+    AlreadyRunning,
+    #[error("the server is shutting down")]
+    ShuttingDown,
+    #[error("the job panicked")]
+    Panicked,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<RunError> for StatusCode {
+    fn from(err: RunError) -> Self {
+        match err {
+            RunError::NotFound => StatusCode::NOT_FOUND,
+            RunError::AlreadyRunning => StatusCode::CONFLICT,
+            RunError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            RunError::Panicked | RunError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The occurrence of `schedule` nearest to `now`. Replicas that fire for the same occurrence
+/// at slightly different times agree on it, which is what makes it usable as a claim key.
+pub fn occurrence(schedule: &Schedule, now: DateTime<Utc>) -> DateTime<Utc> {
+    let skew = Duration::seconds(CLOCK_SKEW_SECS);
+    schedule
+        .after(&(now - skew))
+        .take_while(|tick| *tick <= now + skew)
+        .min_by_key(|tick| (*tick - now).num_milliseconds().abs())
+        .unwrap_or(now)
+}
+
+enum Trigger {
+    Schedule {
+        scheduled_for: DateTime<Utc>,
+    },
+    Manual {
+        dry_run: bool,
+        requested_by: Option<Uuid>,
+    },
+}
+
+/// Records the start of a run. A scheduled run is refused, returning `None`, when the job is
+/// paused or another instance already claimed the occurrence.
+async fn start_run(
+    db: &PgPool,
+    job_name: &str,
+    instance_id: &str,
+    now: DateTime<Utc>,
+    trigger: &Trigger,
+) -> sqlx::Result<Option<JobRun>> {
+    let (scheduled_for, source, dry_run, requested_by) = match *trigger {
+        Trigger::Schedule { scheduled_for } => (scheduled_for, JobRunSource::Schedule, false, None),
+        Trigger::Manual {
+            dry_run,
+            requested_by,
+        } => (now, JobRunSource::Manual, dry_run, requested_by),
+    };
+
+    sqlx::query_as(
+        r#"
+        INSERT INTO job_runs (
+            id, job_name, scheduled_for, instance_id, started_at, source, dry_run, requested_by
+        )
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8
+        WHERE $6 = 'MANUAL'
+        OR NOT EXISTS (SELECT 1 FROM job_pauses WHERE job_name = $2)
+        ON CONFLICT (job_name, scheduled_for) WHERE source = 'SCHEDULE' DO NOTHING
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(job_name)
+    .bind(scheduled_for)
+    .bind(instance_id)
+    .bind(now)
+    .bind(source)
+    .bind(dry_run)
+    .bind(requested_by)
+    .fetch_optional(db)
+    .await
+}
+
+/// Records how a run ended: the number of items it processed, or its error.
+async fn finish_run(
+    db: &PgPool,
+    run_id: Uuid,
+    now: DateTime<Utc>,
+    outcome: &Result<u64>,
+) -> sqlx::Result<JobRun> {
+    let (status, items, error) = match outcome {
+        Ok(items) => (JobRunStatus::Succeeded, Some(*items as i64), None),
+        Err(e) => (JobRunStatus::Failed, None, Some(format!("{e:#}"))),
+    };
+
+    sqlx::query_as(
+        r#"
+        UPDATE job_runs
+        SET status = $2, finished_at = $3, items_processed = $4, error = $5
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(run_id)
+    .bind(status)
+    .bind(now)
+    .bind(items)
+    .bind(error)
+    .fetch_one(db)
+    .await
+}
+
+/// Takes the job's advisory lock on a connection of its own, so that runs of one job never
+/// overlap on any instance. Closing the connection releases the lock, whatever happened to
+/// the run.
+async fn try_lock(db: &PgPool, job_name: &str) -> sqlx::Result<Option<PgConnection>> {
+    let mut conn = db.acquire().await?.detach();
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock(hashtext($1))")
+        .bind(format!("job:{job_name}"))
+        .fetch_one(&mut conn)
+        .await?;
+
+    if locked {
+        Ok(Some(conn))
+    } else {
+        conn.close().await.ok();
+        Ok(None)
+    }
+}
+
+/// Runs jobs on this instance.
+#[derive(Clone)]
+pub struct JobRunner {
+    db: PgPool,
+    clock: Arc<dyn Clock>,
+    instance_id: Arc<str>,
+    runs: JobRuns,
+}
+
+impl JobRunner {
+    pub fn new(db: PgPool, clock: Arc<dyn Clock>, runs: JobRuns) -> Self {
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "api".to_string());
+        Self::with_instance_id(db, clock, runs, format!("{host}-{}", std::process::id()))
+    }
+
+    pub fn with_instance_id(
+        db: PgPool,
+        clock: Arc<dyn Clock>,
+        runs: JobRuns,
+        instance_id: impl Into<Arc<str>>,
+    ) -> Self {
+        Self {
+            db,
+            clock,
+            instance_id: instance_id.into(),
+            runs,
+        }
+    }
+
+    /// Runs `job` under its lock and records the run. `None` means a scheduled run was
+    /// refused by [`start_run`].
+    async fn execute(
+        &self,
+        name: &'static str,
+        job: &JobFn,
+        now: DateTime<Utc>,
+        trigger: Trigger,
+    ) -> Result<Option<JobRun>, RunError> {
+        let Some(lock) = try_lock(&self.db, name).await? else {
+            return Err(RunError::AlreadyRunning);
+        };
+        let result = self.execute_locked(name, job, now, trigger).await;
+        lock.close().await.ok();
+        result
+    }
+
+    async fn execute_locked(
+        &self,
+        name: &'static str,
+        job: &JobFn,
+        now: DateTime<Utc>,
+        trigger: Trigger,
+    ) -> Result<Option<JobRun>, RunError> {
+        let Some(run) = start_run(&self.db, name, &self.instance_id, now, &trigger).await? else {
+            return Ok(None);
+        };
+
+        info!(
+            "Running the {name} job{}",
+            if run.dry_run { " (dry run)" } else { "" }
+        );
+        let outcome = job(JobContext {
+            now,
+            dry_run: run.dry_run,
+            stop: self.runs.token().clone(),
+        })
+        .await;
+        match &outcome {
+            Ok(items) => info!("The {name} job finished with {items} items processed"),
+            Err(e) => warn!("The {name} job failed: {e:#}"),
+        }
+
+        Ok(Some(
+            finish_run(&self.db, run.id, self.clock.now(), &outcome).await?,
+        ))
+    }
+
+    /// Claims the occurrence of `schedule` that is due now and runs `job` for it, unless the
+    /// job is paused or another instance has the occurrence.
+    fn run_scheduled(
+        &self,
+        name: &'static str,
+        schedule: &Schedule,
+        job: JobFn,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let runner = self.clone();
+        let now = self.clock.now();
+        let scheduled_for = occurrence(schedule, now);
+
+        async move {
+            let trigger = Trigger::Schedule { scheduled_for };
+            let run = runner
+                .runs
+                .run(name, runner.execute(name, &job, now, trigger));
+            match run.await {
+                None | Some(Ok(Some(_))) => {}
+                Some(Ok(None)) => {
+                    debug!("Skipping the {name} job for {scheduled_for}: paused or already run")
+                }
+                Some(Err(RunError::AlreadyRunning)) => {
+                    debug!("Skipping the {name} job for {scheduled_for}: still running")
+                }
+                Some(Err(e)) => warn!("Failed to run the {name} job: {e}"),
+            }
+        }
+    }
+
+    /// Runs `job` now, paused or not. The run is spawned so it completes even if the caller
+    /// goes away.
+    async fn run_now(
+        &self,
+        name: &'static str,
+        job: JobFn,
+        dry_run: bool,
+        requested_by: Option<Uuid>,
+    ) -> Result<JobRun, RunError> {
+        let runner = self.clone();
+        let now = self.clock.now();
+        let trigger = Trigger::Manual {
+            dry_run,
+            requested_by,
+        };
+
+        let task = request_id::spawn(async move {
+            let run = runner
+                .runs
+                .run(name, runner.execute(name, &job, now, trigger));
+            run.await
+        });
+        match task.await {
+            Ok(Some(result)) => result?.ok_or(RunError::AlreadyRunning),
+            Ok(None) => Err(RunError::ShuttingDown),
+            Err(_) => Err(RunError::Panicked),
+        }
+    }
+}
+
+/// The jobs this instance knows about.
+pub struct JobRegistry {
+    runner: JobRunner,
+    jobs: Vec<JobDefinition>,
+    /// When the scheduler last ticked, in Unix milliseconds, or 0 if the jobs only run by
+    /// hand here.
+    heartbeat: Arc<AtomicI64>,
+}
+
+impl JobRegistry {
+    pub fn new(runner: JobRunner) -> Self {
+        Self {
+            runner,
+            jobs: Vec::new(),
+            heartbeat: Arc::new(AtomicI64::new(0)),
+        }
+    }
+
+    /// A registry with no jobs, for states that never run any.
+    pub fn empty(db: PgPool) -> Self {
+        let runs = JobRuns::new(CancellationToken::new());
+        Self::new(JobRunner::new(db, Arc::new(SystemClock), runs))
+    }
+
+    /// The library's periodic tasks, on the schedules in `config`.
+    pub fn standard(db: PgPool, config: &Config, runner: JobRunner) -> Result<Self> {
+        let mut registry = Self::new(runner);
+        let jobs = &config.jobs;
+
+        match Resend::from_config(&config.email) {
+            Some(resend) => {
+                let db = db.clone();
+                registry.register(
+                    "overdue_notifications",
+                    "Emails borrowers whose loans are overdue",
+                    &jobs.overdue_notifications,
+                    move |ctx| {
+                        let (db, resend) = (db.clone(), resend.clone());
+                        async move {
+                            services::email::send_overdue_notifications(
+                                &db,
+                                &resend,
+                                ctx.now,
+                                ctx.dry_run,
+                                &ctx.stop,
+                            )
+                            .await
+                        }
+                    },
+                )?;
+            }
+            None => warn!("Resend API key not configured - overdue notices will not be sent"),
+        }
+
+        let purge_db = db.clone();
+        let retention_days = config.retention.soft_delete_days;
+        registry.register(
+            "soft_delete_purge",
+            "Hard-deletes books and users soft-deleted past the retention period",
+            &jobs.soft_delete_purge,
+            move |ctx| {
+                let db = purge_db.clone();
+                async move {
+                    services::retention::purge_soft_deleted(
+                        &db,
+                        retention_days,
+                        ctx.now,
+                        ctx.dry_run,
+                    )
+                    .await
+                }
+            },
+        )?;
+
+        let inventory_db = db.clone();
+        registry.register(
+            "inventory_check",
+            "Logs books whose copy counts disagree with their copies and loans",
+            &jobs.inventory_check,
+            move |_ctx| {
+                let db = inventory_db.clone();
+                async move {
+                    let discrepancies = services::inventory::check_consistency(&db).await?;
+                    Ok(discrepancies as u64)
+                }
+            },
+        )?;
+
+        let idempotency_db = db.clone();
+        let ttl_hours = config.idempotency.key_ttl_hours;
+        registry.register(
+            "idempotency_purge",
+            "Deletes expired idempotency keys",
+            &jobs.idempotency_purge,
+            move |ctx| {
+                let db = idempotency_db.clone();
+                async move { services::idempotency::purge_expired(&db, ttl_hours, ctx.dry_run).await }
+            },
+        )?;
+
+        if config.rate_limit.enabled && config.rate_limit.store == RateLimitStore::Postgres {
+            // Buckets untouched for as long as the slowest rule takes to refill are full.
+            let policies = RateLimitPolicies::from_config(&config.rate_limit)
+                .map_err(|problems| anyhow::anyhow!(problems.join("; ")))?;
+            let full_after = policies
+                .rules()
+                .map(|rule| rule.refill_secs().ceil() as i64)
+                .max()
+                .unwrap_or(0);
+            let rate_limit_db = db;
+            registry.register(
+                "rate_limit_purge",
+                "Deletes rate limit buckets that have refilled",
+                &jobs.rate_limit_purge,
+                move |ctx| {
+                    let db = rate_limit_db.clone();
+                    async move {
+                        services::rate_limit::purge_full(
+                            &db,
+                            Duration::seconds(full_after),
+                            ctx.now,
+                            ctx.dry_run,
+                        )
+                        .await
+                    }
+                },
+            )?;
+        }
+
+        Ok(registry)
+    }
+
+    pub fn register<F, Fut>(
+        &mut self,
+        name: &'static str,
+        description: &'static str,
+        cron: &str,
+        job: F,
+    ) -> Result<()>
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<u64>> + Send + 'static,
+    {
+        self.jobs.push(JobDefinition {
+            name,
+            description,
+            cron: cron.to_string(),
+            schedule: Schedule::from_str(cron)?,
+            run: Arc::new(move |ctx| Box::pin(job(ctx))),
+        });
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<&JobDefinition, RunError> {
+        self.jobs
+            .iter()
+            .find(|job| job.name == name)
+            .ok_or(RunError::NotFound)
+    }
+
+    /// Adds every job to `scheduler`, plus a heartbeat that shows the scheduler is ticking.
+    pub async fn schedule(&self, scheduler: &JobScheduler) -> Result<()> {
+        for job in &self.jobs {
+            let runner = self.runner.clone();
+            let (name, schedule, run) = (job.name, job.schedule.clone(), job.run.clone());
+            let job = Job::new_async(job.cron.as_str(), move |_uuid, _l| {
+                Box::pin(runner.run_scheduled(name, &schedule, run.clone()))
+            })?;
+            scheduler.add(job).await?;
+        }
+
+        let clock = self.runner.clock.clone();
+        let heartbeat = self.heartbeat.clone();
+        heartbeat.store(clock.now().timestamp_millis(), Ordering::SeqCst);
+        scheduler
+            .add(Job::new(HEARTBEAT_CRON, move |_uuid, _l| {
+                heartbeat.store(clock.now().timestamp_millis(), Ordering::SeqCst);
+            })?)
+            .await?;
+        Ok(())
+    }
+
+    /// Tracks work started outside the registry, such as imports, so shutdown waits for it.
+    pub fn runs(&self) -> &JobRuns {
+        &self.runner.runs
+    }
+
+    /// When the scheduler last ticked, or `None` if jobs aren't scheduled on this instance.
+    pub fn heartbeat(&self) -> Option<DateTime<Utc>> {
+        match self.heartbeat.load(Ordering::SeqCst) {
+            0 => None,
+            millis => DateTime::from_timestamp_millis(millis),
+        }
+    }
+
+    /// Runs the job called `name` as if its schedule had fired now.
+    #[cfg(test)]
+    pub async fn run_scheduled(&self, name: &str) -> Result<(), RunError> {
+        let job = self.get(name)?;
+        self.runner
+            .run_scheduled(job.name, &job.schedule, job.run.clone())
+            .await;
+        Ok(())
+    }
+
+    /// Runs the job called `name` now and returns the finished run.
+    pub async fn run_now(
+        &self,
+        name: &str,
+        dry_run: bool,
+        requested_by: Option<Uuid>,
+    ) -> Result<JobRun, RunError> {
+        let job = self.get(name)?;
+        self.runner
+            .run_now(job.name, job.run.clone(), dry_run, requested_by)
+            .await
+    }
+
+    /// Pauses or resumes the job's schedule on every instance.
+    pub async fn set_paused(
+        &self,
+        name: &str,
+        paused: bool,
+        actor_id: Option<Uuid>,
+    ) -> Result<JobStatus, RunError> {
+        let job = self.get(name)?;
+        let db = &self.runner.db;
+
+        if paused {
+            sqlx::query(
+                r#"
+                INSERT INTO job_pauses (job_name, paused_by, paused_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (job_name) DO NOTHING
+                "#,
+            )
+            .bind(job.name)
+            .bind(actor_id)
+            .bind(self.runner.clock.now())
+            .execute(db)
+            .await?;
+        } else {
+            sqlx::query("DELETE FROM job_pauses WHERE job_name = $1")
+                .bind(job.name)
+                .execute(db)
+                .await?;
+        }
+        info!(
+            "{} the {} job",
+            if paused { "Paused" } else { "Resumed" },
+            job.name
+        );
+
+        let statuses = self.statuses().await?;
+        statuses
+            .into_iter()
+            .find(|status| status.name == job.name)
+            .ok_or(RunError::NotFound)
+    }
+
+    /// Every job with its schedule, pause state and latest runs.
+    pub async fn statuses(&self) -> sqlx::Result<Vec<JobStatus>> {
+        let db = &self.runner.db;
+
+        let paused: Vec<String> = sqlx::query_scalar("SELECT job_name FROM job_pauses")
+            .fetch_all(db)
+            .await?;
+        let last_runs: Vec<JobRun> = sqlx::query_as(
+            "SELECT DISTINCT ON (job_name) * FROM job_runs ORDER BY job_name, started_at DESC",
+        )
+        .fetch_all(db)
+        .await?;
+        let last_failures: Vec<JobRun> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON (job_name) * FROM job_runs
+            WHERE status = 'FAILED'
+            ORDER BY job_name, started_at DESC
+            "#,
+        )
+        .fetch_all(db)
+        .await?;
+
+        let now = self.runner.clock.now();
+        let scheduled = self.heartbeat().is_some();
+
+        Ok(self
+            .jobs
+            .iter()
+            .map(|job| {
+                let paused = paused.iter().any(|name| name == job.name);
+                JobStatus {
+                    name: job.name.to_string(),
+                    description: job.description.to_string(),
+                    schedule: job.cron.clone(),
+                    paused,
+                    next_run: (scheduled && !paused)
+                        .then(|| job.schedule.after(&now).next())
+                        .flatten(),
+                    last_run: last_runs
+                        .iter()
+                        .find(|run| run.job_name == job.name)
+                        .cloned(),
+                    last_error: last_failures
+                        .iter()
+                        .find(|run| run.job_name == job.name)
+                        .map(|run| JobError {
+                            run_id: run.id,
+                            at: run.finished_at.unwrap_or(run.started_at),
+                            message: run.error.clone().unwrap_or_default(),
+                        }),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use chrono::TimeZone;
+    use tokio::sync::Notify;
+
+    use super::*;
+    use crate::{services::clock::TestClock, testing::TestDb};
+
+    #[test]
+    fn test_replicas_agree_on_the_occurrence() {
+        let nightly = Schedule::from_str("0 0 0 * * *").unwrap();
+        let midnight = Utc.with_ymd_and_hms(2025, 3, 2, 0, 0, 0).unwrap();
+
+        for offset in [-2000, -10, 0, 450, 5000] {
+            let now = midnight + Duration::milliseconds(offset);
+            assert_eq!(occurrence(&nightly, now), midnight, "{offset}ms");
+        }
+
+        let every_ten_seconds = Schedule::from_str("*/10 * * * * *").unwrap();
+        let now = midnight + Duration::milliseconds(10_300);
+        assert_eq!(
+            occurrence(&every_ten_seconds, now),
+            midnight + Duration::seconds(10)
+        );
+    }
+
+    /// Two replicas sharing `db`, each with an hourly job that counts its calls in `calls`
+    /// and, when `release` is given, waits for it before returning.
+    fn replicas(
+        db: &TestDb,
+        clock: &Arc<TestClock>,
+        calls: &Arc<AtomicUsize>,
+        release: Option<Arc<Notify>>,
+    ) -> [JobRegistry; 2] {
+        ["api-1", "api-2"].map(|instance| {
+            let runner = JobRunner::with_instance_id(
+                db.pool.clone(),
+                clock.clone(),
+                JobRuns::new(CancellationToken::new()),
+                instance,
+            );
+            let mut registry = JobRegistry::new(runner);
+            let (calls, release) = (calls.clone(), release.clone());
+            registry
+                .register("hourly", "Counts its runs", "0 0 * * * *", move |_ctx| {
+                    let (calls, release) = (calls.clone(), release.clone());
+                    async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        if let Some(release) = release {
+                            release.notified().await;
+                        }
+                        Ok(3)
+                    }
+                })
+                .unwrap();
+            registry
+        })
+    }
+
+    #[tokio::test]
+    async fn test_each_occurrence_runs_on_one_instance() {
+        let Some(db) = TestDb::provision().await else {
+            return;
+        };
+        let ten_o_clock = Utc.with_ymd_and_hms(2025, 3, 2, 10, 0, 0).unwrap();
+        let clock = Arc::new(TestClock::new(ten_o_clock));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let [first, second] = replicas(&db, &clock, &calls, None);
+
+        let (a, b) = tokio::join!(
+            first.run_scheduled("hourly"),
+            second.run_scheduled("hourly")
+        );
+        a.unwrap();
+        b.unwrap();
+        second.run_scheduled("hourly").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        clock.advance(Duration::hours(1));
+        second.set_paused("hourly", true, None).await.unwrap();
+        first.run_scheduled("hourly").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        second.set_paused("hourly", false, None).await.unwrap();
+        first.run_scheduled("hourly").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let runs: Vec<JobRun> = sqlx::query_as("SELECT * FROM job_runs ORDER BY started_at")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs.iter().all(|run| run.status == JobRunStatus::Succeeded));
+        assert_eq!(runs[0].items_processed, Some(3));
+        assert_eq!(runs[0].scheduled_for, ten_o_clock);
+        assert_eq!(runs[1].instance_id, "api-1");
+        assert_eq!(runs[1].scheduled_for, ten_o_clock + Duration::hours(1));
+    }
+
+    #[tokio::test]
+    async fn test_runs_of_a_job_never_overlap() {
+        let Some(db) = TestDb::provision().await else {
+            return;
+        };
+        let clock = Arc::new(TestClock::new(Utc::now()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+        let [first, second] = replicas(&db, &clock, &calls, Some(release.clone()));
+        let first = Arc::new(first);
+
+        let running = tokio::spawn({
+            let first = first.clone();
+            async move { first.run_now("hourly", false, None).await }
+        });
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let overlapping = second.run_now("hourly", true, None).await;
+        assert!(matches!(overlapping, Err(RunError::AlreadyRunning)));
+
+        release.notify_one();
+        let run = running.await.unwrap().unwrap();
+        assert_eq!(run.source, JobRunSource::Manual);
+        assert_eq!(run.status, JobRunStatus::Succeeded);
+
+        release.notify_one();
+        let run = second.run_now("hourly", true, None).await.unwrap();
+        assert!(run.dry_run);
+        assert!(matches!(
+            second.run_now("daily", false, None).await,
+            Err(RunError::NotFound)
+        ));
+    }
+}
@@ -1,2 +1,13 @@
+pub mod audit;
+pub mod catalog_import;
+pub mod circulation;
+pub mod clock;
 pub mod email;
+pub mod export;
+pub mod health;
+pub mod idempotency;
+pub mod inventory;
+pub mod jobs;
+pub mod rate_limit;
+pub mod retention;
 pub mod supabase_sync;
@@ -0,0 +1,296 @@
+//! Token buckets for rate limiting. A bucket holds up to `burst` tokens and refills
+//! continuously at `requests_per_minute`; each request takes a token, and is refused when
+//! there is none. Buckets live in this process or, to share them between replicas, in
+//! Postgres.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use tracing::info;
+
+/// Buckets kept in memory before full ones are dropped.
+const MAX_MEMORY_BUCKETS: usize = 100_000;
+
+/// A limit: the default, or one of the configured groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+impl Rule {
+    fn per_second(&self) -> f64 {
+        f64::from(self.requests_per_minute) / 60.0
+    }
+
+    /// How long an empty bucket takes to fill.
+    pub fn refill_secs(&self) -> f64 {
+        f64::from(self.burst) / self.per_second()
+    }
+
+    /// The `RateLimit-Policy` value: the burst, and the window it refills over.
+    pub fn policy(&self) -> String {
+        format!("{};w={}", self.burst, self.refill_secs().ceil())
+    }
+
+    /// The tokens in a bucket that had `tokens` at `updated_at`.
+    fn refill(&self, tokens: f64, updated_at: DateTime<Utc>, now: DateTime<Utc>) -> f64 {
+        let elapsed = (now - updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+        (tokens + elapsed * self.per_second()).min(f64::from(self.burst))
+    }
+}
+
+/// The outcome of taking a token, in the terms of the `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset_secs: u64,
+    /// Seconds until a token is available; 0 when the request was allowed.
+    pub retry_after_secs: u64,
+}
+
+impl Decision {
+    /// `tokens` is what the bucket holds after the request took its token, if it was granted.
+    fn new(rule: &Rule, granted: bool, tokens: f64) -> Self {
+        let seconds_until = |target: f64| ((target - tokens).max(0.0) / rule.per_second()).ceil();
+        Self {
+            allowed: granted,
+            limit: rule.burst,
+            remaining: tokens.floor().max(0.0) as u32,
+            reset_secs: seconds_until(f64::from(rule.burst)) as u64,
+            retry_after_secs: if granted {
+                0
+            } else {
+                seconds_until(1.0).max(1.0) as u64
+            },
+        }
+    }
+}
+
+#[async_trait]
+pub trait BucketStore: Send + Sync {
+    /// Takes a token from the bucket called `key`, which starts out full.
+    async fn take(&self, key: &str, rule: &Rule, now: DateTime<Utc>) -> Result<Decision>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: DateTime<Utc>,
+    full_at: DateTime<Utc>,
+}
+
+/// Buckets for this instance only.
+#[derive(Default)]
+pub struct MemoryBuckets {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryBuckets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BucketStore for MemoryBuckets {
+    async fn take(&self, key: &str, rule: &Rule, now: DateTime<Utc>) -> Result<Decision> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_MEMORY_BUCKETS {
+            // A full bucket is the same as a missing one.
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        let tokens = match buckets.get(key) {
+            Some(bucket) => rule.refill(bucket.tokens, bucket.updated_at, now),
+            None => f64::from(rule.burst),
+        };
+        let granted = tokens >= 1.0;
+        let tokens = if granted { tokens - 1.0 } else { tokens };
+
+        let decision = Decision::new(rule, granted, tokens);
+        buckets.insert(
+            key.to_string(),
+            Bucket {
+                tokens,
+                updated_at: now,
+                full_at: now + Duration::seconds(decision.reset_secs as i64),
+            },
+        );
+        Ok(decision)
+    }
+}
+
+/// Buckets in `rate_limit_buckets`, shared by every instance using the database. Each take
+/// is a single upsert, so concurrent requests can't spend the same token.
+pub struct PgBuckets {
+    db: PgPool,
+}
+
+impl PgBuckets {
+    pub fn new(db: PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl BucketStore for PgBuckets {
+    async fn take(&self, key: &str, rule: &Rule, now: DateTime<Utc>) -> Result<Decision> {
+        let (granted, tokens): (bool, f64) = sqlx::query_as(
+            r#"
+            INSERT INTO rate_limit_buckets AS b (key, tokens, granted, updated_at)
+            VALUES ($1, $2 - 1, TRUE, $3)
+            ON CONFLICT (key) DO UPDATE
+            SET (tokens, granted, updated_at) = (
+                SELECT
+                    CASE WHEN refilled.tokens >= 1 THEN refilled.tokens - 1 ELSE refilled.tokens END,
+                    refilled.tokens >= 1,
+                    GREATEST(b.updated_at, $3)
+                FROM (
+                    SELECT LEAST(
+                        $2,
+                        b.tokens
+                            + GREATEST(0, EXTRACT(EPOCH FROM $3 - b.updated_at)::DOUBLE PRECISION)
+                            * $4
+                    ) AS tokens
+                ) refilled
+            )
+            RETURNING granted, tokens
+            "#,
+        )
+        .bind(key)
+        .bind(f64::from(rule.burst))
+        .bind(now)
+        .bind(rule.per_second())
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(Decision::new(rule, granted, tokens))
+    }
+}
+
+/// Deletes buckets untouched for `full_after`, by which time they have refilled and are no
+/// different from missing ones, or with `dry_run` just counts them.
+pub async fn purge_full(
+    db: &PgPool,
+    full_after: Duration,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<u64> {
+    let cutoff = now - full_after;
+
+    if dry_run {
+        let full: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM rate_limit_buckets WHERE updated_at < $1")
+                .bind(cutoff)
+                .fetch_one(db)
+                .await?;
+        return Ok(full as u64);
+    }
+
+    let deleted = sqlx::query("DELETE FROM rate_limit_buckets WHERE updated_at < $1")
+        .bind(cutoff)
+        .execute(db)
+        .await?
+        .rows_affected();
+
+    if deleted > 0 {
+        info!("Purged {} full rate limit buckets", deleted);
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::testing::TestDb;
+
+    fn rule() -> Rule {
+        Rule {
+            name: "search".to_string(),
+            requests_per_minute: 60,
+            burst: 2,
+        }
+    }
+
+    /// Spends a full bucket, is refused with a retry time, and gets one request back per
+    /// second of refill.
+    async fn check_store(store: &dyn BucketStore) {
+        let rule = rule();
+        let noon = Utc.with_ymd_and_hms(2025, 3, 2, 12, 0, 0).unwrap();
+
+        let first = store.take("search:ip:10.1.1.1", &rule, noon).await.unwrap();
+        assert!(first.allowed);
+        assert_eq!((first.limit, first.remaining, first.reset_secs), (2, 1, 1));
+        assert!(
+            store
+                .take("search:ip:10.1.1.1", &rule, noon)
+                .await
+                .unwrap()
+                .allowed
+        );
+
+        let refused = store.take("search:ip:10.1.1.1", &rule, noon).await.unwrap();
+        assert!(!refused.allowed);
+        assert_eq!((refused.remaining, refused.retry_after_secs), (0, 1));
+        assert!(
+            store
+                .take("search:ip:10.2.2.2", &rule, noon)
+                .await
+                .unwrap()
+                .allowed
+        );
+
+        let later = noon + Duration::milliseconds(1500);
+        let refilled = store
+            .take("search:ip:10.1.1.1", &rule, later)
+            .await
+            .unwrap();
+        assert!(refilled.allowed);
+        assert_eq!(refilled.remaining, 0);
+        assert!(
+            !store
+                .take("search:ip:10.1.1.1", &rule, later)
+                .await
+                .unwrap()
+                .allowed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_buckets() {
+        check_store(&MemoryBuckets::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_postgres_buckets_and_purge() {
+        let Some(db) = TestDb::provision().await else {
+            return;
+        };
+        check_store(&PgBuckets::new(db.pool.clone())).await;
+
+        let next_day = Utc.with_ymd_and_hms(2025, 3, 3, 12, 0, 0).unwrap();
+        let full_after = Duration::seconds(rule().refill_secs().ceil() as i64);
+        assert_eq!(
+            purge_full(&db.pool, full_after, next_day, true)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            purge_full(&db.pool, full_after, next_day, false)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+}
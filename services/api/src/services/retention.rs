@@ -0,0 +1,212 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{
+    models::{Book, User},
+    services::audit::{self, AuditContext, AuditEvent},
+};
+
+pub const DEFAULT_RETENTION_DAYS: i32 = 90;
+
+/// Hard-deletes books and users that were soft-deleted more than `retention_days` before `now`.
+///
+/// Records with any checkouts, open or returned, are kept so that the circulation history
+/// survives; the schema refuses to delete them anyway. A dry run does the same work in a
+/// transaction that is rolled back, so it reports exactly what would be purged.
+pub async fn purge_soft_deleted(
+    db: &PgPool,
+    retention_days: i32,
+    now: DateTime<Utc>,
+    dry_run: bool,
+) -> Result<u64> {
+    let mut tx = db.begin().await?;
+    let ctx = AuditContext::default();
+
+    let books = sqlx::query_as::<_, Book>(
+        r#"
+        DELETE FROM books b
+        WHERE b.deleted_at < $2 - make_interval(days => $1)
+        AND NOT EXISTS (
+            SELECT 1 FROM checkouts c WHERE c.book_id = b.id
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(retention_days)
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for book in &books {
+        audit::record(
+            &mut *tx,
+            &ctx,
+            AuditEvent::new("book.purge", "book", book.id).before(book),
+        )
+        .await?;
+    }
+
+    let users = sqlx::query_as::<_, User>(
+        r#"
+        DELETE FROM users u
+        WHERE u.deleted_at < $2 - make_interval(days => $1)
+        AND NOT EXISTS (
+            SELECT 1 FROM checkouts c WHERE c.user_id = u.id
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(retention_days)
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for user in &users {
+        audit::record(
+            &mut *tx,
+            &ctx,
+            AuditEvent::new("user.purge", "user", user.id).before(user),
+        )
+        .await?;
+    }
+
+    let purged = (books.len() + users.len()) as u64;
+    if dry_run {
+        tx.rollback().await?;
+        info!(
+            "Would purge {} books and {} users deleted more than {} days ago",
+            books.len(),
+            users.len(),
+            retention_days
+        );
+        return Ok(purged);
+    }
+
+    tx.commit().await?;
+    info!(
+        "Purged {} books and {} users deleted more than {} days ago",
+        books.len(),
+        users.len(),
+        retention_days
+    );
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use chrono::Duration;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{services::clock::Clock, testing::TestApp};
+
+    #[tokio::test]
+    async fn test_purge_keeps_records_with_checkout_history() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        let (_, checkout) = app
+            .post(
+                "/api/checkouts",
+                json!({ "user_id": fixtures.reader.id, "book_id": fixtures.dune.id }),
+            )
+            .await;
+        let (status, _) = app
+            .post(
+                "/api/checkouts/return",
+                json!({ "checkout_id": checkout["id"] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for uri in [
+            format!("/api/books/{}", fixtures.dune.id),
+            format!("/api/books/{}", fixtures.emma.id),
+            format!("/api/users/{}", fixtures.reader.id),
+            format!("/api/users/{}", fixtures.admin.id),
+        ] {
+            let (status, _) = app.request(Method::DELETE, &uri, None).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+        }
+
+        let now = app.clock.now();
+        assert_eq!(
+            purge_soft_deleted(app.pool(), 90, now, false)
+                .await
+                .unwrap(),
+            0
+        );
+        let later = now + Duration::days(91);
+        assert_eq!(
+            purge_soft_deleted(app.pool(), 90, later, true)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            purge_soft_deleted(app.pool(), 90, later, false)
+                .await
+                .unwrap(),
+            2
+        );
+
+        let pool = app.pool();
+        let remaining = |table: &'static str| async move {
+            sqlx::query_scalar::<_, Uuid>(&format!("SELECT id FROM {table}"))
+                .fetch_all(pool)
+                .await
+                .unwrap()
+        };
+        assert_eq!(remaining("books").await, [fixtures.dune.id]);
+        assert_eq!(remaining("users").await, [fixtures.reader.id]);
+        let checkout_id: Uuid = checkout["id"].as_str().unwrap().parse().unwrap();
+        assert_eq!(remaining("checkouts").await, [checkout_id]);
+
+        // Nothing can take the history with it, even outside the purge.
+        let delete = sqlx::query("DELETE FROM books WHERE id = $1")
+            .bind(fixtures.dune.id)
+            .execute(app.pool())
+            .await;
+        assert!(delete.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deletions_are_dated_by_the_clock() {
+        let Some(app) = TestApp::spawn().await else {
+            return;
+        };
+        let fixtures = app.seed().await;
+        app.clock.advance(Duration::days(200));
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/books/{}", fixtures.emma.id),
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (deleted_at, updated_at): (DateTime<Utc>, DateTime<Utc>) =
+            sqlx::query_as("SELECT deleted_at, updated_at FROM books WHERE id = $1")
+                .bind(fixtures.emma.id)
+                .fetch_one(app.pool())
+                .await
+                .unwrap();
+        let wall_clock = Utc::now();
+        assert!(deleted_at > wall_clock + Duration::days(199));
+        assert_eq!(updated_at, deleted_at);
+
+        // Deleted "now" by the clock, so not yet due, whatever the wall clock says.
+        let now = app.clock.now();
+        assert_eq!(
+            purge_soft_deleted(app.pool(), 90, now, true).await.unwrap(),
+            0
+        );
+    }
+}
@@ -1,9 +1,12 @@
-use reqwest::Client;
+use std::time::{Duration, Instant};
+
+use reqwest::{Client, StatusCode};
 use serde_json::{json, Value};
-use std::env;
-use tracing::{error, info, warn};
+use tracing::{error, info, Instrument};
 use uuid::Uuid;
 
+use crate::{metrics::METRICS, middleware::request_id, telemetry};
+
 #[derive(Clone)]
 pub struct SupabaseSync {
     client: Client,
@@ -12,27 +15,70 @@ pub struct SupabaseSync {
 }
 
 impl SupabaseSync {
-    pub fn new() -> Option<Self> {
-        let base_url = env::var("SUPABASE_URL").ok()?;
-        let service_key = env::var("SUPABASE_SERVICE_ROLE_KEY").ok()?;
+    /// `None` when Supabase isn't configured.
+    pub fn from_config(config: &crate::config::SupabaseConfig) -> Option<Self> {
+        let base_url = config.url.as_ref()?;
+        let service_key = config.service_role_key.as_ref()?;
 
-        if base_url.is_empty() || service_key.is_empty() {
-            warn!("Supabase credentials not configured, skipping sync");
-            return None;
-        }
+        Some(Self::with_base_url(base_url, service_key))
+    }
 
-        Some(Self {
+    /// Syncs to the Supabase project at `base_url`, e.g. `https://xyz.supabase.co`.
+    pub fn with_base_url(base_url: &str, service_key: &str) -> Self {
+        Self {
             client: Client::new(),
             base_url: format!("{base_url}/rest/v1"),
-            service_key,
-        })
+            service_key: service_key.to_string(),
+        }
     }
 
+    /// Checks that the Supabase REST API answers, returning its status code.
+    pub async fn ping(&self, timeout: Duration) -> reqwest::Result<StatusCode> {
+        let response = self
+            .client
+            .get(format!("{}/", self.base_url))
+            .header("apikey", &self.service_key)
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        Ok(response.status())
+    }
+
+    /// Sends one request, recording its latency and any failure against the endpoint's table.
     async fn make_request(
         &self,
         method: &str,
         endpoint: &str,
         body: Option<Value>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let table = endpoint.split('?').next().unwrap_or(endpoint);
+        let started = Instant::now();
+        let url = format!("{}/{}", self.base_url, endpoint);
+        let result = self
+            .send_request(method, endpoint, body)
+            .instrument(telemetry::client_span("supabase", method, &url))
+            .await;
+
+        METRICS
+            .supabase_sync_duration
+            .with_label_values(&[table])
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            METRICS
+                .supabase_sync_failures
+                .with_label_values(&[table])
+                .inc();
+        }
+
+        result
+    }
+
+    async fn send_request(
+        &self,
+        method: &str,
+        endpoint: &str,
+        body: Option<Value>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let url = format!("{}/{}", self.base_url, endpoint);
 
@@ -43,8 +89,11 @@ impl SupabaseSync {
             _ => return Err("Unsupported HTTP method".into()),
         };
 
-        request = request
-            .header("Authorization", format!("Bearer {service_key}", service_key=self.service_key))
+        request = request_id::propagate(request)
+            .header(
+                "Authorization",
+                format!("Bearer {service_key}", service_key = self.service_key),
+            )
             .header("apikey", &self.service_key)
             .header("Content-Type", "application/json")
             .header("Prefer", "return=minimal");
@@ -148,7 +197,7 @@ impl SupabaseSync {
         if let Err(e) = self
             .make_request(
                 "PATCH",
-                &format!("checkouts?id=eq.{id}", id=checkout.id),
+                &format!("checkouts?id=eq.{id}", id = checkout.id),
                 Some(json!({
                     "status": format!("{:?}", checkout.status).to_uppercase(),
                     "due_date": checkout.due_date.to_rfc3339(),
@@ -192,7 +241,3 @@ impl SupabaseSync {
         }
     }
 }
-
-pub fn get_supabase_sync() -> Option<SupabaseSync> {
-    SupabaseSync::new()
-}
@@ -0,0 +1,139 @@
+//! Graceful shutdown: waiting for SIGTERM or SIGINT, and letting scheduled jobs wind down
+//! before the process exits.
+
+use std::{future::Future, time::Duration};
+
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::info;
+
+/// Resolves on the first SIGTERM (as sent by `docker stop`) or SIGINT.
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => info!("Received SIGINT"),
+        () = terminate => info!("Received SIGTERM"),
+    }
+}
+
+/// Scheduled job runs in flight. Shutdown cancels `token`, which long jobs check between
+/// units of work, then waits for the runs to return.
+#[derive(Clone)]
+pub struct JobRuns {
+    token: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl JobRuns {
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            token,
+            tracker: TaskTracker::new(),
+        }
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Runs `job` unless shutdown has started, tracking it until it returns. Resolves to
+    /// `None` when the job was skipped.
+    pub fn run<F>(
+        &self,
+        name: &'static str,
+        job: F,
+    ) -> impl Future<Output = Option<F::Output>> + Send
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        let job = (!self.token.is_cancelled()).then(|| self.tracker.track_future(job));
+        async move {
+            match job {
+                Some(job) => Some(job.await),
+                None => {
+                    info!("Skipping the {name} job during shutdown");
+                    None
+                }
+            }
+        }
+    }
+
+    /// Cancels the token and waits up to `timeout` for running jobs. Returns whether they
+    /// all finished.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.token.cancel();
+        self.tracker.close();
+        tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_running_jobs() {
+        let runs = JobRuns::new(CancellationToken::new());
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let job = runs.run("slow", {
+            let finished = finished.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                finished.store(true, Ordering::SeqCst);
+            }
+        });
+        tokio::spawn(job);
+
+        assert!(runs.drain(Duration::from_secs(5)).await);
+        assert!(finished.load(Ordering::SeqCst));
+        assert!(runs.token().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_drain_gives_up_after_the_timeout() {
+        let runs = JobRuns::new(CancellationToken::new());
+        tokio::spawn(runs.run("stuck", std::future::pending::<()>()));
+
+        assert!(!runs.drain(Duration::from_millis(50)).await);
+    }
+
+    #[tokio::test]
+    async fn test_jobs_triggered_during_shutdown_do_not_run() {
+        let runs = JobRuns::new(CancellationToken::new());
+        runs.token().cancel();
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let outcome = runs
+            .run("late", {
+                let ran = ran.clone();
+                async move { ran.store(true, Ordering::SeqCst) }
+            })
+            .await;
+
+        assert!(outcome.is_none());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+}
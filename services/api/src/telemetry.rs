@@ -0,0 +1,165 @@
+//! Optional OpenTelemetry export of `tracing` spans over OTLP/HTTP, with W3C `traceparent`
+//! propagation. Incoming requests continue the caller's trace; outbound calls to Supabase,
+//! Resend and the auth service carry the current span's context. Without an endpoint none of
+//! this is installed and propagation does nothing.
+
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    trace::TracerProvider,
+};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+    Resource,
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::TelemetryConfig;
+
+/// Builds the exporting tracer provider when `telemetry.otlp_endpoint` is set, and installs
+/// the W3C trace context propagator. Shut the provider down on exit to flush the last spans.
+pub fn tracer_provider(
+    config: &TelemetryConfig,
+) -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
+}
+
+/// The subscriber layer that turns `tracing` spans into exported OpenTelemetry spans.
+pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("library-api"))
+}
+
+/// Makes `span` a child of the trace in the request's `traceparent` header, if any.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    // Fails only when spans aren't being exported, which leaves nothing to link.
+    let _ = span.set_parent(parent);
+}
+
+/// Adds the current span's trace context to an outbound request.
+pub fn inject(mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    let mut headers = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &Span::current().context(),
+            &mut HeaderInjector(&mut headers),
+        )
+    });
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// A client span for a call to `service`. Build and send the request inside it so that the
+/// `traceparent` sent points at this span.
+pub fn client_span(service: &'static str, method: &str, url: &str) -> Span {
+    tracing::info_span!(
+        "http.client",
+        otel.kind = "client",
+        peer.service = service,
+        http.request.method = method,
+        url.full = url,
+    )
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HashMap<String, String>);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Method};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::testing::HttpStub;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_continue_the_callers_trace_and_reach_the_collector() {
+        let collector = HttpStub::start().await;
+        let config = TelemetryConfig {
+            otlp_endpoint: Some(collector.url().to_string()),
+            service_name: "library-api-test".to_string(),
+        };
+        let provider = tracer_provider(&config).unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+
+        let outbound = tracing::subscriber::with_default(subscriber, || {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "traceparent",
+                HeaderValue::from_str(&format!("00-{TRACE_ID}-00f067aa0ba902b7-01")).unwrap(),
+            );
+            let span = tracing::info_span!("request");
+            continue_trace(&span, &headers);
+
+            let _entered = span.enter();
+            let client = client_span("supabase", "POST", "http://supabase/rest/v1/books");
+            let _entered = client.enter();
+            inject(reqwest::Client::new().post("http://supabase/rest/v1/books"))
+                .build()
+                .unwrap()
+        });
+
+        let traceparent = outbound.headers()["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with(&format!("00-{TRACE_ID}-")));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+        let exports = collector.wait_for(1).await;
+        assert_eq!(exports[0].method, Method::POST);
+        assert_eq!(exports[0].path, "/v1/traces");
+        assert_eq!(exports[0].headers["content-type"], "application/x-protobuf");
+    }
+}
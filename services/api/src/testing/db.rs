@@ -0,0 +1,185 @@
+use std::{
+    net::TcpListener,
+    process::{Command, Stdio},
+};
+
+use sqlx::{postgres::PgPoolOptions, Connection, PgConnection, PgPool};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+/// A Postgres server started from the local `initdb`/`pg_ctl` binaries, stopped on drop.
+struct LocalServer {
+    dir: TempDir,
+    port: u16,
+}
+
+impl LocalServer {
+    fn start() -> Result<Self, String> {
+        let dir = tempfile::Builder::new()
+            .prefix("library-api-pg-")
+            .tempdir()
+            .map_err(|e| e.to_string())?;
+        let data = dir.path().join("data");
+
+        run(Command::new("initdb").arg("--pgdata").arg(&data).args([
+            "--username=postgres",
+            "--auth=trust",
+            "--no-sync",
+        ]))?;
+
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map_err(|e| e.to_string())?
+            .port();
+        let options = format!(
+            "-p {port} -k {} -c listen_addresses=127.0.0.1 -c fsync=off",
+            dir.path().display()
+        );
+
+        run(Command::new("pg_ctl")
+            .arg("--pgdata")
+            .arg(&data)
+            .arg("--log")
+            .arg(dir.path().join("postgres.log"))
+            .args(["--wait", "--options", &options, "start"]))?;
+
+        Ok(Self { dir, port })
+    }
+
+    fn url(&self) -> String {
+        format!("postgres://postgres@127.0.0.1:{}/postgres", self.port)
+    }
+}
+
+impl Drop for LocalServer {
+    fn drop(&mut self) {
+        let _ = Command::new("pg_ctl")
+            .arg("--pgdata")
+            .arg(self.dir.path().join("data"))
+            .args(["--mode=immediate", "stop"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+}
+
+/// A throwaway database with all migrations applied, dropped on drop.
+///
+/// Created on the server named by `TEST_DATABASE_URL` when it is set, otherwise on a
+/// private server started from the local Postgres binaries.
+pub struct TestDb {
+    pub pool: PgPool,
+    admin_url: String,
+    name: String,
+    server: Option<LocalServer>,
+}
+
+impl TestDb {
+    /// Returns `None`, after printing why, when no Postgres is available so the caller can
+    /// skip the test.
+    pub async fn provision() -> Option<Self> {
+        let (admin_url, server) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => (url, None),
+            Err(_) => match LocalServer::start() {
+                Ok(server) => (server.url(), Some(server)),
+                Err(e) => {
+                    eprintln!("no TEST_DATABASE_URL and no local Postgres ({e}); skipping");
+                    return None;
+                }
+            },
+        };
+
+        let name = format!("library_test_{}", Uuid::new_v4().simple());
+        let mut admin = PgConnection::connect(&admin_url)
+            .await
+            .expect("connect to test Postgres");
+        sqlx::query(&format!("CREATE DATABASE {name}"))
+            .execute(&mut admin)
+            .await
+            .expect("create test database");
+        admin.close().await.ok();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(20)
+            .connect(&database_url(&admin_url, &name))
+            .await
+            .expect("connect to test database");
+        crate::MIGRATOR.run(&pool).await.expect("run migrations");
+
+        Some(Self {
+            pool,
+            admin_url,
+            name,
+            server,
+        })
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        if self.server.is_some() {
+            // The whole cluster is thrown away with the server.
+            return;
+        }
+
+        // Drop runs inside the test's runtime, so clean up on a fresh one.
+        let admin_url = self.admin_url.clone();
+        let statement = format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.name);
+        let _ = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("build cleanup runtime");
+            runtime.block_on(async {
+                if let Ok(mut admin) = PgConnection::connect(&admin_url).await {
+                    let _ = sqlx::query(&statement).execute(&mut admin).await;
+                }
+            });
+        })
+        .join();
+    }
+}
+
+/// `url` with its database name replaced by `name`.
+fn database_url(url: &str, name: &str) -> String {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (url, None),
+    };
+    let authority_end = base.find("://").map_or(0, |i| i + 3);
+    let base = match base[authority_end..].find('/') {
+        Some(i) => &base[..authority_end + i],
+        None => base,
+    };
+
+    match query {
+        Some(query) => format!("{base}/{name}?{query}"),
+        None => format!("{base}/{name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::database_url;
+
+    #[test]
+    fn test_database_url_replaces_only_the_database_name() {
+        assert_eq!(
+            database_url("postgres://postgres@localhost/conc", "t1"),
+            "postgres://postgres@localhost/t1"
+        );
+        assert_eq!(
+            database_url("postgres://u:p@db:5432?sslmode=disable", "t1"),
+            "postgres://u:p@db:5432/t1?sslmode=disable"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Integration test harness: a throwaway Postgres database, local stubs for Supabase,
+//! Resend and the auth service, and the real router wired to them.
+
+pub mod db;
+pub mod stub;
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::AUTHORIZATION, request::Builder, Method, Request, StatusCode},
+    Router,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+pub use db::TestDb;
+pub use stub::HttpStub;
+
+use crate::{
+    app,
+    config::Config,
+    models::{Book, CreateBookRequest, User, UserRole},
+    repositories::{
+        postgres::{PgBookRepository, PgUserRepository},
+        BookRepository, NewUser, UserRepository,
+    },
+    services::{
+        audit::AuditContext,
+        clock::{Clock, TestClock},
+        email::Resend,
+        jobs::{JobRegistry, JobRunner},
+        supabase_sync::SupabaseSync,
+    },
+    shutdown::JobRuns,
+    AppState,
+};
+
+/// The application running against a [`TestDb`], with Supabase, Resend and the auth service
+/// pointed at [`HttpStub`]s and time read from a [`TestClock`] that starts at the real time.
+/// The standard jobs are registered but not scheduled; the admin API runs them.
+///
+/// Requests are anonymous until [`TestApp::sign_in`]; the auth stub accepts a user's id as
+/// their bearer token.
+pub struct TestApp {
+    pub db: TestDb,
+    pub router: Router,
+    pub clock: Arc<TestClock>,
+    pub supabase: HttpStub,
+    pub resend: HttpStub,
+    pub auth: HttpStub,
+    token: Mutex<Option<String>>,
+}
+
+impl TestApp {
+    /// Returns `None` when no Postgres is available; see [`TestDb::provision`].
+    pub async fn spawn() -> Option<Self> {
+        let db = TestDb::provision().await?;
+        let supabase = HttpStub::start().await;
+        let resend = HttpStub::start().await;
+        let auth = HttpStub::with_body(|request| {
+            match request.body["token"].as_str().map(Uuid::parse_str) {
+                Some(Ok(id)) => json!({ "valid": true, "user": { "id": id } }),
+                _ => json!({ "valid": false }),
+            }
+        })
+        .await;
+
+        let clock = Arc::new(TestClock::new(Utc::now()));
+        let sync = SupabaseSync::with_base_url(supabase.url(), "test-service-key");
+        let mut config = Config::default();
+        config.email.resend_api_url = resend.url().to_string();
+        config.email.resend_api_key = Some("test-api-key".to_string());
+        config.email.from = "Library <test@example.com>".to_string();
+        config.auth.service_url = auth.url().to_string();
+
+        let runs = JobRuns::new(CancellationToken::new());
+        let runner = JobRunner::new(db.pool.clone(), clock.clone(), runs);
+        let jobs = JobRegistry::standard(db.pool.clone(), &config, runner).expect("job registry");
+
+        let state = AppState::new(db.pool.clone(), Some(sync))
+            .with_clock(clock.clone())
+            .with_config(Arc::new(config))
+            .with_jobs(Arc::new(jobs));
+        let router = app(state);
+
+        Some(Self {
+            db,
+            router,
+            clock,
+            supabase,
+            resend,
+            auth,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Sends later requests with `user`'s bearer token.
+    pub fn sign_in(&self, user: &User) {
+        *self.token.lock().unwrap() = Some(user.id.to_string());
+    }
+
+    /// Sends later requests anonymously.
+    pub fn sign_out(&self) {
+        *self.token.lock().unwrap() = None;
+    }
+
+    /// A request builder carrying the signed-in user's token, if any.
+    fn builder(&self, method: Method, uri: &str) -> Builder {
+        let request = Request::builder().method(method).uri(uri);
+        match self.token.lock().unwrap().as_deref() {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.db.pool
+    }
+
+    /// Sends a request through the router and returns the status and JSON body
+    /// (`Value::Null` when the body is empty or not JSON).
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = self.builder(method, uri);
+        let request = match body {
+            Some(body) => request
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+        self.send(request).await
+    }
+
+    /// Sends `body` as-is with the given `Content-Type`, for endpoints taking uploads.
+    pub async fn upload(
+        &self,
+        uri: &str,
+        content_type: &str,
+        body: impl Into<Body>,
+    ) -> (StatusCode, Value) {
+        let request = self
+            .builder(Method::POST, uri)
+            .header("content-type", content_type)
+            .body(body.into())
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// GETs `uri` and returns the status, the `Content-Type` and the raw body.
+    pub async fn download(&self, uri: &str) -> (StatusCode, String, Vec<u8>) {
+        let request = self.builder(Method::GET, uri).body(Body::empty()).unwrap();
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, content_type, body.to_vec())
+    }
+
+    async fn send(&self, request: Request<Body>) -> (StatusCode, Value) {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, Some(body)).await
+    }
+
+    /// Runs the nightly overdue job at the test clock's time, sending through the Resend stub.
+    pub async fn run_overdue_job(&self) {
+        self.run_overdue_job_until(&CancellationToken::new()).await;
+    }
+
+    /// [`Self::run_overdue_job`], stopping early once `stop` is cancelled.
+    pub async fn run_overdue_job_until(&self, stop: &CancellationToken) {
+        let resend = Resend::new(
+            self.resend.url(),
+            "test-api-key",
+            "Library <test@example.com>",
+        );
+        let now = self.clock.now();
+        crate::services::email::send_overdue_notifications(self.pool(), &resend, now, false, stop)
+            .await
+            .expect("overdue job");
+    }
+
+    /// Seeds the standard fixtures.
+    pub async fn seed(&self) -> Fixtures {
+        Fixtures::insert(self.pool()).await
+    }
+}
+
+/// A small catalog and two patrons, inserted through the Postgres repositories.
+pub struct Fixtures {
+    pub admin: User,
+    pub reader: User,
+    /// Two copies.
+    pub dune: Book,
+    /// One copy.
+    pub emma: Book,
+}
+
+impl Fixtures {
+    pub async fn insert(db: &PgPool) -> Self {
+        let users = PgUserRepository::new(db.clone());
+        let books = PgBookRepository::new(db.clone());
+        let ctx = AuditContext::default();
+
+        let user = |email: &str, name: &str, role| NewUser {
+            id: Uuid::new_v4(),
+            email: email.to_string(),
+            name: name.to_string(),
+            role,
+            max_checkouts: 5,
+        };
+        let book = |isbn: &str, title: &str, author: &str, copies| CreateBookRequest {
+            isbn: isbn.to_string(),
+            title: title.to_string(),
+            author: author.to_string(),
+            publisher: None,
+            published_year: None,
+            genre: Some("Fiction".to_string()),
+            description: None,
+            cover_url: None,
+            total_copies: Some(copies),
+            shelf_location: None,
+        };
+
+        Self {
+            admin: users
+                .create(
+                    user("admin@example.com", "Ada", UserRole::Admin),
+                    "user.create",
+                    &ctx,
+                )
+                .await
+                .unwrap(),
+            reader: users
+                .create(
+                    user("reader@example.com", "Reed", UserRole::User),
+                    "user.create",
+                    &ctx,
+                )
+                .await
+                .unwrap(),
+            dune: books
+                .create(book("9780441013593", "Dune", "Frank Herbert", 2), &ctx)
+                .await
+                .unwrap(),
+            emma: books
+                .create(book("9780141439587", "Emma", "Jane Austen", 1), &ctx)
+                .await
+                .unwrap(),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    Json, Router,
+};
+use serde_json::Value;
+
+/// A request received by an [`HttpStub`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Path and query string, e.g. `/rest/v1/books?id=eq.…`.
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+type Responder = Arc<dyn Fn(&RecordedRequest) -> Value + Send + Sync>;
+
+#[derive(Clone)]
+struct StubState {
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    status: Arc<AtomicU16>,
+    respond: Responder,
+}
+
+/// A local HTTP server standing in for an external API. It records every request and
+/// answers with a configurable status and, unless started [`HttpStub::with_body`], an empty
+/// JSON object.
+pub struct HttpStub {
+    url: String,
+    state: StubState,
+}
+
+impl HttpStub {
+    pub async fn start() -> Self {
+        Self::with_body(|_| Value::Object(Default::default())).await
+    }
+
+    /// A stub whose response body is computed from each request.
+    pub async fn with_body(
+        respond: impl Fn(&RecordedRequest) -> Value + Send + Sync + 'static,
+    ) -> Self {
+        let state = StubState {
+            requests: Arc::default(),
+            status: Arc::new(AtomicU16::new(StatusCode::OK.as_u16())),
+            respond: Arc::new(respond),
+        };
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind stub listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().fallback(record).with_state(state.clone());
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.ok();
+        });
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn respond_with(&self, status: StatusCode) {
+        self.state.status.store(status.as_u16(), Ordering::SeqCst);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    /// Waits up to five seconds for at least `count` requests, for callers that fire
+    /// requests from spawned tasks.
+    pub async fn wait_for(&self, count: usize) -> Vec<RecordedRequest> {
+        for _ in 0..100 {
+            let requests = self.requests();
+            if requests.len() >= count {
+                return requests;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        self.requests()
+    }
+}
+
+async fn record(
+    State(state): State<StubState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<Value>) {
+    let request = RecordedRequest {
+        method,
+        path: uri
+            .path_and_query()
+            .map_or_else(|| uri.path().to_string(), |pq| pq.to_string()),
+        headers,
+        body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+    };
+    let body = (state.respond)(&request);
+    state.requests.lock().unwrap().push(request);
+
+    let status = StatusCode::from_u16(state.status.load(Ordering::SeqCst))
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, Json(body))
+}